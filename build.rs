@@ -16,6 +16,11 @@ fn main() {
         .write_to_file(out_path.join("bindings.rs"))
         .expect("Couldn't write bindings to epicardium.h!");
 
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        // The mock implements the whole API in Rust, there is no firmware to link against
+        return;
+    }
+
     let firmware_build_dir = env::var("EPICARDIUM_BUILD_DIR").expect("You need to set the EPICARDIUM_BUILD_DIR environment variable to the build directory of the firmware");
 
     println!(
@@ -5,7 +5,7 @@ use core::fmt::Write;
 use rustcardium;
 use rustcardium::main;
 use rustcardium::UART;
-use rustcardium::bhi160::{SensorType, Sensor, SensorConfig};
+use rustcardium::bhi160::{Sample, SensorType, Sensor, SensorConfig, STANDARD_GRAVITY};
 
 const BLACK: rustcardium::Color = rustcardium::Color { r: 0, g: 0, b: 0 };
 
@@ -35,21 +35,12 @@ fn execute_with_error() -> Result<(), rustcardium::Error> {
         sample_buffer_len: 100,
         sample_rate: 100,
         dynamic_range: 2,
-    });
+    })?;
 
     let display = rustcardium::display::Display::open()?;
     
     let mut i: u16= 0;
 
-    let mut min_x = i16::max_value() as f32;
-    let mut max_x = i16::min_value() as f32;
-
-    let mut min_y = i16::max_value() as f32;
-    let mut max_y = i16::min_value() as f32;
-
-    let mut min_z = i16::max_value() as f32;
-    let mut max_z = i16::min_value() as f32;
-
     display.clear(Some(WHITE))?;
     display.print("x", RED, WHITE, 0, 0)?;
     display.print("y", GREEN, WHITE, 20, 0)?;
@@ -67,51 +58,20 @@ fn execute_with_error() -> Result<(), rustcardium::Error> {
             display.line(i-1, 20, i-1, 80, WHITE, false, 1)?;
         }
 
-        let values = accel.read();
-
-        
-        for v in values {
-
-            let x = v.x as f32;
-            let y = v.y as f32;
-            let z = v.z as f32;
-
-            min_x = if x < min_x {x} else {min_x};
-            max_x = if x > max_x {x} else {max_x};
-
-            min_y = if y < min_y {y} else {min_y};
-            max_y = if y > max_y {y} else {max_y};
-
-            min_z = if z < min_z {z} else {min_z};
-            max_z = if z > max_z {z} else {max_z};
-
-            // start at 0
-            let x = x - min_x;
-            let y = y - min_y;
-            let z = z - min_z;
-            
-            // normalize to 0:1
-            let x : f32 = if max_x > min_x {
-                x / (max_x - min_x)
-            } else {
-                x
+        for sample in accel.samples() {
+            let a = match sample? {
+                Sample::Acceleration(a) => a,
+                _ => continue,
             };
-            let y : f32 = if max_y > min_y {
-                y / (max_y - min_y)
-            } else {
-                y
-            };
-            let z : f32 = if max_z > min_z {
-                z / (max_z - min_z)
-            } else {
-                z
-            };
-            // scale up to 20-40
-            let x = (x * 20.0) + 20.0;
-            // scale up to 40-60
-            let y = (y * 20.0) + 40.0;
-            // scale up to 60-80
-            let z = (z * 20.0) + 60.0;
+
+            // scale -2g:2g to a band of 20 pixels
+            let scale = 10.0 / (2.0 * STANDARD_GRAVITY);
+            // 20-40
+            let x = (a.x * scale) + 30.0;
+            // 40-60
+            let y = (a.y * scale) + 50.0;
+            // 60-80
+            let z = (a.z * scale) + 70.0;
 
             if let Some(last_x) = last_x {                
                 // draw line from line from last to this one
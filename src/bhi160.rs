@@ -1,8 +1,14 @@
 use super::*;
+use crate::executor;
+use crate::interrupts::{self, Interrupt};
+use crate::time::{self, Duration};
 
-use arrayvec::ArrayVec;
+use core::sync::atomic::{AtomicU32, Ordering};
 
-pub const MAX_BUFFER_SIZE: usize = 1024;
+pub mod fusion;
+
+/// Number of samples `Drain` reads from the sensor at once.
+pub const DRAIN_CHUNK_LEN: usize = 32;
 
 /// Configuration for a BHI160 sensor.
 ///
@@ -13,86 +19,597 @@ pub struct SensorConfig {
     pub dynamic_range: u16,
 }
 
-#[derive(Clone)]
+/// BHI160 virtual sensor type, mirrors `enum bhi160_sensor_type` of Epicardium.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum SensorType {
+    /// Accelerometer, the dynamic range is given in g's (1x Earth Gravity, ~9.81m*s^-2)
     Accelerometer = 0,
-    Orientation = 1,
-    Gyroscope = 2,
+    /// Magnetometer (unimplemented by Epicardium)
+    Magnetometer = 1,
+    Orientation = 2,
+    Gyroscope = 3,
+    /// Gravity (unimplemented by Epicardium)
+    Gravity = 4,
+    /// Linear acceleration (unimplemented by Epicardium)
+    LinearAcceleration = 5,
+    /// Rotation vector (unimplemented by Epicardium)
+    RotationVector = 6,
+    /// Uncalibrated magnetometer (unimplemented by Epicardium)
+    UncalibratedMagnetometer = 7,
+    GameRotationVector = 8,
+    /// Uncalibrated gyroscope (unimplemented by Epicardium)
+    UncalibratedGyroscope = 9,
+    /// Geomagnetic rotation vector (unimplemented by Epicardium)
+    GeomagneticRotationVector = 10,
+}
+
+impl SensorType {
+    /// All sensor types, ordered by their numeric id.
+    pub const ALL: [SensorType; 11] = [
+        SensorType::Accelerometer,
+        SensorType::Magnetometer,
+        SensorType::Orientation,
+        SensorType::Gyroscope,
+        SensorType::Gravity,
+        SensorType::LinearAcceleration,
+        SensorType::RotationVector,
+        SensorType::UncalibratedMagnetometer,
+        SensorType::GameRotationVector,
+        SensorType::UncalibratedGyroscope,
+        SensorType::GeomagneticRotationVector,
+    ];
+
+    /// The `bhi160_sensor_type` id of this sensor.
+    pub fn id(self) -> sys::bhi160_sensor_type {
+        self as sys::bhi160_sensor_type
+    }
+
+    /// The sensor type with the given `bhi160_sensor_type` id.
+    pub fn from_id(id: sys::bhi160_sensor_type) -> Option<SensorType> {
+        SensorType::ALL.get(id as usize).cloned()
+    }
+
+    /// True if Epicardium can provide samples for this sensor.
+    pub fn is_implemented(self) -> bool {
+        matches!(
+            self,
+            SensorType::Accelerometer
+                | SensorType::Orientation
+                | SensorType::Gyroscope
+                | SensorType::GameRotationVector
+        )
+    }
+
+    /// The interrupt which signals new samples of this sensor, if there is one.
+    pub fn interrupt(self) -> Option<Interrupt> {
+        match self {
+            SensorType::Accelerometer => Some(Interrupt::Bhi160Accelerometer),
+            SensorType::Orientation => Some(Interrupt::Bhi160Orientation),
+            SensorType::Gyroscope => Some(Interrupt::Bhi160Gyroscope),
+            _ => None,
+        }
+    }
+}
+
+/// Bit mask of the sensor types which have a `Sensor` handle.
+static ACTIVE: AtomicU32 = AtomicU32::new(0);
+
+/// Forgets all sensor handles, see `mock::reset()`.
+#[cfg(feature = "mock")]
+pub(crate) fn reset() {
+    ACTIVE.store(0, Ordering::SeqCst);
+}
+
+/// Keeps track of the active sensors.
+///
+/// Every sensor type can only have one `Sensor` handle at a time, otherwise dropping one handle
+/// would disable the sensor under the other one.
+pub struct SensorManager;
+
+impl SensorManager {
+    /// True if there is a `Sensor` handle for the sensor type.
+    pub fn is_active(sensor_type: SensorType) -> bool {
+        ACTIVE.load(Ordering::SeqCst) & (1 << sensor_type.id()) != 0
+    }
+
+    /// The sensor types which have a `Sensor` handle.
+    pub fn active() -> impl Iterator<Item = SensorType> {
+        SensorType::ALL
+            .iter()
+            .cloned()
+            .filter(|sensor_type| SensorManager::is_active(*sensor_type))
+    }
+
+    /// Marks the sensor type as active, unless it already is.
+    fn acquire(sensor_type: SensorType) -> Result<()> {
+        let bit = 1 << sensor_type.id();
+        if ACTIVE.fetch_or(bit, Ordering::SeqCst) & bit != 0 {
+            Err(Error::SensorBusy)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn release(sensor_type: SensorType) {
+        ACTIVE.fetch_and(!(1 << sensor_type.id()), Ordering::SeqCst);
+    }
 }
 
+/// Handle of an enabled sensor, the sensor is disabled when the handle is dropped.
 pub struct Sensor {
     sd: Option<i32>,
     sensor_type: SensorType,
     cfg: SensorConfig,
 }
 
+/// Raw sample of a sensor, see `Sensor::scale()` for the physical values.
+///
+/// The layout matches `struct bhi160_data_vector`, so Epicardium writes the samples
+/// directly into a slice of `DataVector`s.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub struct DataVector {
+    /// Always `BHI160_DATA_TYPE_VECTOR`
+    data_type: sys::bhi160_data_type,
     pub x: i16,
     pub y: i16,
     pub z: i16,
     pub status: u8,
 }
 
+impl DataVector {
+    pub fn new(x: i16, y: i16, z: i16, status: u8) -> DataVector {
+        DataVector {
+            data_type: sys::bhi160_data_type_BHI160_DATA_TYPE_VECTOR,
+            x,
+            y,
+            z,
+            status,
+        }
+    }
+}
+
+/// Standard gravity in m/s².
+pub const STANDARD_GRAVITY: f32 = 9.806_65;
+
+/// Accuracy of a sample, decoded from the `status` of the raw sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Accuracy {
+    Unreliable = 0,
+    Low = 1,
+    Medium = 2,
+    High = 3,
+}
+
+impl Accuracy {
+    /// Decodes the accuracy from the lowest two bits of the status.
+    pub fn from_status(status: u8) -> Accuracy {
+        match status & 0b11 {
+            0 => Accuracy::Unreliable,
+            1 => Accuracy::Low,
+            2 => Accuracy::Medium,
+            _ => Accuracy::High,
+        }
+    }
+}
+
+/// Acceleration in m/s², measured by the accelerometer, gravity and linear acceleration sensors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Acceleration {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub accuracy: Accuracy,
+}
+
+impl Acceleration {
+    /// The acceleration in milli-g (1000 milli-g = 1x Earth gravity) as `(x, y, z)`.
+    pub fn milli_g(&self) -> (f32, f32, f32) {
+        let scale = 1000.0 / STANDARD_GRAVITY;
+        (self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+/// Angular rate in deg/s, measured by the gyroscope.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AngularRate {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub accuracy: Accuracy,
+}
+
+/// Orientation in degrees, measured by the orientation sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Orientation {
+    /// Heading (azimuth), 0 <= heading < 360
+    pub heading: f32,
+    /// Pitch, -180 <= pitch <= 180
+    pub pitch: f32,
+    /// Roll, -90 <= roll <= 90
+    pub roll: f32,
+    pub accuracy: Accuracy,
+}
+
+/// Unit quaternion, measured by the rotation vector sensors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub accuracy: Accuracy,
+}
+
+/// Sample of a sensor in physical units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sample {
+    Acceleration(Acceleration),
+    AngularRate(AngularRate),
+    Orientation(Orientation),
+    Quaternion(Quaternion),
+    /// Samples of sensors without known scaling, i.e. the magnetometers
+    Raw(DataVector),
+}
+
+/// Full scale of the 16 bit raw values.
+const FULL_SCALE: f32 = 32768.0;
+
+/// Scale of the quaternion components, which are given as 2.14 fixed point values.
+const QUATERNION_SCALE: f32 = 16384.0;
+
+impl Sample {
+    /// Converts a raw sample into physical units.
+    ///
+    /// # Arguments
+    /// * `sensor_type` - The sensor which measured the sample
+    /// * `dynamic_range` - The dynamic range from the `SensorConfig` of the sensor
+    /// * `raw` - The raw sample
+    pub fn from_raw(sensor_type: SensorType, dynamic_range: u16, raw: &DataVector) -> Sample {
+        let accuracy = Accuracy::from_status(raw.status);
+        let (x, y, z) = (f32::from(raw.x), f32::from(raw.y), f32::from(raw.z));
+        match sensor_type {
+            SensorType::Accelerometer | SensorType::Gravity | SensorType::LinearAcceleration => {
+                // the dynamic range is given in g's
+                let scale = f32::from(dynamic_range) * STANDARD_GRAVITY / FULL_SCALE;
+                Sample::Acceleration(Acceleration {
+                    x: x * scale,
+                    y: y * scale,
+                    z: z * scale,
+                    accuracy,
+                })
+            }
+            SensorType::Gyroscope | SensorType::UncalibratedGyroscope => {
+                // the dynamic range is given in deg/s
+                let scale = f32::from(dynamic_range) / FULL_SCALE;
+                Sample::AngularRate(AngularRate {
+                    x: x * scale,
+                    y: y * scale,
+                    z: z * scale,
+                    accuracy,
+                })
+            }
+            SensorType::Orientation => {
+                // the orientation uses a fixed scale of 360° for the full range
+                let scale = 360.0 / FULL_SCALE;
+                Sample::Orientation(Orientation {
+                    heading: x * scale,
+                    pitch: y * scale,
+                    roll: z * scale,
+                    accuracy,
+                })
+            }
+            SensorType::RotationVector
+            | SensorType::GameRotationVector
+            | SensorType::GeomagneticRotationVector => {
+                // Epicardium only passes the vector part, w follows from the unit length
+                let (x, y, z) = (
+                    x / QUATERNION_SCALE,
+                    y / QUATERNION_SCALE,
+                    z / QUATERNION_SCALE,
+                );
+                let w = libm::sqrtf((1.0 - x * x - y * y - z * z).max(0.0));
+                Sample::Quaternion(Quaternion {
+                    w,
+                    x,
+                    y,
+                    z,
+                    accuracy,
+                })
+            }
+            SensorType::Magnetometer | SensorType::UncalibratedMagnetometer => Sample::Raw(*raw),
+        }
+    }
+
+    /// The accuracy of the sample.
+    pub fn accuracy(&self) -> Accuracy {
+        match self {
+            Sample::Acceleration(a) => a.accuracy,
+            Sample::AngularRate(r) => r.accuracy,
+            Sample::Orientation(o) => o.accuracy,
+            Sample::Quaternion(q) => q.accuracy,
+            Sample::Raw(raw) => Accuracy::from_status(raw.status),
+        }
+    }
+}
+
 impl Sensor {
-    pub fn enable(sensor_type: SensorType, cfg: SensorConfig) -> Sensor {
+    /// Enables a sensor, its samples are collected until the sensor is disabled or dropped.
+    ///
+    /// # Arguments
+    /// * `sensor_type` - The sensor to enable
+    /// * `cfg` - Buffer length, sample rate and dynamic range of the sensor
+    ///
+    /// # Returns
+    /// `Error::UnsupportedSensor` if Epicardium can't provide samples for the sensor type and
+    /// `Error::SensorBusy` if the sensor already has a handle or the BHI160 driver is busy.
+    pub fn enable(sensor_type: SensorType, cfg: SensorConfig) -> Result<Sensor> {
+        if !sensor_type.is_implemented() {
+            return Err(Error::UnsupportedSensor);
+        }
+        SensorManager::acquire(sensor_type)?;
         let mut raw_cfg = sys::bhi160_sensor_config {
             sample_buffer_len: cfg.sample_buffer_len,
             sample_rate: cfg.sample_rate,
             dynamic_range: cfg.dynamic_range,
             _padding: [0, 0, 0, 0, 0, 0, 0, 0],
         };
-        let sd =
-            unsafe { sys::epic_bhi160_enable_sensor(sensor_type.clone() as u32, &mut raw_cfg) };
-        Sensor {
-            sd: Some(sd),
-            sensor_type,
-            cfg,
+        let sd = unsafe {
+            check(sys::epic_bhi160_enable_sensor(
+                sensor_type.id(),
+                &mut raw_cfg,
+            ))
+        };
+        match sd {
+            Ok(sd) => Ok(Sensor {
+                sd: Some(sd),
+                sensor_type,
+                cfg,
+            }),
+            Err(e) => {
+                SensorManager::release(sensor_type);
+                match e {
+                    Error::DeviceOrResourceBusy => Err(Error::SensorBusy),
+                    e => Err(e),
+                }
+            }
         }
     }
 
-    pub fn read(&self) -> ArrayVec<[DataVector; MAX_BUFFER_SIZE]> {
-        let mut result = ArrayVec::<[DataVector; MAX_BUFFER_SIZE]>::new();
-        match self.sd {
-            Some(sd) => {
-                let default_buffer_value = sys::bhi160_data_vector {
-                    x: 0,
-                    y: 0,
-                    z: 0,
-                    status: 0,
-                    data_type: 0,
-                };
-                let mut buffer: [sys::bhi160_data_vector; MAX_BUFFER_SIZE] =
-                    [default_buffer_value; MAX_BUFFER_SIZE];
-
-                let n: usize = unsafe {
-                    sys::epic_stream_read(
-                        sd,
-                        &mut buffer as *mut _ as *mut core::ffi::c_void,
-                        self.cfg.sample_buffer_len,
-                    ) as usize
-                };
-                for i in 0..n {
-                    result.push(DataVector {
-                        x: buffer[i].x,
-                        y: buffer[i].y,
-                        z: buffer[i].z,
-                        status: buffer[i].status,
-                    });
+    /// The type of this sensor.
+    pub fn sensor_type(&self) -> SensorType {
+        self.sensor_type
+    }
+
+    /// Reads the available samples into the buffer without blocking.
+    ///
+    /// # Arguments
+    /// * `buf` - Buffer for the samples, at most `buf.len()` samples are read
+    ///
+    /// # Returns
+    /// The number of samples read, 0 if no samples were available.
+    pub fn read_into(&self, buf: &mut [DataVector]) -> Result<usize> {
+        let sd = self.sd.ok_or(Error::BadFileDescriptor)?;
+        let n = unsafe {
+            check(sys::epic_stream_read(
+                sd,
+                buf.as_mut_ptr() as *mut core::ffi::c_void,
+                core::mem::size_of_val(buf) as sys::size_t,
+            ))?
+        };
+        Ok(n as usize)
+    }
+
+    /// Iterates over the available samples, which are read in chunks of `DRAIN_CHUNK_LEN`.
+    ///
+    /// The iteration stops when no more samples are available or after the first error.
+    pub fn drain(&self) -> Drain<'_> {
+        Drain {
+            sensor: self,
+            chunk: [DataVector::default(); DRAIN_CHUNK_LEN],
+            pos: 0,
+            len: 0,
+            done: false,
+        }
+    }
+
+    /// Converts a raw sample of this sensor into physical units, using the configured dynamic range.
+    pub fn scale(&self, raw: &DataVector) -> Sample {
+        Sample::from_raw(self.sensor_type, self.cfg.dynamic_range, raw)
+    }
+
+    /// Reads the available samples and converts them into physical units.
+    ///
+    /// # Example
+    /// ```
+    /// use rustcardium::bhi160::{Sample, Sensor, SensorConfig, SensorType};
+    ///
+    /// let accel = Sensor::enable(
+    ///     SensorType::Accelerometer,
+    ///     SensorConfig {
+    ///         sample_buffer_len: 120,
+    ///         sample_rate: 25,
+    ///         dynamic_range: 2,
+    ///     },
+    /// )?;
+    /// # rustcardium::mock::push_bhi160_samples(0, &[(0, 0, 16384, 3)]);
+    /// for sample in accel.samples() {
+    ///     if let Sample::Acceleration(a) = sample? {
+    ///         // `a.z` is about 9.81 m/s² when the card10 lies flat
+    ///     }
+    /// }
+    /// # Ok::<(), rustcardium::Error>(())
+    /// ```
+    pub fn samples(&self) -> impl Iterator<Item = Result<Sample>> + '_ {
+        self.drain().map(move |raw| raw.map(|raw| self.scale(&raw)))
+    }
+
+    /// Waits asynchronously for new samples and reads them into the buffer, see `executor`.
+    ///
+    /// # Arguments
+    /// * `buf` - Buffer for the samples
+    ///
+    /// # Returns
+    /// The number of samples read, which is only 0 for an empty buffer.
+    pub async fn next_batch(&self, buf: &mut [DataVector]) -> Result<usize> {
+        loop {
+            // wait for interrupts that occur while reading the available samples
+            let available = self.sensor_type.interrupt().map(interrupts::wait);
+            let n = self.read_into(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+            match available {
+                Some(available) => available.await?,
+                // without an interrupt, check again after one sample period
+                None => {
+                    let period = 1000 / u64::from(self.cfg.sample_rate.max(1));
+                    time::sleep(Duration::from_millis(period)).await
                 }
             }
-            None => {}
         }
-        result
     }
 
-    pub fn disable(&mut self) {
-        unsafe { sys::epic_bhi160_disable_sensor(self.sensor_type.clone() as u32) };
-        self.sd = None;
+    /// Blocks until new samples are available and reads them into the buffer.
+    ///
+    /// The core sleeps while waiting for the interrupt of the sensor, which uses much less power
+    /// than calling `read_into()` in a loop. Must not be called from within an `async` task,
+    /// use `next_batch()` there.
+    ///
+    /// # Arguments
+    /// * `buf` - Buffer for the samples
+    ///
+    /// # Returns
+    /// The number of samples read, which is only 0 for an empty buffer.
+    pub fn read_blocking(&self, buf: &mut [DataVector]) -> Result<usize> {
+        executor::block_on(self.next_batch(buf))
+    }
+
+    /// Registers a function which is called in interrupt context when new samples are available.
+    ///
+    /// The samples still need to be read with `read_into()` or `drain()`, typically after the
+    /// function set a flag for the main loop.
+    ///
+    /// # Arguments
+    /// * `handler` - Function called when new samples are available
+    ///
+    /// # Returns
+    /// The registration of the handler, see `interrupts::register()`, or
+    /// `Error::UnsupportedSensor` if Epicardium has no interrupt for this sensor.
+    ///
+    /// # Example
+    /// ```
+    /// use core::sync::atomic::{AtomicBool, Ordering};
+    /// use rustcardium::bhi160::{DataVector, Sensor, SensorConfig, SensorType};
+    ///
+    /// static SAMPLES: AtomicBool = AtomicBool::new(false);
+    ///
+    /// let accel = Sensor::enable(
+    ///     SensorType::Accelerometer,
+    ///     SensorConfig {
+    ///         sample_buffer_len: 120,
+    ///         sample_rate: 25,
+    ///         dynamic_range: 2,
+    ///     },
+    /// )?;
+    /// let _data = accel.on_data(|| SAMPLES.store(true, Ordering::SeqCst))?;
+    /// # rustcardium::mock::push_bhi160_samples(0, &[(0, 0, 16384, 3)]);
+    /// let mut buf = [DataVector::default(); 16];
+    /// if SAMPLES.swap(false, Ordering::SeqCst) {
+    ///     let n = accel.read_into(&mut buf)?;
+    ///     // count steps in `buf[..n]`
+    /// }
+    /// # Ok::<(), rustcardium::Error>(())
+    /// ```
+    pub fn on_data(&self, handler: fn()) -> Result<interrupts::Registration> {
+        let interrupt = self
+            .sensor_type
+            .interrupt()
+            .ok_or(Error::UnsupportedSensor)?;
+        interrupts::register(interrupt, handler)
+    }
+
+    /// Disables the sensor, afterwards no more samples can be read with this handle.
+    ///
+    /// The handle is released even if Epicardium fails to disable the sensor, so the sensor
+    /// type can be enabled again.
+    pub fn disable(&mut self) -> Result<()> {
+        if self.sd.take().is_none() {
+            return Ok(());
+        }
+        SensorManager::release(self.sensor_type);
+        match unsafe { check(sys::epic_bhi160_disable_sensor(self.sensor_type.id())) } {
+            Err(Error::DeviceOrResourceBusy) => Err(Error::SensorBusy),
+            result => result.map(|_| ()),
+        }
     }
 }
 
 impl Drop for Sensor {
     fn drop(&mut self) {
-        self.disable();
+        let _ = self.disable();
+    }
+}
+
+/// Iterator over the available samples of a sensor, created with `Sensor::drain()`.
+pub struct Drain<'a> {
+    sensor: &'a Sensor,
+    chunk: [DataVector; DRAIN_CHUNK_LEN],
+    pos: usize,
+    len: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Drain<'a> {
+    type Item = Result<DataVector>;
+
+    fn next(&mut self) -> Option<Result<DataVector>> {
+        if self.pos == self.len {
+            if self.done {
+                return None;
+            }
+            self.pos = 0;
+            self.len = 0;
+            match self.sensor.read_into(&mut self.chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Ok(n) => self.len = n,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        let sample = self.chunk[self.pos];
+        self.pos += 1;
+        Some(Ok(sample))
+    }
+}
+
+/// Samples for the tests of the modules which process BHI160 data.
+#[cfg(test)]
+pub(crate) mod test_util {
+    use super::{Acceleration, Accuracy, AngularRate};
+
+    /// An acceleration in m/s² with high accuracy.
+    pub(crate) fn accel(x: f32, y: f32, z: f32) -> Acceleration {
+        Acceleration {
+            x,
+            y,
+            z,
+            accuracy: Accuracy::High,
+        }
+    }
+
+    /// An angular rate in °/s with high accuracy.
+    pub(crate) fn gyro(x: f32, y: f32, z: f32) -> AngularRate {
+        AngularRate {
+            x,
+            y,
+            z,
+            accuracy: Accuracy::High,
+        }
     }
 }
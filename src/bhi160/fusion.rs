@@ -0,0 +1,592 @@
+//! Sensor fusion of the accelerometer and the gyroscope into a stable orientation.
+//!
+//! Epicardium doesn't implement the rotation vector and gravity sensors of the BHI160, so the
+//! orientation is calculated here with a Madgwick filter: the gyroscope gives fast and smooth
+//! changes of the orientation, while the accelerometer corrects the drift of roll and pitch.
+//! Without a magnetometer, the yaw is relative to the start and drifts slowly.
+//!
+//! `Fusion` combines the filter with a `Calibration`, which removes the bias of the gyroscope
+//! and the offset of the accelerometer. The gyroscope bias is updated automatically whenever
+//! the card10 lies still. Calibrations can be stored in a file and loaded again on the next start.
+//!
+//! The accelerometer and the gyroscope need to be enabled with the same sample rate, their
+//! samples are processed in pairs.
+//!
+//! # Example
+//! ```
+//! use rustcardium::bhi160::fusion::{Calibration, Fusion};
+//! use rustcardium::bhi160::{Sample, Sensor, SensorConfig, SensorType};
+//!
+//! let accel = Sensor::enable(
+//!     SensorType::Accelerometer,
+//!     SensorConfig {
+//!         sample_buffer_len: 120,
+//!         sample_rate: 50,
+//!         dynamic_range: 4,
+//!     },
+//! )?;
+//! let gyro = Sensor::enable(
+//!     SensorType::Gyroscope,
+//!     SensorConfig {
+//!         sample_buffer_len: 120,
+//!         sample_rate: 50,
+//!         dynamic_range: 2000,
+//!     },
+//! )?;
+//! let calibration = Calibration::load(Calibration::DEFAULT_PATH).unwrap_or_default();
+//! let mut fusion = Fusion::new(50, calibration);
+//! # rustcardium::mock::push_bhi160_samples(0, &[(0, 0, 8192, 3); 10]);
+//! # rustcardium::mock::push_bhi160_samples(3, &[(0, 0, 0, 3); 10]);
+//! for (a, g) in accel.samples().zip(gyro.samples()) {
+//!     if let (Sample::Acceleration(a), Sample::AngularRate(g)) = (a?, g?) {
+//!         fusion.update(&a, &g);
+//!     }
+//! }
+//! let tilt = fusion.euler();
+//! // move the player with `tilt.roll` and `tilt.pitch`
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::fs;
+use crate::io::Read;
+
+/// Degrees to radians.
+const DEG_TO_RAD: f32 = core::f32::consts::PI / 180.0;
+
+/// Unit quaternion describing the orientation of the card10.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Quaternion {
+    /// The quaternion without rotation.
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    /// Scales the quaternion to unit length.
+    fn normalized(self) -> Quaternion {
+        let norm =
+            libm::sqrtf(self.w * self.w + self.x * self.x + self.y * self.y + self.z * self.z);
+        if norm == 0.0 {
+            return Quaternion::IDENTITY;
+        }
+        Quaternion {
+            w: self.w / norm,
+            x: self.x / norm,
+            y: self.y / norm,
+            z: self.z / norm,
+        }
+    }
+
+    /// Converts the quaternion into roll, pitch and yaw.
+    pub fn to_euler(&self) -> EulerAngles {
+        let Quaternion { w, x, y, z } = *self;
+        let roll = libm::atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+        let pitch = libm::asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+        let yaw = libm::atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+        EulerAngles {
+            roll: roll / DEG_TO_RAD,
+            pitch: pitch / DEG_TO_RAD,
+            yaw: yaw / DEG_TO_RAD,
+        }
+    }
+}
+
+impl From<super::Quaternion> for Quaternion {
+    fn from(q: super::Quaternion) -> Quaternion {
+        Quaternion {
+            w: q.w,
+            x: q.x,
+            y: q.y,
+            z: q.z,
+        }
+    }
+}
+
+/// Orientation as rotations around the axes of the card10 in degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EulerAngles {
+    /// Rotation around x, -180 <= roll <= 180
+    pub roll: f32,
+    /// Rotation around y, -90 <= pitch <= 90
+    pub pitch: f32,
+    /// Rotation around z, -180 <= yaw <= 180
+    pub yaw: f32,
+}
+
+/// Madgwick filter which fuses the accelerometer and the gyroscope into an orientation.
+pub struct Madgwick {
+    /// Gain of the accelerometer correction, higher values converge faster but are more noisy
+    pub beta: f32,
+    sample_period: f32,
+    q: Quaternion,
+    initialized: bool,
+}
+
+impl Madgwick {
+    /// Default gain of the accelerometer correction.
+    pub const DEFAULT_BETA: f32 = 0.1;
+
+    /// Creates a filter for the given sample rate in Hz.
+    pub fn new(sample_rate: u16) -> Madgwick {
+        Madgwick {
+            beta: Madgwick::DEFAULT_BETA,
+            sample_period: 1.0 / f32::from(sample_rate.max(1)),
+            q: Quaternion::IDENTITY,
+            initialized: false,
+        }
+    }
+
+    /// The current orientation.
+    pub fn quaternion(&self) -> Quaternion {
+        self.q
+    }
+
+    /// Starts again from the orientation given by the next accelerometer sample.
+    pub fn reset(&mut self) {
+        self.q = Quaternion::IDENTITY;
+        self.initialized = false;
+    }
+
+    /// Processes a pair of calibrated samples.
+    ///
+    /// # Arguments
+    /// * `a` - Sample of the accelerometer
+    /// * `g` - Sample of the gyroscope
+    pub fn update(&mut self, a: &Acceleration, g: &AngularRate) {
+        let norm = libm::sqrtf(a.x * a.x + a.y * a.y + a.z * a.z);
+        if !self.initialized {
+            if norm == 0.0 {
+                return;
+            }
+            // start with roll and pitch given by gravity, so the filter doesn't need to converge
+            let roll = libm::atan2f(a.y, a.z);
+            let pitch = libm::atan2f(-a.x, libm::sqrtf(a.y * a.y + a.z * a.z));
+            let (sr, cr) = (libm::sinf(roll / 2.0), libm::cosf(roll / 2.0));
+            let (sp, cp) = (libm::sinf(pitch / 2.0), libm::cosf(pitch / 2.0));
+            self.q = Quaternion {
+                w: cr * cp,
+                x: sr * cp,
+                y: cr * sp,
+                z: -sr * sp,
+            };
+            self.initialized = true;
+            return;
+        }
+
+        let Quaternion {
+            w: q0,
+            x: q1,
+            y: q2,
+            z: q3,
+        } = self.q;
+        let (gx, gy, gz) = (g.x * DEG_TO_RAD, g.y * DEG_TO_RAD, g.z * DEG_TO_RAD);
+
+        // rate of change of the quaternion from the gyroscope
+        let mut d0 = 0.5 * (-q1 * gx - q2 * gy - q3 * gz);
+        let mut d1 = 0.5 * (q0 * gx + q2 * gz - q3 * gy);
+        let mut d2 = 0.5 * (q0 * gy - q1 * gz + q3 * gx);
+        let mut d3 = 0.5 * (q0 * gz + q1 * gy - q2 * gx);
+
+        if norm > 0.0 {
+            let (ax, ay, az) = (a.x / norm, a.y / norm, a.z / norm);
+            // gradient descent step towards the orientation given by gravity
+            let s0 = 4.0 * q0 * q2 * q2 + 2.0 * q2 * ax + 4.0 * q0 * q1 * q1 - 2.0 * q1 * ay;
+            let s1 =
+                4.0 * q1 * q3 * q3 - 2.0 * q3 * ax + 4.0 * q0 * q0 * q1 - 2.0 * q0 * ay - 4.0 * q1
+                    + 8.0 * q1 * q1 * q1
+                    + 8.0 * q1 * q2 * q2
+                    + 4.0 * q1 * az;
+            let s2 =
+                4.0 * q0 * q0 * q2 + 2.0 * q0 * ax + 4.0 * q2 * q3 * q3 - 2.0 * q3 * ay - 4.0 * q2
+                    + 8.0 * q2 * q1 * q1
+                    + 8.0 * q2 * q2 * q2
+                    + 4.0 * q2 * az;
+            let s3 = 4.0 * q1 * q1 * q3 - 2.0 * q1 * ax + 4.0 * q2 * q2 * q3 - 2.0 * q2 * ay;
+            let s_norm = libm::sqrtf(s0 * s0 + s1 * s1 + s2 * s2 + s3 * s3);
+            if s_norm > 0.0 {
+                d0 -= self.beta * s0 / s_norm;
+                d1 -= self.beta * s1 / s_norm;
+                d2 -= self.beta * s2 / s_norm;
+                d3 -= self.beta * s3 / s_norm;
+            }
+        }
+
+        self.q = Quaternion {
+            w: q0 + d0 * self.sample_period,
+            x: q1 + d1 * self.sample_period,
+            y: q2 + d2 * self.sample_period,
+            z: q3 + d3 * self.sample_period,
+        }
+        .normalized();
+    }
+}
+
+/// Gyroscope bias and accelerometer offset, which are subtracted from the samples.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Calibration {
+    /// Bias of the gyroscope in deg/s
+    pub gyro_bias: [f32; 3],
+    /// Offset of the accelerometer in m/s²
+    pub accel_offset: [f32; 3],
+}
+
+/// Identifies calibration files and their format version.
+const CALIBRATION_MAGIC: &[u8; 4] = b"CAL1";
+
+/// Size of a calibration file, the magic followed by 6 little endian `f32`.
+const CALIBRATION_SIZE: usize = 4 + 6 * 4;
+
+impl Calibration {
+    /// Default path of the calibration file.
+    pub const DEFAULT_PATH: &'static str = "/bhi160.cal";
+
+    /// Removes the bias from a sample of the gyroscope.
+    pub fn apply_gyro(&self, g: &AngularRate) -> AngularRate {
+        AngularRate {
+            x: g.x - self.gyro_bias[0],
+            y: g.y - self.gyro_bias[1],
+            z: g.z - self.gyro_bias[2],
+            accuracy: g.accuracy,
+        }
+    }
+
+    /// Removes the offset from a sample of the accelerometer.
+    pub fn apply_accel(&self, a: &Acceleration) -> Acceleration {
+        Acceleration {
+            x: a.x - self.accel_offset[0],
+            y: a.y - self.accel_offset[1],
+            z: a.z - self.accel_offset[2],
+            accuracy: a.accuracy,
+        }
+    }
+
+    /// Loads a calibration which was stored with `save()`.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the calibration file
+    ///
+    /// # Returns
+    /// `Error::FileNotInLoadableFormat` if the file is no calibration file.
+    pub fn load(path: &str) -> Result<Calibration> {
+        let mut data = [0; CALIBRATION_SIZE];
+        let mut file = fs::File::open(path)?;
+        file.read_exact(&mut data).map_err(|e| match e {
+            Error::UnexpectedEof => Error::FileNotInLoadableFormat,
+            e => e,
+        })?;
+        file.close()?;
+        if &data[..4] != CALIBRATION_MAGIC {
+            return Err(Error::FileNotInLoadableFormat);
+        }
+        let mut values = [0.0; 6];
+        for (value, bytes) in values.iter_mut().zip(data[4..].chunks(4)) {
+            *value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        Ok(Calibration {
+            gyro_bias: [values[0], values[1], values[2]],
+            accel_offset: [values[3], values[4], values[5]],
+        })
+    }
+
+    /// Stores the calibration in a file, which is created or replaced.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the calibration file
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut data = [0; CALIBRATION_SIZE];
+        data[..4].copy_from_slice(CALIBRATION_MAGIC);
+        let values = self.gyro_bias.iter().chain(self.accel_offset.iter());
+        for (bytes, value) in data[4..].chunks_mut(4).zip(values) {
+            bytes.copy_from_slice(&value.to_le_bytes());
+        }
+        fs::write(path, &data)
+    }
+}
+
+/// Averages samples while the card10 lies still, used to calibrate the sensors.
+///
+/// The average is restarted whenever a sample deviates more than the tolerance from the
+/// current average.
+pub struct Calibrator {
+    samples: u32,
+    tolerance: f32,
+    sum: [f32; 3],
+    count: u32,
+}
+
+impl Calibrator {
+    /// Creates a calibrator.
+    ///
+    /// # Arguments
+    /// * `samples` - Number of samples to average, e.g. 2 seconds worth of samples
+    /// * `tolerance` - Maximal deviation of a sample from the average, in the unit of the samples
+    pub fn new(samples: u32, tolerance: f32) -> Calibrator {
+        Calibrator {
+            samples: samples.max(1),
+            tolerance,
+            sum: [0.0; 3],
+            count: 0,
+        }
+    }
+
+    /// Adds a sample.
+    ///
+    /// # Returns
+    /// The average once enough consecutive samples were still.
+    pub fn update(&mut self, x: f32, y: f32, z: f32) -> Option<[f32; 3]> {
+        let sample = [x, y, z];
+        if self.count > 0 {
+            let moved = (0..3).any(|i| {
+                let mean = self.sum[i] / self.count as f32;
+                (sample[i] - mean).abs() > self.tolerance
+            });
+            if moved {
+                self.reset();
+            }
+        }
+        for (sum, value) in self.sum.iter_mut().zip(sample.iter()) {
+            *sum += value;
+        }
+        self.count += 1;
+        if self.count < self.samples {
+            return None;
+        }
+        let count = self.count as f32;
+        let mean = [
+            self.sum[0] / count,
+            self.sum[1] / count,
+            self.sum[2] / count,
+        ];
+        self.reset();
+        Some(mean)
+    }
+
+    /// Discards the collected samples.
+    pub fn reset(&mut self) {
+        self.sum = [0.0; 3];
+        self.count = 0;
+    }
+
+    /// Calibrates the gyroscope, which measures no rotation when still.
+    ///
+    /// # Returns
+    /// The bias of the gyroscope once enough consecutive samples were still.
+    pub fn update_gyro(&mut self, g: &AngularRate) -> Option<[f32; 3]> {
+        self.update(g.x, g.y, g.z)
+    }
+
+    /// Calibrates the accelerometer, the card10 needs to lie flat with the display facing up.
+    ///
+    /// # Returns
+    /// The offset of the accelerometer once enough consecutive samples were still.
+    pub fn update_accel(&mut self, a: &Acceleration) -> Option<[f32; 3]> {
+        self.update(a.x, a.y, a.z)
+            .map(|[x, y, z]| [x, y, z - STANDARD_GRAVITY])
+    }
+}
+
+/// Calibrated sensor fusion with automatic gyroscope bias calibration.
+pub struct Fusion {
+    filter: Madgwick,
+    sample_rate: u16,
+    calibration: Calibration,
+    gyro_calibrator: Option<Calibrator>,
+}
+
+impl Fusion {
+    /// Maximal deviation of the gyroscope in deg/s while still.
+    pub const STILL_TOLERANCE: f32 = 1.5;
+
+    /// Creates a fusion for sensors with the given sample rate in Hz.
+    ///
+    /// The gyroscope bias is recalibrated whenever the card10 lies still for two seconds.
+    pub fn new(sample_rate: u16, calibration: Calibration) -> Fusion {
+        Fusion {
+            filter: Madgwick::new(sample_rate),
+            sample_rate,
+            calibration,
+            gyro_calibrator: Some(Fusion::gyro_calibrator(sample_rate)),
+        }
+    }
+
+    /// Calibrator of the gyroscope bias over two seconds, a sample rate of 0 counts as 1 Hz
+    /// like in `Madgwick::new()`.
+    fn gyro_calibrator(sample_rate: u16) -> Calibrator {
+        Calibrator::new(2 * u32::from(sample_rate.max(1)), Fusion::STILL_TOLERANCE)
+    }
+
+    /// Enables or disables the automatic gyroscope bias calibration.
+    pub fn set_auto_calibration(&mut self, enabled: bool) {
+        self.gyro_calibrator = if enabled {
+            Some(Fusion::gyro_calibrator(self.sample_rate))
+        } else {
+            None
+        };
+    }
+
+    /// The current calibration, including the automatic gyroscope bias calibration.
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    /// The underlying filter, e.g. to change its gain.
+    pub fn filter(&mut self) -> &mut Madgwick {
+        &mut self.filter
+    }
+
+    /// Processes a pair of raw samples of the accelerometer and the gyroscope.
+    pub fn update(&mut self, a: &Acceleration, g: &AngularRate) {
+        if let Some(calibrator) = &mut self.gyro_calibrator {
+            if let Some(bias) = calibrator.update_gyro(g) {
+                self.calibration.gyro_bias = bias;
+            }
+        }
+        let a = self.calibration.apply_accel(a);
+        let g = self.calibration.apply_gyro(g);
+        self.filter.update(&a, &g);
+    }
+
+    /// The current orientation as quaternion.
+    pub fn quaternion(&self) -> Quaternion {
+        self.filter.quaternion()
+    }
+
+    /// The current orientation as roll, pitch and yaw.
+    pub fn euler(&self) -> EulerAngles {
+        self.filter.quaternion().to_euler()
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::bhi160::test_util::{accel, gyro};
+    use crate::mock;
+
+    #[test]
+    fn converges_to_gravity() {
+        let mut filter = Madgwick::new(50);
+        // start flat, then hold the card10 rolled by 30° without any rotation measured
+        filter.update(&accel(0.0, 0.0, STANDARD_GRAVITY), &gyro(0.0, 0.0, 0.0));
+        assert_eq!(filter.quaternion(), Quaternion::IDENTITY);
+        let roll = 30.0 * DEG_TO_RAD;
+        let tilted = accel(
+            0.0,
+            STANDARD_GRAVITY * libm::sinf(roll),
+            STANDARD_GRAVITY * libm::cosf(roll),
+        );
+        for _ in 0..20 * 50 {
+            filter.update(&tilted, &gyro(0.0, 0.0, 0.0));
+        }
+        let euler = filter.quaternion().to_euler();
+        assert!((euler.roll - 30.0).abs() < 1.0, "{:?}", euler);
+        assert!(euler.pitch.abs() < 1.0, "{:?}", euler);
+    }
+
+    #[test]
+    fn starts_from_gravity() {
+        let mut filter = Madgwick::new(50);
+        // pitched by 45°
+        let g = STANDARD_GRAVITY / core::f32::consts::SQRT_2;
+        filter.update(&accel(-g, 0.0, g), &gyro(0.0, 0.0, 0.0));
+        let euler = filter.quaternion().to_euler();
+        assert!((euler.pitch - 45.0).abs() < 0.1, "{:?}", euler);
+        assert!(euler.roll.abs() < 0.1, "{:?}", euler);
+    }
+
+    #[test]
+    fn gyro_bias_is_calibrated() {
+        let mut fusion = Fusion::new(50, Calibration::default());
+        let bias = gyro(0.5, -0.3, 0.8);
+        for _ in 0..2 * 50 {
+            fusion.update(&accel(0.0, 0.0, STANDARD_GRAVITY), &bias);
+        }
+        let calibrated = fusion.calibration().gyro_bias;
+        for (value, expected) in calibrated.iter().zip([0.5, -0.3, 0.8].iter()) {
+            assert!((value - expected).abs() < 1e-4, "{:?}", calibrated);
+        }
+        // without the bias, the yaw doesn't drift anymore
+        let yaw = fusion.euler().yaw;
+        for _ in 0..10 * 50 {
+            fusion.update(&accel(0.0, 0.0, STANDARD_GRAVITY), &bias);
+        }
+        assert!((fusion.euler().yaw - yaw).abs() < 0.01);
+    }
+
+    #[test]
+    fn auto_calibration_keeps_its_window() {
+        // a sample rate of 0 counts as 1 Hz, so two samples are two seconds
+        for &enable_again in &[false, true] {
+            let mut fusion = Fusion::new(0, Calibration::default());
+            if enable_again {
+                fusion.set_auto_calibration(false);
+                fusion.set_auto_calibration(true);
+            }
+            let bias = gyro(0.5, 0.0, 0.0);
+            fusion.update(&accel(0.0, 0.0, STANDARD_GRAVITY), &bias);
+            assert_eq!(fusion.calibration().gyro_bias, [0.0; 3]);
+            fusion.update(&accel(0.0, 0.0, STANDARD_GRAVITY), &bias);
+            assert_eq!(fusion.calibration().gyro_bias, [0.5, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn calibrator_restarts_on_movement() {
+        let mut calibrator = Calibrator::new(10, 0.5);
+        for _ in 0..9 {
+            assert_eq!(calibrator.update_accel(&accel(0.1, 0.2, 9.9)), None);
+        }
+        // the card10 moved, the average starts again after the movement
+        assert_eq!(calibrator.update_accel(&accel(3.0, 0.2, 9.9)), None);
+        for _ in 0..9 {
+            assert_eq!(calibrator.update_accel(&accel(0.1, 0.2, 9.9)), None);
+        }
+        let offset = calibrator.update_accel(&accel(0.1, 0.2, 9.9)).unwrap();
+        assert!((offset[0] - 0.1).abs() < 1e-4);
+        assert!((offset[2] - (9.9 - STANDARD_GRAVITY)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn calibration_round_trip() {
+        mock::reset();
+        let calibration = Calibration {
+            gyro_bias: [0.25, -1.5, 3.0],
+            accel_offset: [0.01, -0.02, 0.125],
+        };
+        calibration.save(Calibration::DEFAULT_PATH).unwrap();
+        let file = mock::file(Calibration::DEFAULT_PATH).unwrap();
+        assert_eq!(file.len(), CALIBRATION_SIZE);
+        assert_eq!(&file[..4], b"CAL1");
+        assert_eq!(
+            Calibration::load(Calibration::DEFAULT_PATH),
+            Ok(calibration)
+        );
+    }
+
+    #[test]
+    fn invalid_calibration_files() {
+        mock::reset();
+        let mut data = [0u8; CALIBRATION_SIZE];
+        data[..4].copy_from_slice(b"CAL2");
+        mock::add_file("/other.cal", &data);
+        mock::add_file("/truncated.cal", &b"CAL1\0\0\0\0"[..]);
+        assert_eq!(
+            Calibration::load("/other.cal"),
+            Err(Error::FileNotInLoadableFormat)
+        );
+        assert_eq!(
+            Calibration::load("/truncated.cal"),
+            Err(Error::FileNotInLoadableFormat)
+        );
+        assert!(Calibration::load("/missing.cal").is_err());
+    }
+}
@@ -0,0 +1,477 @@
+//! Environmental data of the BME680: temperature, humidity, pressure and gas resistance.
+//!
+//! The sensor is initialized with `Bme680::init()` and deinitialized when the last handle is
+//! dropped, several handles share it.
+//! Besides the measured values, a `Reading` derives the dew point and the altitude, and
+//! `AirQuality` calculates an indoor air quality index from the gas resistance. The `history`
+//! module keeps the readings of the last hours for statistics, trends and charts.
+//!
+//! # Example
+//! ```
+//! use rustcardium::bme680::{AirQuality, AirQualityConfig, Bme680};
+//!
+//! let bme680 = Bme680::init()?;
+//! let mut air_quality = AirQuality::new(AirQualityConfig::default());
+//! let reading = bme680.read()?;
+//! let dew_point = reading.dew_point();
+//! if let Some(index) = air_quality.update(&reading) {
+//!     // 0 is excellent, 500 extremely polluted air
+//! }
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub mod history;
+
+/// Mean pressure at sea level in hPa, the default reference for `Reading::altitude()`.
+pub const STANDARD_SEA_LEVEL_PRESSURE: f32 = 1013.25;
+
+/// Number of `Bme680` handles, the sensor is initialized while it isn't zero.
+static HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Forgets all handles, see `mock::reset()`.
+#[cfg(feature = "mock")]
+pub(crate) fn reset() {
+    HANDLES.store(0, Ordering::SeqCst);
+}
+
+/// Handle of the initialized BME680, several handles share the sensor. It is deinitialized when
+/// the last handle is dropped.
+pub struct Bme680 {
+    initialized: bool,
+}
+
+impl Bme680 {
+    /// Initializes the sensor, if there isn't a handle yet.
+    ///
+    /// # Returns
+    /// `Error::IoError` if the communication with the sensor failed and `Error::NoSuchDevice` if
+    /// the sensor was not found.
+    pub fn init() -> Result<Bme680> {
+        if HANDLES.fetch_add(1, Ordering::SeqCst) == 0 {
+            if let Err(e) = unsafe { check(sys::epic_bme680_init()) } {
+                HANDLES.fetch_sub(1, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(Bme680 { initialized: true })
+    }
+
+    /// True if there is a `Bme680` handle.
+    pub fn is_initialized() -> bool {
+        HANDLES.load(Ordering::SeqCst) > 0
+    }
+
+    /// Reads the current data of the sensor.
+    ///
+    /// # Returns
+    /// `Error::InvalidArgument` if the sensor is not initialized, `Error::IoError` if the
+    /// communication with the sensor failed and `Error::NoSuchDevice` if the sensor was not
+    /// found.
+    pub fn read(&self) -> Result<Reading> {
+        let mut data = sys::bme680_sensor_data {
+            temperature: 0.0,
+            humidity: 0.0,
+            pressure: 0.0,
+            gas_resistance: 0.0,
+        };
+        unsafe { check(sys::epic_bme680_read_sensors(&mut data))? };
+        Ok(Reading {
+            temperature: data.temperature,
+            humidity: data.humidity,
+            pressure: data.pressure,
+            gas_resistance: data.gas_resistance,
+        })
+    }
+
+    /// Releases the handle and deinitializes the sensor if it was the last one.
+    ///
+    /// In contrast to dropping the handle, this reports errors.
+    pub fn deinit(mut self) -> Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> Result<()> {
+        if !self.initialized {
+            return Ok(());
+        }
+        self.initialized = false;
+        if HANDLES.fetch_sub(1, Ordering::SeqCst) == 1 {
+            unsafe { check(sys::epic_bme680_deinit())? };
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Bme680 {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+/// Data measured by the BME680.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// Temperature in °C
+    pub temperature: f32,
+    /// Relative humidity in %
+    pub humidity: f32,
+    /// Pressure in hPa
+    pub pressure: f32,
+    /// Resistance of the gas sensor in Ω, higher values mean cleaner air
+    pub gas_resistance: f32,
+}
+
+impl Reading {
+    /// Temperature in °C at which the air would be saturated with water vapour.
+    ///
+    /// Calculated with the Magnus formula, which is accurate to about 0.1 °C between -45 °C and 60 °C.
+    pub fn dew_point(&self) -> f32 {
+        const A: f32 = 17.62;
+        const B: f32 = 243.12;
+        let humidity = self.humidity.clamp(0.01, 100.0);
+        let gamma = libm::logf(humidity / 100.0) + A * self.temperature / (B + self.temperature);
+        B * gamma / (A - gamma)
+    }
+
+    /// Altitude in meters, estimated with the barometric formula of the standard atmosphere.
+    ///
+    /// # Arguments
+    /// * `sea_level_pressure` - Current pressure at sea level in hPa, e.g. `STANDARD_SEA_LEVEL_PRESSURE` or a local weather report
+    pub fn altitude(&self, sea_level_pressure: f32) -> f32 {
+        44_330.0 * (1.0 - libm::powf(self.pressure / sea_level_pressure, 1.0 / 5.255))
+    }
+
+    /// Pressure at sea level in hPa, which can be used as reference for `altitude()` if the
+    /// current altitude is known.
+    ///
+    /// # Arguments
+    /// * `altitude` - Current altitude in meters
+    pub fn sea_level_pressure(&self, altitude: f32) -> f32 {
+        self.pressure / libm::powf(1.0 - altitude / 44_330.0, 5.255)
+    }
+}
+
+/// Parameters of the `AirQuality` index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AirQualityConfig {
+    /// Number of readings which determine the initial gas resistance of clean air, the sensor
+    /// needs a few minutes to heat up
+    pub burn_in: u32,
+    /// Relative humidity in % which is considered optimal
+    pub humidity_baseline: f32,
+    /// Share of the humidity in the index, 0 <= humidity_weighting <= 1
+    pub humidity_weighting: f32,
+    /// Weight of a new reading when the gas resistance of clean air adapts to lower values
+    pub baseline_adaptation: f32,
+}
+
+impl Default for AirQualityConfig {
+    fn default() -> Self {
+        AirQualityConfig {
+            burn_in: 50,
+            humidity_baseline: 40.0,
+            humidity_weighting: 0.25,
+            baseline_adaptation: 0.001,
+        }
+    }
+}
+
+/// Classification of the `AirQuality` index, as used by Bosch for the BME680.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AirQualityLevel {
+    /// Index 0 to 50
+    Excellent,
+    /// Index 51 to 100
+    Good,
+    /// Index 101 to 150
+    LightlyPolluted,
+    /// Index 151 to 200
+    ModeratelyPolluted,
+    /// Index 201 to 250
+    HeavilyPolluted,
+    /// Index 251 to 350
+    SeverelyPolluted,
+    /// Index above 350
+    ExtremelyPolluted,
+}
+
+impl AirQualityLevel {
+    /// The level of an index.
+    pub fn from_index(index: f32) -> AirQualityLevel {
+        if index <= 50.0 {
+            AirQualityLevel::Excellent
+        } else if index <= 100.0 {
+            AirQualityLevel::Good
+        } else if index <= 150.0 {
+            AirQualityLevel::LightlyPolluted
+        } else if index <= 200.0 {
+            AirQualityLevel::ModeratelyPolluted
+        } else if index <= 250.0 {
+            AirQualityLevel::HeavilyPolluted
+        } else if index <= 350.0 {
+            AirQualityLevel::SeverelyPolluted
+        } else {
+            AirQualityLevel::ExtremelyPolluted
+        }
+    }
+}
+
+/// Rolling indoor air quality index from 0 (excellent) to 500 (extremely polluted).
+///
+/// The gas resistance is compared to the resistance of clean air, which is the mean of the
+/// burn-in readings at first. Higher resistances raise it immediately, lower resistances only
+/// lower it slowly, so the index adapts to the drift of the sensor. The deviation of the
+/// humidity from the optimum adds to the index.
+pub struct AirQuality {
+    config: AirQualityConfig,
+    readings: u32,
+    baseline: f32,
+    index: Option<f32>,
+}
+
+impl AirQuality {
+    /// Creates an index which needs `config.burn_in` readings before it returns values.
+    pub fn new(config: AirQualityConfig) -> AirQuality {
+        AirQuality {
+            config,
+            readings: 0,
+            baseline: 0.0,
+            index: None,
+        }
+    }
+
+    /// The configuration of this index.
+    pub fn config(&self) -> &AirQualityConfig {
+        &self.config
+    }
+
+    /// Processes the next reading.
+    ///
+    /// # Returns
+    /// The updated index, None during the burn-in.
+    pub fn update(&mut self, reading: &Reading) -> Option<f32> {
+        let gas = reading.gas_resistance;
+        if self.readings < self.config.burn_in {
+            self.readings += 1;
+            self.baseline += (gas - self.baseline) / self.readings as f32;
+            return None;
+        }
+        if gas > self.baseline {
+            self.baseline = gas;
+        } else {
+            self.baseline += self.config.baseline_adaptation * (gas - self.baseline);
+        }
+
+        let weighting = self.config.humidity_weighting.clamp(0.0, 1.0);
+        let optimum = self.config.humidity_baseline.clamp(1.0, 99.0);
+        let offset = reading.humidity - optimum;
+        let humidity_score = if offset > 0.0 {
+            (100.0 - optimum - offset) / (100.0 - optimum)
+        } else {
+            (optimum + offset) / optimum
+        };
+        let gas_score = if self.baseline > 0.0 {
+            gas / self.baseline
+        } else {
+            1.0
+        };
+        let score = weighting * humidity_score.max(0.0) + (1.0 - weighting) * gas_score.max(0.0);
+        let index = (1.0 - score.min(1.0)) * 500.0;
+        self.index = Some(index);
+        self.index
+    }
+
+    /// The index of the last reading, None during the burn-in.
+    pub fn index(&self) -> Option<f32> {
+        self.index
+    }
+
+    /// The classification of the last index, None during the burn-in.
+    pub fn level(&self) -> Option<AirQualityLevel> {
+        self.index.map(AirQualityLevel::from_index)
+    }
+
+    /// Gas resistance of clean air in Ω, None during the burn-in.
+    pub fn baseline(&self) -> Option<f32> {
+        self.index.map(|_| self.baseline)
+    }
+
+    /// Starts a new burn-in, e.g. after the sensor was deinitialized.
+    pub fn reset(&mut self) {
+        self.readings = 0;
+        self.baseline = 0.0;
+        self.index = None;
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    fn reading(temperature: f32, humidity: f32, pressure: f32, gas_resistance: f32) -> Reading {
+        Reading {
+            temperature,
+            humidity,
+            pressure,
+            gas_resistance,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn dew_point() {
+        assert_close(reading(20.0, 50.0, 1000.0, 0.0).dew_point(), 9.26, 0.05);
+        assert_close(reading(30.0, 80.0, 1000.0, 0.0).dew_point(), 26.17, 0.05);
+        assert_close(reading(-10.0, 60.0, 1000.0, 0.0).dew_point(), -16.3, 0.05);
+        // saturated air
+        assert_close(reading(25.0, 100.0, 1000.0, 0.0).dew_point(), 25.0, 0.01);
+        assert_close(reading(0.0, 100.0, 1000.0, 0.0).dew_point(), 0.0, 0.01);
+        // the humidity is clamped
+        assert_close(reading(20.0, 120.0, 1000.0, 0.0).dew_point(), 20.0, 0.01);
+        assert!(reading(20.0, 0.0, 1000.0, 0.0).dew_point().is_finite());
+    }
+
+    #[test]
+    fn altitude() {
+        let at = |pressure| reading(20.0, 50.0, pressure, 0.0);
+        assert_close(at(1013.25).altitude(STANDARD_SEA_LEVEL_PRESSURE), 0.0, 0.01);
+        // the standard atmosphere at 1000 m and 3000 m
+        assert_close(
+            at(898.75).altitude(STANDARD_SEA_LEVEL_PRESSURE),
+            1000.0,
+            2.0,
+        );
+        assert_close(at(701.1).altitude(STANDARD_SEA_LEVEL_PRESSURE), 3000.0, 5.0);
+        // below sea level
+        assert!(at(1020.0).altitude(STANDARD_SEA_LEVEL_PRESSURE) < 0.0);
+        // a lower pressure at sea level means a lower altitude
+        assert_close(at(990.0).altitude(990.0), 0.0, 0.01);
+    }
+
+    #[test]
+    fn sea_level_pressure() {
+        let reading = reading(20.0, 50.0, 898.75, 0.0);
+        assert_close(reading.sea_level_pressure(0.0), 898.75, 0.01);
+        assert_close(reading.sea_level_pressure(1000.0), 1013.25, 0.3);
+        // the inverse of altitude()
+        for &altitude in &[-100.0, 0.0, 250.0, 1000.0, 2500.0] {
+            let sea_level = reading.sea_level_pressure(altitude);
+            assert_close(reading.altitude(sea_level), altitude, 0.5);
+        }
+    }
+
+    fn air_quality() -> AirQuality {
+        AirQuality::new(AirQualityConfig {
+            burn_in: 3,
+            ..AirQualityConfig::default()
+        })
+    }
+
+    #[test]
+    fn air_quality_burn_in() {
+        let mut air_quality = air_quality();
+        for &gas in &[100_000.0, 110_000.0, 120_000.0] {
+            assert_eq!(air_quality.update(&reading(20.0, 40.0, 1000.0, gas)), None);
+            assert_eq!(air_quality.index(), None);
+            assert_eq!(air_quality.level(), None);
+            assert_eq!(air_quality.baseline(), None);
+        }
+        // the baseline is the mean of the burn-in readings
+        let index = air_quality.update(&reading(20.0, 40.0, 1000.0, 110_000.0));
+        assert_eq!(index, Some(0.0));
+        assert_eq!(air_quality.level(), Some(AirQualityLevel::Excellent));
+        assert_close(air_quality.baseline().unwrap(), 110_000.0, 0.5);
+
+        air_quality.reset();
+        assert_eq!(air_quality.index(), None);
+        assert_eq!(
+            air_quality.update(&reading(20.0, 40.0, 1000.0, 50_000.0)),
+            None
+        );
+
+        let mut default = AirQuality::new(AirQualityConfig::default());
+        for _ in 0..50 {
+            assert_eq!(default.update(&reading(20.0, 40.0, 1000.0, 1.0e5)), None);
+        }
+        assert_eq!(
+            default.update(&reading(20.0, 40.0, 1000.0, 1.0e5)),
+            Some(0.0)
+        );
+    }
+
+    #[test]
+    fn air_quality_baseline() {
+        let mut air_quality = air_quality();
+        for _ in 0..3 {
+            air_quality.update(&reading(20.0, 40.0, 1000.0, 100_000.0));
+        }
+        // a lower resistance only lowers the baseline slowly
+        let index = air_quality
+            .update(&reading(20.0, 40.0, 1000.0, 50_000.0))
+            .unwrap();
+        assert_close(air_quality.baseline().unwrap(), 99_950.0, 0.5);
+        // 500 * 0.75 * (1 - 50000 / 99950)
+        assert_close(index, 187.4, 0.1);
+        assert_eq!(
+            air_quality.level(),
+            Some(AirQualityLevel::ModeratelyPolluted)
+        );
+        // a higher resistance raises it immediately
+        let index = air_quality
+            .update(&reading(20.0, 40.0, 1000.0, 150_000.0))
+            .unwrap();
+        assert_eq!(index, 0.0);
+        assert_eq!(air_quality.baseline(), Some(150_000.0));
+        // after a long time in polluted air, the baseline has drifted towards it
+        for _ in 0..5000 {
+            air_quality.update(&reading(20.0, 40.0, 1000.0, 75_000.0));
+        }
+        assert!(air_quality.baseline().unwrap() < 76_000.0);
+        assert!(air_quality.index().unwrap() < 5.0);
+    }
+
+    #[test]
+    fn air_quality_humidity() {
+        let mut air_quality = air_quality();
+        for _ in 0..3 {
+            air_quality.update(&reading(20.0, 40.0, 1000.0, 100_000.0));
+        }
+        // half of the humidity score, which is weighted with 25 %
+        let index = air_quality.update(&reading(20.0, 70.0, 1000.0, 100_000.0));
+        assert_close(index.unwrap(), 62.5, 0.01);
+        let index = air_quality.update(&reading(20.0, 20.0, 1000.0, 100_000.0));
+        assert_close(index.unwrap(), 62.5, 0.01);
+        assert_eq!(air_quality.level(), Some(AirQualityLevel::Good));
+        let index = air_quality.update(&reading(20.0, 100.0, 1000.0, 100_000.0));
+        assert_close(index.unwrap(), 125.0, 0.01);
+    }
+
+    #[test]
+    fn air_quality_levels() {
+        use AirQualityLevel::*;
+        for &(index, level) in &[
+            (0.0, Excellent),
+            (50.0, Excellent),
+            (50.5, Good),
+            (100.0, Good),
+            (150.0, LightlyPolluted),
+            (200.0, ModeratelyPolluted),
+            (250.0, HeavilyPolluted),
+            (350.0, SeverelyPolluted),
+            (350.5, ExtremelyPolluted),
+            (500.0, ExtremelyPolluted),
+        ] {
+            assert_eq!(AirQualityLevel::from_index(index), level, "{}", index);
+        }
+    }
+}
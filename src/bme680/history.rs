@@ -0,0 +1,708 @@
+//! History of the BME680 readings with statistics, trends and persistence.
+//!
+//! The `History` is a ring buffer of `CAPACITY` entries, one for each interval, e.g. 24 hours at
+//! one-minute resolution. Readings within the same interval are averaged and intervals without
+//! readings are marked as gaps, whose values are NaN. The pressure trend of the last three
+//! hours gives a simple weather hint.
+//!
+//! The series of a field can be drawn with a `chart::Chart`, and the history can be saved to a
+//! file so it survives restarts of the l0dable.
+//!
+//! A history needs 23 KiB of memory, which is too much for the stack. `History::new()` is a
+//! `const fn`, so the history can be kept in a `static`, and `load_into()` loads a file into
+//! it in place.
+//!
+//! # Example
+//! ```
+//! use rustcardium::bme680::history::{Field, History};
+//! use rustcardium::bme680::Bme680;
+//! use rustcardium::chart::Chart;
+//! use rustcardium::framebuffer::Framebuffer;
+//! use rustcardium::time::SystemTime;
+//! use rustcardium::Color;
+//!
+//! static mut HISTORY: History = History::new(60);
+//!
+//! // the l0dable is single-threaded, so this is the only reference to the history
+//! let history = unsafe { &mut *core::ptr::addr_of_mut!(HISTORY) };
+//! let bme680 = Bme680::init()?;
+//! if history.load_into("/climate.his").is_err() {
+//!     // start with an empty history
+//! }
+//! history.update(&bme680.read()?, SystemTime::now());
+//!
+//! if let Some(hint) = history.weather_hint() {
+//!     // show the hint
+//! }
+//! let mut fb = Framebuffer::new();
+//! let chart = Chart::new(0, 20, 160, 60, Color { r: 0, g: 128, b: 255 });
+//! chart.draw(&mut fb, history.series(Field::Temperature))?;
+//! history.save("/climate.his")?;
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::fs;
+use crate::io::{Read, Write};
+use crate::time::{Duration, SystemTime};
+
+/// Number of entries of a `History`, 24 hours at one-minute resolution.
+pub const CAPACITY: usize = 1440;
+
+const MAGIC: &[u8; 4] = b"HIS1";
+const HEADER_SIZE: usize = 20;
+const ENTRY_SIZE: usize = 16;
+
+/// Number of entries which are read or written at once.
+const CHUNK_LEN: usize = 16;
+
+/// Entry of an interval without readings.
+const GAP: Reading = Reading {
+    temperature: f32::NAN,
+    humidity: f32::NAN,
+    pressure: f32::NAN,
+    gas_resistance: f32::NAN,
+};
+
+/// A field of the readings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Temperature,
+    Humidity,
+    Pressure,
+    GasResistance,
+}
+
+impl Field {
+    /// The value of this field in a reading.
+    pub fn of(self, reading: &Reading) -> f32 {
+        match self {
+            Field::Temperature => reading.temperature,
+            Field::Humidity => reading.humidity,
+            Field::Pressure => reading.pressure,
+            Field::GasResistance => reading.gas_resistance,
+        }
+    }
+}
+
+/// Statistics of a field, gaps are ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+    /// Number of entries with a value
+    pub count: usize,
+}
+
+/// Tendency of the pressure, with the thresholds used in weather reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trend {
+    /// Falls more than 3.5 hPa in 3 hours
+    FallingFast,
+    /// Falls 1.6 to 3.5 hPa in 3 hours
+    Falling,
+    /// Changes less than 1.6 hPa in 3 hours
+    Steady,
+    /// Rises 1.6 to 3.5 hPa in 3 hours
+    Rising,
+    /// Rises more than 3.5 hPa in 3 hours
+    RisingFast,
+}
+
+impl Trend {
+    /// Classifies a change of the pressure.
+    ///
+    /// # Arguments
+    /// * `change` - Change of the pressure in hPa within 3 hours
+    pub fn from_pressure_change(change: f32) -> Trend {
+        if change < -3.5 {
+            Trend::FallingFast
+        } else if change <= -1.6 {
+            Trend::Falling
+        } else if change < 1.6 {
+            Trend::Steady
+        } else if change <= 3.5 {
+            Trend::Rising
+        } else {
+            Trend::RisingFast
+        }
+    }
+
+    /// The weather which a pressure trend usually indicates.
+    pub fn weather_hint(self) -> WeatherHint {
+        match self {
+            Trend::FallingFast => WeatherHint::Storm,
+            Trend::Falling => WeatherHint::Worsening,
+            Trend::Steady => WeatherHint::Unchanged,
+            Trend::Rising | Trend::RisingFast => WeatherHint::Improving,
+        }
+    }
+}
+
+/// Rough forecast from the pressure trend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeatherHint {
+    Storm,
+    Worsening,
+    Unchanged,
+    Improving,
+}
+
+impl WeatherHint {
+    /// Short description for the display.
+    pub fn name(self) -> &'static str {
+        match self {
+            WeatherHint::Storm => "Storm",
+            WeatherHint::Worsening => "Worsening",
+            WeatherHint::Unchanged => "Unchanged",
+            WeatherHint::Improving => "Improving",
+        }
+    }
+}
+
+/// Ring buffer of the readings of the last `CAPACITY` intervals.
+///
+/// The history needs 23 KiB of memory, keep it in a `static` instead of on the stack.
+pub struct History {
+    /// Length of an interval in seconds
+    interval: u32,
+    entries: [Reading; CAPACITY],
+    /// Index of the oldest entry
+    head: usize,
+    len: usize,
+    /// Number of the interval of the newest entry, counted since the Unix epoch
+    last_slot: Option<u32>,
+    /// Number of readings averaged in the newest entry
+    count: u32,
+}
+
+impl History {
+    /// Creates an empty history, e.g. to initialize a `static`.
+    ///
+    /// # Arguments
+    /// * `interval` - Length of an interval in seconds, at least one second
+    pub const fn new(interval: u32) -> History {
+        History {
+            interval: if interval == 0 { 1 } else { interval },
+            entries: [GAP; CAPACITY],
+            head: 0,
+            len: 0,
+            last_slot: None,
+            count: 0,
+        }
+    }
+
+    /// Length of an interval.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(u64::from(self.interval))
+    }
+
+    /// Number of entries, including gaps.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if there are no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Removes all entries.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+        self.last_slot = None;
+        self.count = 0;
+    }
+
+    /// Adds a reading.
+    ///
+    /// The reading is averaged with the other readings of its interval. If the time is before
+    /// the newest entry, e.g. because the clock was set back, the history is cleared.
+    ///
+    /// # Arguments
+    /// * `reading` - The reading
+    /// * `time` - Time of the reading, usually `SystemTime::now()`
+    pub fn update(&mut self, reading: &Reading, time: SystemTime) {
+        let slot = (time.unix_seconds() / u64::from(self.interval)) as u32;
+        match self.last_slot {
+            Some(last) if slot == last => {
+                self.count += 1;
+                let n = self.count as f32;
+                let newest = &mut self.entries[(self.head + self.len - 1) % CAPACITY];
+                newest.temperature += (reading.temperature - newest.temperature) / n;
+                newest.humidity += (reading.humidity - newest.humidity) / n;
+                newest.pressure += (reading.pressure - newest.pressure) / n;
+                newest.gas_resistance += (reading.gas_resistance - newest.gas_resistance) / n;
+                return;
+            }
+            Some(last) if slot > last => {
+                let gaps = ((slot - last - 1) as usize).min(CAPACITY);
+                for _ in 0..gaps {
+                    self.push(GAP);
+                }
+            }
+            Some(_) => self.clear(),
+            None => {}
+        }
+        self.push(*reading);
+        self.last_slot = Some(slot);
+        self.count = 1;
+    }
+
+    fn push(&mut self, entry: Reading) {
+        if self.len < CAPACITY {
+            self.entries[(self.head + self.len) % CAPACITY] = entry;
+            self.len += 1;
+        } else {
+            self.entries[self.head] = entry;
+            self.head = (self.head + 1) % CAPACITY;
+        }
+    }
+
+    /// The newest entry, None if the history is empty.
+    pub fn latest(&self) -> Option<&Reading> {
+        self.iter().last()
+    }
+
+    /// Start of the interval of the oldest entry.
+    pub fn start_time(&self) -> Option<SystemTime> {
+        self.last_slot.map(|last| {
+            let slot = (u64::from(last) + 1).saturating_sub(self.len as u64);
+            SystemTime::from_unix_seconds((slot * u64::from(self.interval)) as u32)
+        })
+    }
+
+    /// Iterates over the entries from the oldest to the newest, gaps are NaN.
+    pub fn iter(&self) -> impl Iterator<Item = &Reading> + Clone {
+        let (newer, older) = self.entries.split_at(self.head);
+        older.iter().chain(newer.iter()).take(self.len)
+    }
+
+    /// Iterates over the values of a field from the oldest to the newest, e.g. to draw them
+    /// with a `chart::Chart`. Gaps are NaN.
+    pub fn series(&self, field: Field) -> impl Iterator<Item = f32> + Clone + '_ {
+        self.iter().map(move |reading| field.of(reading))
+    }
+
+    /// The values of a field within a time span before the newest entry.
+    fn recent(&self, field: Field, window: Option<Duration>) -> impl Iterator<Item = f32> + '_ {
+        let entries = window
+            .map(|window| ((window.as_secs() / u64::from(self.interval)) as usize).max(1))
+            .unwrap_or(self.len)
+            .min(self.len);
+        self.series(field).skip(self.len - entries)
+    }
+
+    /// Minimum, maximum and average of a field.
+    ///
+    /// # Arguments
+    /// * `field` - The field
+    /// * `window` - Time span before the newest entry, or None for the whole history
+    ///
+    /// # Returns
+    /// None if there are no values within the window.
+    pub fn stats(&self, field: Field, window: Option<Duration>) -> Option<Stats> {
+        let mut stats = Stats {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+            avg: 0.0,
+            count: 0,
+        };
+        for value in self.recent(field, window).filter(|v| !v.is_nan()) {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.count += 1;
+            stats.avg += (value - stats.avg) / stats.count as f32;
+        }
+        if stats.count == 0 {
+            None
+        } else {
+            Some(stats)
+        }
+    }
+
+    /// Change of a field per hour, the slope of a linear regression.
+    ///
+    /// # Arguments
+    /// * `field` - The field
+    /// * `window` - Time span before the newest entry
+    ///
+    /// # Returns
+    /// None if there are less than two values within the window.
+    pub fn trend(&self, field: Field, window: Duration) -> Option<f32> {
+        let hours_per_entry = self.interval as f32 / 3600.0;
+        let (mut n, mut sum_x, mut sum_y, mut sum_xx, mut sum_xy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, value) in self.recent(field, Some(window)).enumerate() {
+            if value.is_nan() {
+                continue;
+            }
+            let x = i as f32 * hours_per_entry;
+            n += 1.0;
+            sum_x += x;
+            sum_y += value;
+            sum_xx += x * x;
+            sum_xy += x * value;
+        }
+        let denominator = n * sum_xx - sum_x * sum_x;
+        if n < 2.0 || denominator == 0.0 {
+            return None;
+        }
+        Some((n * sum_xy - sum_x * sum_y) / denominator)
+    }
+
+    /// Tendency of the pressure in the last three hours.
+    pub fn pressure_trend(&self) -> Option<Trend> {
+        self.trend(Field::Pressure, Duration::from_secs(3 * 3600))
+            .map(|per_hour| Trend::from_pressure_change(per_hour * 3.0))
+    }
+
+    /// Rough forecast from the pressure trend of the last three hours.
+    pub fn weather_hint(&self) -> Option<WeatherHint> {
+        self.pressure_trend().map(Trend::weather_hint)
+    }
+
+    /// Stores the history in a file, which is created or replaced.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the file
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut file = fs::File::create(path)?;
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&self.interval.to_le_bytes());
+        header[8..12].copy_from_slice(&self.last_slot.unwrap_or(0).to_le_bytes());
+        header[12..16].copy_from_slice(&self.count.to_le_bytes());
+        header[16..].copy_from_slice(&(self.len as u32).to_le_bytes());
+        file.write_all(&header)?;
+
+        let mut chunk = [0; CHUNK_LEN * ENTRY_SIZE];
+        let mut len = 0;
+        for reading in self.iter() {
+            let values = [
+                reading.temperature,
+                reading.humidity,
+                reading.pressure,
+                reading.gas_resistance,
+            ];
+            for value in values.iter() {
+                chunk[len..len + 4].copy_from_slice(&value.to_le_bytes());
+                len += 4;
+            }
+            if len == chunk.len() {
+                file.write_all(&chunk)?;
+                len = 0;
+            }
+        }
+        file.write_all(&chunk[..len])?;
+        file.close()
+    }
+
+    /// Replaces the entries and the interval with a history stored with `save()`.
+    ///
+    /// The file is read in place, so no second history is needed on the stack. The history is
+    /// unchanged if the file has no valid header, and cleared if reading the entries fails,
+    /// e.g. because the file is truncated.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the file
+    ///
+    /// # Returns
+    /// `Error::FileNotInLoadableFormat` if the file is no history or is truncated.
+    pub fn load_into(&mut self, path: &str) -> Result<()> {
+        let mut file = fs::File::open(path)?;
+        let eof_to_format = |e| match e {
+            Error::UnexpectedEof => Error::FileNotInLoadableFormat,
+            e => e,
+        };
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header).map_err(eof_to_format)?;
+        let word =
+            |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+        let (interval, last_slot, count, len) = (word(4), word(8), word(12), word(16) as usize);
+        if &header[..4] != MAGIC || interval == 0 || len > CAPACITY {
+            return Err(Error::FileNotInLoadableFormat);
+        }
+        self.clear();
+        self.interval = interval;
+        if let Err(e) = self.read_entries(&mut file, len) {
+            self.clear();
+            return Err(eof_to_format(e));
+        }
+        file.close()?;
+        if len > 0 {
+            self.last_slot = Some(last_slot);
+            self.count = count;
+        }
+        Ok(())
+    }
+
+    fn read_entries(&mut self, file: &mut fs::File, len: usize) -> Result<()> {
+        let mut chunk = [0; CHUNK_LEN * ENTRY_SIZE];
+        let mut remaining = len;
+        while remaining > 0 {
+            let entries = remaining.min(CHUNK_LEN);
+            let bytes = &mut chunk[..entries * ENTRY_SIZE];
+            file.read_exact(bytes)?;
+            for entry in bytes.chunks(ENTRY_SIZE) {
+                let value = |i: usize| {
+                    f32::from_le_bytes([entry[i], entry[i + 1], entry[i + 2], entry[i + 3]])
+                };
+                self.push(Reading {
+                    temperature: value(0),
+                    humidity: value(4),
+                    pressure: value(8),
+                    gas_resistance: value(12),
+                });
+            }
+            remaining -= entries;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    fn reading(temperature: f32, pressure: f32) -> Reading {
+        Reading {
+            temperature,
+            humidity: 40.0,
+            pressure,
+            gas_resistance: 100_000.0,
+        }
+    }
+
+    fn at(seconds: u32) -> SystemTime {
+        SystemTime::from_unix_seconds(seconds)
+    }
+
+    fn temperatures(history: &History) -> [f32; 8] {
+        let mut values = [f32::INFINITY; 8];
+        for (value, t) in values.iter_mut().zip(history.series(Field::Temperature)) {
+            *value = t;
+        }
+        values
+    }
+
+    fn same(a: &Reading, b: &Reading) -> bool {
+        let bits = |r: &Reading| {
+            [
+                r.temperature.to_bits(),
+                r.humidity.to_bits(),
+                r.pressure.to_bits(),
+                r.gas_resistance.to_bits(),
+            ]
+        };
+        bits(a) == bits(b)
+    }
+
+    #[test]
+    fn readings_of_an_interval_are_averaged() {
+        let mut history = History::new(60);
+        history.update(&reading(20.0, 1000.0), at(600));
+        history.update(&reading(22.0, 1002.0), at(630));
+        history.update(&reading(24.0, 1004.0), at(659));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.latest(), Some(&reading(22.0, 1002.0)));
+        history.update(&reading(25.0, 1005.0), at(660));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.latest(), Some(&reading(25.0, 1005.0)));
+        assert_eq!(history.start_time(), Some(at(600)));
+    }
+
+    #[test]
+    fn missing_intervals_are_gaps() {
+        let mut history = History::new(60);
+        history.update(&reading(20.0, 1000.0), at(0));
+        history.update(&reading(21.0, 1000.0), at(185));
+        assert_eq!(history.len(), 4);
+        let t = temperatures(&history);
+        assert_eq!(t[0], 20.0);
+        assert!(t[1].is_nan() && t[2].is_nan());
+        assert_eq!(t[3], 21.0);
+        assert_eq!(history.start_time(), Some(at(0)));
+
+        // a gap longer than the history leaves only the new reading and gaps
+        history.update(&reading(22.0, 1000.0), at(185 + 2 * 60 * CAPACITY as u32));
+        assert_eq!(history.len(), CAPACITY);
+        assert_eq!(
+            history
+                .series(Field::Temperature)
+                .filter(|t| !t.is_nan())
+                .count(),
+            1
+        );
+        assert_eq!(history.latest().unwrap().temperature, 22.0);
+    }
+
+    #[test]
+    fn oldest_entries_are_overwritten() {
+        let mut history = History::new(1);
+        for i in 0..CAPACITY as u32 + 3 {
+            history.update(&reading(i as f32, 1000.0), at(i));
+        }
+        assert_eq!(history.len(), CAPACITY);
+        assert_eq!(history.iter().next().unwrap().temperature, 3.0);
+        assert_eq!(history.start_time(), Some(at(3)));
+        assert_eq!(history.latest().unwrap().temperature, CAPACITY as f32 + 2.0);
+    }
+
+    #[test]
+    fn clock_set_back_clears_the_history() {
+        let mut history = History::new(60);
+        history.update(&reading(20.0, 1000.0), at(600));
+        history.update(&reading(21.0, 1000.0), at(660));
+        history.update(&reading(22.0, 1000.0), at(60));
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.start_time(), Some(at(60)));
+        assert_eq!(history.latest().unwrap().temperature, 22.0);
+    }
+
+    #[test]
+    fn stats_of_a_window() {
+        let mut history = History::new(60);
+        for i in 0..10 {
+            history.update(&reading(i as f32 + 1.0, 1000.0), at(i * 60));
+        }
+        // a gap, which is ignored
+        history.update(&reading(8.0, 1000.0), at(11 * 60));
+        let all = history.stats(Field::Temperature, None).unwrap();
+        assert_eq!((all.min, all.max, all.count), (1.0, 10.0, 11));
+        assert!((all.avg - 63.0 / 11.0).abs() < 1e-4);
+        let recent = history
+            .stats(Field::Temperature, Some(Duration::from_secs(4 * 60)))
+            .unwrap();
+        assert_eq!((recent.min, recent.max, recent.count), (8.0, 10.0, 3));
+        assert!((recent.avg - 9.0).abs() < 1e-4);
+        assert_eq!(History::new(60).stats(Field::Temperature, None), None);
+    }
+
+    #[test]
+    fn trend_of_a_window() {
+        let mut history = History::new(60);
+        // steady for two hours, then rising 1.5 hPa per hour
+        for minute in 0..180 {
+            let pressure = 1000.0 + (minute as f32 - 120.0).max(0.0) * 0.025;
+            history.update(&reading(20.0, pressure), at(minute * 60));
+        }
+        let per_hour = history
+            .trend(Field::Pressure, Duration::from_secs(3600))
+            .unwrap();
+        assert!((per_hour - 1.5).abs() < 0.01, "{}", per_hour);
+        assert_eq!(
+            history.trend(Field::Temperature, Duration::from_secs(3600)),
+            Some(0.0)
+        );
+        assert_eq!(
+            history.trend(Field::Pressure, Duration::from_secs(60)),
+            None
+        );
+        assert_eq!(history.pressure_trend(), Some(Trend::Steady));
+
+        for minute in 180..240 {
+            let pressure = 1001.5 + (minute as f32 - 180.0) * 0.025;
+            history.update(&reading(20.0, pressure), at(minute * 60));
+        }
+        // 1.5 hPa per hour over the last two hours and none in the hour before
+        assert_eq!(history.pressure_trend(), Some(Trend::Rising));
+        assert_eq!(history.weather_hint(), Some(WeatherHint::Improving));
+    }
+
+    #[test]
+    fn trend_thresholds() {
+        let cases = [
+            (-3.55, Trend::FallingFast),
+            (-3.5, Trend::Falling),
+            (-1.6, Trend::Falling),
+            (-1.55, Trend::Steady),
+            (1.55, Trend::Steady),
+            (1.6, Trend::Rising),
+            (3.5, Trend::Rising),
+            (3.55, Trend::RisingFast),
+        ];
+        for &(change, trend) in &cases {
+            assert_eq!(Trend::from_pressure_change(change), trend, "{}", change);
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        mock::reset();
+        let mut history = History::new(60);
+        history.update(&reading(20.0, 1000.0), at(6000));
+        history.update(&reading(21.0, 1001.0), at(6120));
+        history.update(&reading(23.0, 1003.0), at(6150));
+        history.save("/climate.his").unwrap();
+
+        let data = mock::file("/climate.his").unwrap();
+        assert_eq!(data.len(), HEADER_SIZE + 3 * ENTRY_SIZE);
+        assert_eq!(&data[..4], b"HIS1");
+        assert_eq!(data[4..8], 60u32.to_le_bytes());
+        assert_eq!(data[8..12], 102u32.to_le_bytes());
+        assert_eq!(data[12..16], 2u32.to_le_bytes());
+        assert_eq!(data[16..20], 3u32.to_le_bytes());
+        assert_eq!(data[20..24], 20.0f32.to_le_bytes());
+        assert_eq!(data[36..40], f32::NAN.to_le_bytes());
+
+        let mut loaded = History::new(1);
+        loaded.update(&reading(30.0, 990.0), at(0));
+        loaded.load_into("/climate.his").unwrap();
+        assert_eq!(loaded.interval(), Duration::from_secs(60));
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded.start_time(), Some(at(6000)));
+        assert!(loaded.iter().zip(history.iter()).all(|(a, b)| same(a, b)));
+
+        // the newest interval continues with its two readings
+        loaded.update(&reading(25.0, 1005.0), at(6170));
+        assert_eq!(loaded.latest(), Some(&reading(23.0, 1003.0)));
+    }
+
+    #[test]
+    fn invalid_files() {
+        mock::reset();
+        let mut history = History::new(60);
+        history.update(&reading(20.0, 1000.0), at(0));
+        history.update(&reading(21.0, 1000.0), at(60));
+        history.save("/climate.his").unwrap();
+        let data = mock::file("/climate.his").unwrap();
+
+        let mut other = History::new(60);
+        other.update(&reading(30.0, 1000.0), at(0));
+        assert_eq!(other.load_into("/missing.his"), Err(Error::FileNotFound));
+        assert_eq!(other.len(), 1);
+
+        // a wrong magic or a short header leave the history unchanged
+        let mut bad_magic = data.clone();
+        bad_magic[3] = b'2';
+        mock::add_file("/bad.his", &bad_magic);
+        assert_eq!(
+            other.load_into("/bad.his"),
+            Err(Error::FileNotInLoadableFormat)
+        );
+        mock::add_file("/short.his", &data[..HEADER_SIZE - 1]);
+        assert_eq!(
+            other.load_into("/short.his"),
+            Err(Error::FileNotInLoadableFormat)
+        );
+        let mut too_long = data.clone();
+        too_long[16..20].copy_from_slice(&(CAPACITY as u32 + 1).to_le_bytes());
+        mock::add_file("/long.his", &too_long);
+        assert_eq!(
+            other.load_into("/long.his"),
+            Err(Error::FileNotInLoadableFormat)
+        );
+        assert_eq!(other.latest(), Some(&reading(30.0, 1000.0)));
+
+        // missing entries clear the history
+        mock::add_file("/truncated.his", &data[..data.len() - 1]);
+        assert_eq!(
+            other.load_into("/truncated.his"),
+            Err(Error::FileNotInLoadableFormat)
+        );
+        assert!(other.is_empty());
+        assert_eq!(other.start_time(), None);
+    }
+}
@@ -3,7 +3,13 @@
 /// TODO
 ///
 ///
+use crate::executor;
 use crate::sys;
+use crate::time::{Duration, Instant};
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 
 ///  Bottom left button (bit 0).
 pub const LEFT_BOTTOM: u8 = 1;
@@ -17,6 +23,13 @@ pub const RIGHT_TOP: u8 = 4;
 /// Top left and reset button (bit 3).
 pub const RESET: u8 = 8;
 
+/// Interval in which `wait_press()` reads the buttons, since there is no interrupt for them.
+///
+/// The RTC alarm which wakes the executor only has a resolution of one second, so the core
+/// busy-polls while waiting with this interval. See `wait_press_every()` for intervals which
+/// let the core sleep.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
 /// Read button status.
 ///
 /// `epic_buttons_read()` will read all buttons specified in mask and return
@@ -51,3 +64,78 @@ pub const RESET: u8 = 8;
 pub fn read(mask: u8) -> u8 {
     unsafe { sys::epic_buttons_read(mask) }
 }
+
+/// Future which completes when a button is pressed, created with `wait_press()`.
+pub struct WaitPress {
+    mask: u8,
+    previous: u8,
+    interval: Duration,
+}
+
+impl Future for WaitPress {
+    type Output = u8;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<u8> {
+        let current = read(self.mask);
+        let pressed = current & !self.previous;
+        self.previous = current;
+        if pressed != 0 {
+            Poll::Ready(pressed)
+        } else {
+            // There is no interrupt for the buttons, read them again after the poll interval
+            executor::register_timer(Instant::now() + self.interval);
+            Poll::Pending
+        }
+    }
+}
+
+/// Waits asynchronously until one of the buttons is pressed, see `executor`.
+///
+/// Buttons which are already pressed when calling this function need to be released and
+/// pressed again. Since there is no interrupt for the buttons, they are read every
+/// `POLL_INTERVAL` while waiting, shorter presses may be missed. The core busy-polls and doesn't
+/// sleep while waiting, use `wait_press_every()` with an interval of at least one second to
+/// save power.
+///
+/// # Arguments
+/// * `mask` - Mask of buttons to wait for, e.g. `LEFT_BOTTOM | RIGHT_BOTTOM`
+///
+/// # Returns
+/// The buttons which were pressed.
+///
+/// # Example
+/// ```
+/// use rustcardium::buttons;
+/// use rustcardium::executor::block_on;
+///
+/// block_on(async {
+///     let wait = buttons::wait_press(buttons::LEFT_BOTTOM | buttons::RIGHT_BOTTOM);
+/// #   rustcardium::mock::set_buttons(buttons::RIGHT_BOTTOM);
+///     if wait.await & buttons::RIGHT_BOTTOM != 0 {
+///         // next page
+///     }
+/// });
+/// ```
+pub fn wait_press(mask: u8) -> WaitPress {
+    wait_press_every(mask, POLL_INTERVAL)
+}
+
+/// Waits asynchronously until one of the buttons is pressed, reading them in the given interval.
+///
+/// With an interval of at least one second, the executor sleeps between the reads until the RTC
+/// alarm wakes it, but the button has to be held until the next read. Shorter intervals
+/// busy-poll like `wait_press()`.
+///
+/// # Arguments
+/// * `mask` - Mask of buttons to wait for, e.g. `LEFT_BOTTOM | RIGHT_BOTTOM`
+/// * `interval` - Time between two reads of the buttons
+///
+/// # Returns
+/// The buttons which were pressed.
+pub fn wait_press_every(mask: u8, interval: Duration) -> WaitPress {
+    WaitPress {
+        mask,
+        previous: read(mask),
+        interval,
+    }
+}
@@ -0,0 +1,305 @@
+//! Line charts of data series on the display or a framebuffer.
+//!
+//! A `Chart` scales a series of values to its area and draws it on any `Canvas`, i.e. the
+//! `Display` or a `Framebuffer`. When the series has more values than the chart is wide, each
+//! column shows the range of its values. Values which are NaN leave a gap.
+//!
+//! # Example
+//! ```
+//! use rustcardium::chart::Chart;
+//! use rustcardium::framebuffer::Framebuffer;
+//! use rustcardium::Color;
+//!
+//! let mut fb = Framebuffer::new();
+//! let mut chart = Chart::new(0, 20, 160, 60, Color { r: 0, g: 255, b: 0 });
+//! chart.background = Some(Color { r: 0, g: 0, b: 0 });
+//! let temperatures = [20.5, 20.7, 21.0, f32::NAN, 21.4, 21.2];
+//! chart.draw(&mut fb, temperatures.iter().copied())?;
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::display::Display;
+use crate::framebuffer::{self, Framebuffer};
+
+/// Something to draw on, implemented for the `Display` and the `Framebuffer`.
+pub trait Canvas {
+    /// Sets the color of a pixel.
+    fn set_pixel(&mut self, x: u16, y: u16, col: Color) -> Result<()>;
+
+    /// Fills a rectangle, both corners are included.
+    ///
+    /// # Arguments
+    /// * `xs` - X start coordinate
+    /// * `ys` - Y start coordinate
+    /// * `xe` - X end coordinate, xs <= xe
+    /// * `ye` - Y end coordinate, ys <= ye
+    /// * `col` - color of the rectangle
+    fn fill_rect(&mut self, xs: u16, ys: u16, xe: u16, ye: u16, col: Color) -> Result<()> {
+        for y in ys..=ye {
+            for x in xs..=xe {
+                self.set_pixel(x, y, col)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Canvas for Display {
+    fn set_pixel(&mut self, x: u16, y: u16, col: Color) -> Result<()> {
+        self.pixel(x, y, col)
+    }
+
+    fn fill_rect(&mut self, xs: u16, ys: u16, xe: u16, ye: u16, col: Color) -> Result<()> {
+        // the end coordinates of filled rectangles are exclusive in the firmware
+        self.rect(
+            xs,
+            ys,
+            xe.saturating_add(1),
+            ye.saturating_add(1),
+            col,
+            true,
+            1,
+        )
+    }
+}
+
+impl Canvas for Framebuffer {
+    fn set_pixel(&mut self, x: u16, y: u16, col: Color) -> Result<()> {
+        Framebuffer::set_pixel(self, x, y, col)
+    }
+
+    fn fill_rect(&mut self, xs: u16, ys: u16, xe: u16, ye: u16, col: Color) -> Result<()> {
+        if xe >= framebuffer::WIDTH || ye >= framebuffer::HEIGHT {
+            return Err(Error::OutsideDisplay);
+        }
+        let raw = col.rgb565();
+        for y in ys..=ye {
+            for x in xs..=xe {
+                self.set_raw_pixel(x, y, raw);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line chart in a rectangular area.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chart {
+    /// X coordinate of the left edge
+    pub x: u16,
+    /// Y coordinate of the top edge
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Color of the line
+    pub color: Color,
+    /// Color the area is cleared with before drawing, the area isn't cleared if None
+    pub background: Option<Color>,
+    /// Values at the bottom and the top edge as `(min, max)`, the range of the series if None
+    pub range: Option<(f32, f32)>,
+}
+
+impl Chart {
+    /// Creates a chart which is scaled to the range of the series and doesn't clear its area.
+    ///
+    /// # Arguments
+    /// * `x` - X coordinate of the left edge
+    /// * `y` - Y coordinate of the top edge
+    /// * `width` - Width in pixels
+    /// * `height` - Height in pixels
+    /// * `color` - Color of the line
+    pub fn new(x: u16, y: u16, width: u16, height: u16, color: Color) -> Chart {
+        Chart {
+            x,
+            y,
+            width,
+            height,
+            color,
+            background: None,
+            range: None,
+        }
+    }
+
+    /// The range of the chart for a series, as `(min, max)`.
+    ///
+    /// # Returns
+    /// `range` if it is set, otherwise the range of the values which aren't NaN, or None if
+    /// there are no such values.
+    pub fn range_of<I>(&self, values: I) -> Option<(f32, f32)>
+    where
+        I: IntoIterator<Item = f32>,
+    {
+        if self.range.is_some() {
+            return self.range;
+        }
+        let (min, max) = values
+            .into_iter()
+            .filter(|v| !v.is_nan())
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        if min > max {
+            None
+        } else if min == max {
+            // a constant series is drawn in the middle
+            Some((min - 1.0, max + 1.0))
+        } else {
+            Some((min, max))
+        }
+    }
+
+    /// Draws a series of values from left to right.
+    ///
+    /// # Arguments
+    /// * `canvas` - The display or framebuffer to draw on
+    /// * `values` - The series, NaN values leave a gap
+    pub fn draw<C, I>(&self, canvas: &mut C, values: I) -> Result<()>
+    where
+        C: Canvas,
+        I: Iterator<Item = f32> + Clone,
+    {
+        if self.width == 0 || self.height == 0 {
+            return Ok(());
+        }
+        if let Some(background) = self.background {
+            canvas.fill_rect(
+                self.x,
+                self.y,
+                self.x + self.width - 1,
+                self.y + self.height - 1,
+                background,
+            )?;
+        }
+        let (min, max) = match self.range_of(values.clone()) {
+            Some(range) => range,
+            None => return Ok(()),
+        };
+        let len = values.clone().count();
+        let width = usize::from(self.width);
+
+        let mut columns: Option<Columns> = None;
+        let mut previous: Option<f32> = None;
+        for (i, value) in values.enumerate() {
+            let start = i * width / len;
+            if let Some(c) = columns.as_mut() {
+                if c.start == start {
+                    c.add(value);
+                    continue;
+                }
+                if c.last.is_some() {
+                    self.draw_columns(canvas, c, previous, min, max)?;
+                }
+                previous = c.last;
+            }
+            let mut c = Columns {
+                start,
+                end: ((i + 1) * width / len).max(start + 1),
+                low: f32::INFINITY,
+                high: f32::NEG_INFINITY,
+                last: None,
+            };
+            c.add(value);
+            columns = Some(c);
+        }
+        if let Some(c) = columns.as_ref() {
+            if c.last.is_some() {
+                self.draw_columns(canvas, c, previous, min, max)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the range of values of some columns, connected to the previous value.
+    fn draw_columns<C: Canvas>(
+        &self,
+        canvas: &mut C,
+        columns: &Columns,
+        previous: Option<f32>,
+        min: f32,
+        max: f32,
+    ) -> Result<()> {
+        let (low, high) = match previous {
+            Some(previous) => (columns.low.min(previous), columns.high.max(previous)),
+            None => (columns.low, columns.high),
+        };
+        let y = |value: f32| {
+            let scaled = (value - min) / (max - min) * f32::from(self.height - 1);
+            let offset = libm::roundf(scaled.clamp(0.0, f32::from(self.height - 1))) as u16;
+            self.y + self.height - 1 - offset
+        };
+        canvas.fill_rect(
+            self.x + columns.start as u16,
+            y(high),
+            self.x + columns.end as u16 - 1,
+            y(low),
+            self.color,
+        )
+    }
+}
+
+/// Adjacent columns of a chart which show the same values.
+struct Columns {
+    start: usize,
+    end: usize,
+    low: f32,
+    high: f32,
+    /// The last value which isn't NaN
+    last: Option<f32>,
+}
+
+impl Columns {
+    fn add(&mut self, value: f32) {
+        if !value.is_nan() {
+            self.low = self.low.min(value);
+            self.high = self.high.max(value);
+            self.last = Some(value);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    const GREEN: Color = Color { r: 0, g: 255, b: 0 };
+
+    fn shown(x: usize, y: usize) -> u16 {
+        mock::with(|s| s.screen_pixel(x, y))
+    }
+
+    #[test]
+    fn display_fill_rect_includes_the_corners() {
+        mock::reset();
+        let mut display = Display::open().unwrap();
+        display.fill_rect(10, 20, 10, 20, GREEN).unwrap();
+        display.fill_rect(30, 5, 32, 6, GREEN).unwrap();
+        display.update().unwrap();
+        assert_eq!(shown(10, 20), GREEN.rgb565());
+        assert_eq!(shown(11, 20), 0);
+        assert_eq!(shown(10, 21), 0);
+        for &(x, y) in &[(30, 5), (32, 5), (30, 6), (32, 6)] {
+            assert_eq!(shown(x, y), GREEN.rgb565());
+        }
+        assert_eq!(shown(33, 6), 0);
+        assert_eq!(shown(32, 7), 0);
+    }
+
+    #[test]
+    fn chart_on_display() {
+        mock::reset();
+        let mut display = Display::open().unwrap();
+        let mut chart = Chart::new(0, 0, 4, 10, GREEN);
+        chart.range = Some((0.0, 9.0));
+        chart
+            .draw(&mut display, [0.0, 9.0, 9.0, 0.0].iter().copied())
+            .unwrap();
+        display.update().unwrap();
+        // every column is drawn, one pixel wide
+        assert_eq!(shown(0, 9), GREEN.rgb565());
+        assert_eq!(shown(1, 0), GREEN.rgb565());
+        assert_eq!(shown(2, 0), GREEN.rgb565());
+        assert_eq!(shown(3, 9), GREEN.rgb565());
+        assert_eq!(shown(4, 9), 0);
+    }
+}
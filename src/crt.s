@@ -259,6 +259,52 @@ DefaultHandler:
 		def_irq_handler    HTMR0_IRQHandler
 		def_irq_handler    HTMR1_IRQHandler
 
+		/*
+		 * Sleeps until the next interrupt if the word at r0 equals r1.
+		 *
+		 * Interrupts are masked while comparing, so an interrupt which
+		 * changes the word can't slip in between the check and the wfi.
+		 * A pending interrupt wakes the core even while it is masked and
+		 * is handled as soon as interrupts are unmasked again.
+		 */
+		.section .text.rustcardium_sleep_while_equal
+		.thumb_func
+		.globl rustcardium_sleep_while_equal
+		.type rustcardium_sleep_while_equal, %function
+rustcardium_sleep_while_equal:
+		cpsid   i
+		ldr     r2, [r0]
+		cmp     r2, r1
+		bne     1f
+		wfi
+1:
+		cpsie   i
+		bx      lr
+
+		/*
+		 * Masks all interrupts and returns the previous PRIMASK in r0, which
+		 * is restored with rustcardium_restore_interrupts.
+		 */
+		.section .text.rustcardium_mask_interrupts
+		.thumb_func
+		.globl rustcardium_mask_interrupts
+		.type rustcardium_mask_interrupts, %function
+rustcardium_mask_interrupts:
+		mrs     r0, primask
+		cpsid   i
+		bx      lr
+
+		/*
+		 * Sets PRIMASK to r0, as returned by rustcardium_mask_interrupts.
+		 */
+		.section .text.rustcardium_restore_interrupts
+		.thumb_func
+		.globl rustcardium_restore_interrupts
+		.type rustcardium_restore_interrupts, %function
+rustcardium_restore_interrupts:
+		msr     primask, r0
+		bx      lr
+
 		.section .cinterp
 		.asciz "card10-l0dable"
 		.byte
@@ -12,11 +12,11 @@ enum State {
 ///
 /// # Example
 /// ```
-/// if let Ok(display) = rustcardium::Display::open() {
+/// if let Ok(display) = rustcardium::display::Display::open() {
 ///     let color_black = rustcardium::Color {r: 0, g: 0, b: 0};
 ///     let color_white = rustcardium::Color {r: 255, g: 255, b: 255};
 ///
-///     display.print("Hello World", 0, 0, black, white).unwrap();
+///     display.print("Hello World", color_black, color_white, 0, 0).unwrap();
 /// }
 /// ```
 pub struct Display {
@@ -105,8 +105,34 @@ impl Display {
             }
             State::Opened => unsafe {
                 let text = create_nullterminated_str(text);
-                let result =
-                    sys::epic_disp_print(posx, posy, text.as_ptr(), fg.rgb565(), bg.rgb565());
+                let result = sys::epic_disp_print(
+                    posx,
+                    posy,
+                    text.as_ptr() as *const cty::c_char,
+                    fg.rgb565(),
+                    bg.rgb565(),
+                );
+                if result != 0 {
+                    return Err(Error::DeviceOrResourceBusy);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Immediately shows the content of a framebuffer on the display.
+    ///
+    /// This overrides anything drawn by the immediate mode functions, calling `update()` is not necessary.
+    ///
+    /// # Arguments
+    /// * `fb` - Framebuffer to display
+    pub fn show_framebuffer(&self, fb: &framebuffer::Framebuffer) -> Result<()> {
+        match self.state {
+            State::Closed => {
+                return Err(Error::DisplayClosed);
+            }
+            State::Opened => unsafe {
+                let result = sys::epic_disp_framebuffer(fb.as_raw());
                 if result != 0 {
                     return Err(Error::DeviceOrResourceBusy);
                 }
@@ -185,6 +211,10 @@ impl Display {
 
     /// Draws a rectangle on the display.
     ///
+    /// Like in the firmware, the end coordinates of a filled rectangle are exclusive, while the
+    /// outline of an empty rectangle includes them. To fill the pixels from `xs` to `xe`, pass
+    /// `xe + 1` and `ye + 1`.
+    ///
     /// # Arguments
     ///
     /// * `xs` - X start coordinate, 0<= x <= 160
@@ -218,7 +248,7 @@ impl Display {
                 } else {
                     sys::disp_fillstyle_FILLSTYLE_EMPTY
                 };
-                let result = sys::epic_disp_rect(xs, ys, ye, ye, col.rgb565(), fillstyle, size);
+                let result = sys::epic_disp_rect(xs, ys, xe, ye, col.rgb565(), fillstyle, size);
                 if result != 0 {
                     return Err(Error::DeviceOrResourceBusy);
                 }
@@ -268,6 +298,26 @@ impl Display {
         }
         Ok(())
     }
+
+    /// Sets the brightness of the backlight.
+    ///
+    /// # Arguments
+    ///
+    /// * `brightness` - brightness from 0 (off) to 100
+    pub fn backlight(&self, brightness: u16) -> Result<()> {
+        match self.state {
+            State::Closed => {
+                return Err(Error::DisplayClosed);
+            }
+            State::Opened => unsafe {
+                let result = sys::epic_disp_backlight(brightness.min(100));
+                if result != 0 {
+                    return Err(Error::DeviceOrResourceBusy);
+                }
+            },
+        }
+        Ok(())
+    }
 }
 
 impl Drop for Display {
@@ -0,0 +1,162 @@
+//! A single stream of input events from the buttons and other sources like gestures.
+//!
+//! The `EventStream` detects presses and releases of the buttons by polling them. Events of
+//! other sources, e.g. the gestures of a `gesture::GestureDetector` or the changes reported by a
+//! `power::Monitor`, are added with `push()`.
+//!
+//! # Example
+//! ```
+//! use rustcardium::buttons;
+//! use rustcardium::events::{Event, EventStream};
+//! use rustcardium::gesture::Gesture;
+//!
+//! let mut events = EventStream::new(buttons::LEFT_BOTTOM | buttons::RIGHT_BOTTOM);
+//! # rustcardium::mock::set_buttons(buttons::RIGHT_BOTTOM);
+//! events.push(Event::Gesture(Gesture::Shake));
+//! while let Some(event) = events.poll() {
+//!     match event {
+//!         Event::ButtonsPressed(pressed) if pressed & buttons::RIGHT_BOTTOM != 0 => {
+//!             // next page
+//!         }
+//!         Event::Gesture(Gesture::Shake) => {
+//!             // undo
+//!         }
+//!         _ => {}
+//!     }
+//! }
+//! ```
+use crate::buttons;
+use crate::gesture::Gesture;
+use crate::power::PowerEvent;
+use crate::time;
+
+/// Number of events which can be queued in an `EventStream`.
+pub const QUEUE_LEN: usize = 16;
+
+/// An input event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Mask of the buttons which were pressed, see the constants in `buttons`
+    ButtonsPressed(u8),
+    /// Mask of the buttons which were released, see the constants in `buttons`
+    ButtonsReleased(u8),
+    Gesture(Gesture),
+    Power(PowerEvent),
+}
+
+impl From<Gesture> for Event {
+    fn from(gesture: Gesture) -> Event {
+        Event::Gesture(gesture)
+    }
+}
+
+impl From<PowerEvent> for Event {
+    fn from(event: PowerEvent) -> Event {
+        Event::Power(event)
+    }
+}
+
+/// Queue of events from the buttons and other sources.
+pub struct EventStream {
+    queue: [Option<Event>; QUEUE_LEN],
+    head: usize,
+    len: usize,
+    button_mask: u8,
+    buttons: u8,
+    poll_interval: time::Duration,
+}
+
+impl EventStream {
+    /// Creates an event stream for the given buttons.
+    ///
+    /// Buttons which are already pressed only generate an event when they are released.
+    ///
+    /// # Arguments
+    /// * `button_mask` - Mask of the buttons to generate events for, e.g. `LEFT_BOTTOM | RIGHT_BOTTOM`
+    pub fn new(button_mask: u8) -> EventStream {
+        EventStream {
+            queue: [None; QUEUE_LEN],
+            head: 0,
+            len: 0,
+            button_mask,
+            buttons: buttons::read(button_mask),
+            poll_interval: buttons::POLL_INTERVAL,
+        }
+    }
+
+    /// Sets the interval in which `next()` polls the stream while waiting, by default
+    /// `buttons::POLL_INTERVAL`.
+    ///
+    /// With an interval of at least one second, the executor sleeps between the polls until the
+    /// RTC alarm wakes it, but buttons have to be held until the next poll. Shorter intervals
+    /// busy-poll.
+    pub fn set_poll_interval(&mut self, interval: time::Duration) {
+        self.poll_interval = interval;
+    }
+
+    /// Adds an event, e.g. a gesture.
+    ///
+    /// # Returns
+    /// False if the queue is full and the oldest event was dropped.
+    pub fn push(&mut self, event: impl Into<Event>) -> bool {
+        let dropped = self.len == QUEUE_LEN;
+        if dropped {
+            self.head = (self.head + 1) % QUEUE_LEN;
+            self.len -= 1;
+        }
+        self.queue[(self.head + self.len) % QUEUE_LEN] = Some(event.into());
+        self.len += 1;
+        !dropped
+    }
+
+    /// Checks the buttons and returns the next event without blocking.
+    ///
+    /// Button events are only detected while polling, so poll regularly to not miss short presses.
+    pub fn poll(&mut self) -> Option<Event> {
+        self.poll_buttons();
+        if self.len == 0 {
+            return None;
+        }
+        let event = self.queue[self.head].take();
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        event
+    }
+
+    /// Waits asynchronously for the next event, see `executor`.
+    ///
+    /// Since there is no interrupt for the buttons, the stream is polled every
+    /// `buttons::POLL_INTERVAL` while waiting, which busy-polls the core. Use
+    /// `set_poll_interval()` with an interval of at least one second to let the core sleep.
+    pub async fn next(&mut self) -> Event {
+        loop {
+            if let Some(event) = self.poll() {
+                return event;
+            }
+            time::sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Number of queued events, without the button changes since the last poll.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// True if there are no queued events, without the button changes since the last poll.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn poll_buttons(&mut self) {
+        let current = buttons::read(self.button_mask);
+        let released = self.buttons & !current;
+        let pressed = current & !self.buttons;
+        self.buttons = current;
+        if released != 0 {
+            self.push(Event::ButtonsReleased(released));
+        }
+        if pressed != 0 {
+            self.push(Event::ButtonsPressed(pressed));
+        }
+    }
+}
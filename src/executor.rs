@@ -0,0 +1,309 @@
+//! A small single-threaded executor for `async` apps.
+//!
+//! Tasks are woken by the interrupts of Epicardium (see `interrupts`), by timers created with
+//! `time::sleep()` or by their own `Waker`. Every interrupt wakes all tasks, which is cheap
+//! for the handful of tasks of a typical app and avoids any allocation.
+//!
+//! While no task is ready, the core sleeps until the next interrupt. Timers are woken by the
+//! RTC alarm, which only has a resolution of one second: the core busy-waits during the last
+//! second before a deadline, and for the whole time if the alarm is used by a `time::Alarm`.
+//! Short sleeps, e.g. for animations, keep the core busy.
+//!
+//! Futures provided by this crate:
+//! * `interrupts::wait()` completes when an interrupt occurs
+//! * `time::sleep()` and `time::sleep_until()` complete after some time
+//! * `buttons::wait_press()` and `buttons::wait_press_every()` complete when a button is pressed
+//! * `uart::read_line()` reads a line from the serial console
+//! * `bhi160::Sensor::next_batch()` returns the next samples of a sensor
+//!
+//! # Example
+//! ```
+//! use rustcardium::executor::Executor;
+//! use rustcardium::time::{self, Duration};
+//! use rustcardium::{pin_mut, uart};
+//!
+//! let blink = async {
+//!     for _ in 0..3 {
+//!         rustcardium::leds::set_rocket(rustcardium::leds::Rocket::Blue, 31);
+//!         time::sleep(Duration::from_millis(500)).await;
+//!         rustcardium::leds::set_rocket(rustcardium::leds::Rocket::Blue, 0);
+//!         time::sleep(Duration::from_millis(500)).await;
+//!     }
+//! };
+//! let input = async {
+//!     let mut line = [0; 64];
+//!     if let Ok(len) = uart::read_line(&mut line).await {
+//!         // handle the command in `line[..len]`
+//!     }
+//! };
+//! # rustcardium::mock::with(|s| s.rtc_auto_advance = 1);
+//! # rustcardium::mock::push_uart_input("help\n");
+//! pin_mut!(blink, input);
+//!
+//! let mut executor = Executor::new();
+//! executor.spawn(blink)?;
+//! executor.spawn(input)?;
+//! executor.run();
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::interrupts::{self, Interrupt};
+use crate::time::{Duration, Instant, SystemTime};
+
+use arrayvec::ArrayVec;
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+/// Maximum number of tasks of an `Executor`.
+pub const MAX_TASKS: usize = 16;
+
+/// Bit mask of the tasks which need to be polled.
+static READY: AtomicU32 = AtomicU32::new(0);
+
+/// Deadline of the earliest timer.
+///
+/// It is only accessed within `interrupts::free()`.
+struct Timer(Cell<Option<Instant>>);
+
+unsafe impl Sync for Timer {}
+
+impl Timer {
+    fn get(&self) -> Option<Instant> {
+        interrupts::free(|| self.0.get())
+    }
+
+    fn set(&self, deadline: Option<Instant>) {
+        interrupts::free(|| self.0.set(deadline))
+    }
+}
+
+static NEXT_TIMER: Timer = Timer(Cell::new(None));
+
+/// Forgets the ready tasks and the timer, see `mock::reset()`.
+#[cfg(feature = "mock")]
+pub(crate) fn reset() {
+    READY.store(0, Ordering::SeqCst);
+    NEXT_TIMER.set(None);
+}
+
+/// Wakes all tasks, called for every interrupt.
+pub(crate) fn wake_all() {
+    READY.store(u32::MAX, Ordering::SeqCst);
+}
+
+/// Makes sure the executor wakes up all tasks at the deadline.
+pub(crate) fn register_timer(deadline: Instant) {
+    interrupts::free(|| {
+        let next = NEXT_TIMER.0.get();
+        if next.map(|next| deadline < next).unwrap_or(true) {
+            NEXT_TIMER.0.set(Some(deadline));
+        }
+    })
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake_task, wake_task, drop_waker);
+
+unsafe fn clone_waker(task: *const ()) -> RawWaker {
+    RawWaker::new(task, &VTABLE)
+}
+
+unsafe fn wake_task(task: *const ()) {
+    READY.fetch_or(1 << (task as usize), Ordering::SeqCst);
+}
+
+unsafe fn drop_waker(_task: *const ()) {}
+
+/// Waker which marks the task with the given index as ready.
+fn waker(task: usize) -> Waker {
+    unsafe { Waker::from_raw(RawWaker::new(task as *const (), &VTABLE)) }
+}
+
+/// Schedules the RTC alarm for a timer, unless the alarm is used otherwise.
+///
+/// The RTC alarm has a resolution of one second, so it is scheduled at the full second before
+/// the deadline and `idle()` busy-waits for the rest of the time.
+///
+/// # Returns
+/// True if the alarm was scheduled.
+fn arm_rtc_alarm(deadline: Instant) -> bool {
+    if interrupts::is_registered(Interrupt::RtcAlarm) {
+        return false;
+    }
+    let at = SystemTime::now() + deadline.duration_since(Instant::now());
+    let seconds = at.unix_millis() / 1000;
+    let scheduled = unsafe { sys::epic_rtc_schedule_alarm(seconds as u32) == 0 };
+    scheduled && interrupts::enable(Interrupt::RtcAlarm).is_ok()
+}
+
+/// Waits until a task is woken or the earliest timer expires.
+///
+/// The core sleeps until the next interrupt or until the RTC alarm fires in the last second
+/// before the earliest timer. It busy-waits if the timer expires within a second or the RTC
+/// alarm is used by a `time::Alarm`.
+fn idle() {
+    let timer = NEXT_TIMER.get();
+    let mut sleep = true;
+    if let Some(deadline) = timer {
+        sleep = deadline.duration_since(Instant::now()) >= Duration::from_secs(1)
+            && arm_rtc_alarm(deadline);
+    }
+    while READY.load(Ordering::SeqCst) == 0 {
+        if let Some(deadline) = timer {
+            if Instant::now() >= deadline {
+                NEXT_TIMER.set(None);
+                wake_all();
+                return;
+            }
+            // the RTC alarm only has a resolution of one second
+            if deadline.duration_since(Instant::now()) < Duration::from_secs(1) {
+                sleep = false;
+            }
+        }
+        if sleep {
+            interrupts::sleep_while_equal(&READY, 0);
+        } else {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Runs a future to completion and returns its output.
+///
+/// Must not be called from within a task of an `Executor` or another `block_on()`.
+///
+/// # Example
+/// ```
+/// use rustcardium::executor::block_on;
+/// use rustcardium::time::{self, Duration};
+///
+/// # rustcardium::mock::with(|s| s.rtc_auto_advance = 1);
+/// let answer = block_on(async {
+///     time::sleep(Duration::from_millis(10)).await;
+///     42
+/// });
+/// ```
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    crate::pin_mut!(future);
+    let waker = waker(0);
+    let mut cx = Context::from_waker(&waker);
+    wake_all();
+    loop {
+        if READY.swap(0, Ordering::SeqCst) != 0 {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+        idle();
+    }
+}
+
+/// A task of an `Executor`, a pinned future without output.
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+/// Runs several tasks concurrently until all of them are completed.
+///
+/// Tasks need to be pinned, e.g. with the `pin_mut!` macro.
+pub struct Executor<'a> {
+    tasks: ArrayVec<[Option<Task<'a>>; MAX_TASKS]>,
+}
+
+impl<'a> Executor<'a> {
+    /// Creates an executor without any tasks.
+    pub fn new() -> Executor<'a> {
+        Executor {
+            tasks: ArrayVec::new(),
+        }
+    }
+
+    /// Adds a task, it is started with `run()`.
+    ///
+    /// # Returns
+    /// `Error::TooManyTasks` if the executor already has `MAX_TASKS` tasks.
+    pub fn spawn(&mut self, task: Task<'a>) -> Result<()> {
+        self.tasks
+            .try_push(Some(task))
+            .map_err(|_| Error::TooManyTasks)
+    }
+
+    /// Polls the tasks whenever they are woken, until all tasks are completed.
+    ///
+    /// Must not be called from within a task or `block_on()`.
+    pub fn run(&mut self) {
+        wake_all();
+        while self.tasks.iter().any(Option::is_some) {
+            let ready = READY.swap(0, Ordering::SeqCst);
+            for (index, slot) in self.tasks.iter_mut().enumerate() {
+                if ready & (1 << index) == 0 {
+                    continue;
+                }
+                if let Some(task) = slot {
+                    let waker = waker(index);
+                    let mut cx = Context::from_waker(&waker);
+                    if task.as_mut().poll(&mut cx).is_ready() {
+                        *slot = None;
+                    }
+                }
+            }
+            if self.tasks.iter().any(Option::is_some) {
+                idle();
+            }
+        }
+        self.tasks.clear();
+    }
+}
+
+impl<'a> Default for Executor<'a> {
+    fn default() -> Self {
+        Executor::new()
+    }
+}
+
+/// Future which lets other tasks run once.
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.yielded {
+            Poll::Ready(())
+        } else {
+            self.yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+/// Gives the other tasks a chance to run, e.g. in a long computation.
+///
+/// The task is woken again immediately, so the core doesn't sleep. To wait for a condition
+/// without an interrupt, check it after a short `time::sleep()` instead of yielding in a loop.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+/// Pins local variables on the stack, so they can be polled as futures.
+///
+/// # Example
+/// ```
+/// use rustcardium::pin_mut;
+///
+/// let task = async {};
+/// pin_mut!(task);
+/// // `task` is now a `Pin<&mut _>`
+/// ```
+#[macro_export]
+macro_rules! pin_mut {
+    ($($x:ident),* $(,)?) => { $(
+        // Move the value to make sure it can't be accessed except through the pinned reference
+        let mut $x = $x;
+        #[allow(unused_mut)]
+        let mut $x = unsafe { ::core::pin::Pin::new_unchecked(&mut $x) };
+    )* }
+}
@@ -0,0 +1,188 @@
+use super::*;
+
+/// Width of the display and framebuffer in pixels.
+pub const WIDTH: u16 = sys::DISP_WIDTH as u16;
+
+/// Height of the display and framebuffer in pixels.
+pub const HEIGHT: u16 = sys::DISP_HEIGHT as u16;
+
+const BUFFER_SIZE: usize = WIDTH as usize * HEIGHT as usize * 2;
+
+/// An owned software framebuffer with the same layout as the display.
+///
+/// Pixels are stored as RGB565 in big-endian byte order, as expected by Epicardium.
+/// Instead of calling the (slow) immediate mode functions of the display for each
+/// primitive, draw a whole frame here and upload it with `Display::show_framebuffer()`.
+///
+/// The framebuffer needs 25 KiB of memory, consider keeping it in a `static` instead of on the stack.
+///
+/// # Example
+/// ```
+/// use rustcardium::framebuffer::Framebuffer;
+/// use rustcardium::Color;
+///
+/// let mut fb = Framebuffer::new();
+/// fb.fill(Color { r: 0, g: 0, b: 255 });
+/// fb.set_pixel(10, 20, Color { r: 255, g: 255, b: 255 }).unwrap();
+///
+/// if let Ok(display) = rustcardium::display::Display::open() {
+///     display.show_framebuffer(&fb).unwrap();
+/// }
+/// ```
+pub struct Framebuffer {
+    buffer: [u8; BUFFER_SIZE],
+}
+
+impl Framebuffer {
+    /// Creates a new framebuffer where all pixels are black.
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            buffer: [0; BUFFER_SIZE],
+        }
+    }
+
+    fn offset(x: u16, y: u16) -> usize {
+        (usize::from(y) * usize::from(WIDTH) + usize::from(x)) * 2
+    }
+
+    fn raw_pixel(&self, x: u16, y: u16) -> u16 {
+        let offset = Framebuffer::offset(x, y);
+        u16::from_be_bytes([self.buffer[offset], self.buffer[offset + 1]])
+    }
+
+    pub(crate) fn set_raw_pixel(&mut self, x: u16, y: u16, raw: u16) {
+        let offset = Framebuffer::offset(x, y);
+        self.buffer[offset..offset + 2].copy_from_slice(&raw.to_be_bytes());
+    }
+
+    /// Returns the color of a pixel.
+    ///
+    /// Since the framebuffer stores RGB565, the lower bits of each component are approximated.
+    ///
+    /// # Arguments
+    /// * `x` - X coordinate, 0<= x < 160
+    /// * `y` - Y coordinate, 0<= y < 80
+    pub fn pixel(&self, x: u16, y: u16) -> Result<Color> {
+        if x >= WIDTH || y >= HEIGHT {
+            return Err(Error::OutsideDisplay);
+        }
+        Ok(Color::from_rgb565(self.raw_pixel(x, y)))
+    }
+
+    /// Sets the color of a pixel.
+    ///
+    /// # Arguments
+    /// * `x` - X coordinate, 0<= x < 160
+    /// * `y` - Y coordinate, 0<= y < 80
+    /// * `col` - color of the pixel
+    pub fn set_pixel(&mut self, x: u16, y: u16, col: Color) -> Result<()> {
+        if x >= WIDTH || y >= HEIGHT {
+            return Err(Error::OutsideDisplay);
+        }
+        self.set_raw_pixel(x, y, col.rgb565());
+        Ok(())
+    }
+
+    /// Fills the whole framebuffer with one color.
+    pub fn fill(&mut self, col: Color) {
+        let raw = col.rgb565().to_be_bytes();
+        for pixel in self.buffer.chunks_mut(2) {
+            pixel.copy_from_slice(&raw);
+        }
+    }
+
+    /// Copies an image into the framebuffer.
+    ///
+    /// Parts of the image outside of the framebuffer are clipped.
+    ///
+    /// # Arguments
+    /// * `x` - X coordinate of the top left corner of the image, can be negative
+    /// * `y` - Y coordinate of the top left corner of the image, can be negative
+    /// * `width` - Width of the image in pixels
+    /// * `pixels` - RGB565 values of the image, row by row
+    pub fn blit(&mut self, x: i16, y: i16, width: u16, pixels: &[u16]) {
+        if width == 0 {
+            return;
+        }
+        for (row, line) in pixels.chunks(usize::from(width)).enumerate() {
+            let target_y = i32::from(y) + row as i32;
+            if target_y < 0 {
+                continue;
+            }
+            if target_y >= i32::from(HEIGHT) {
+                break;
+            }
+            for (column, raw) in line.iter().enumerate() {
+                let target_x = i32::from(x) + column as i32;
+                if target_x >= 0 && target_x < i32::from(WIDTH) {
+                    self.set_raw_pixel(target_x as u16, target_y as u16, *raw);
+                }
+            }
+        }
+    }
+
+    /// Moves the content of the framebuffer.
+    ///
+    /// Pixels moved outside the framebuffer are discarded and the uncovered area is filled.
+    ///
+    /// # Arguments
+    /// * `dx` - Number of pixels to move to the right (negative values move to the left)
+    /// * `dy` - Number of pixels to move down (negative values move up)
+    /// * `fill` - Color of the uncovered area
+    pub fn scroll(&mut self, dx: i16, dy: i16, fill: Color) {
+        let width = i32::from(WIDTH);
+        let height = i32::from(HEIGHT);
+        let (dx, dy) = (i32::from(dx), i32::from(dy));
+        let fill = fill.rgb565();
+
+        if dx.abs() >= width || dy.abs() >= height {
+            self.fill(Color::from_rgb565(fill));
+            return;
+        }
+
+        // Iterate in the opposite direction of the movement so no pixel is overwritten before it is copied
+        for i in 0..height {
+            let y = if dy > 0 { height - 1 - i } else { i };
+            let source_y = y - dy;
+            let row_offset = Framebuffer::offset(0, y as u16);
+            if source_y < 0 || source_y >= height {
+                for x in 0..WIDTH {
+                    self.set_raw_pixel(x, y as u16, fill);
+                }
+                continue;
+            }
+            let source_offset = Framebuffer::offset(0, source_y as u16);
+            let row_bytes = (width - dx.abs()) as usize * 2;
+            if dx >= 0 {
+                self.buffer.copy_within(
+                    source_offset..source_offset + row_bytes,
+                    row_offset + dx as usize * 2,
+                );
+            } else {
+                let start = source_offset + (-dx) as usize * 2;
+                self.buffer
+                    .copy_within(start..start + row_bytes, row_offset);
+            }
+            let uncovered = if dx >= 0 { 0..dx } else { width + dx..width };
+            for x in uncovered {
+                self.set_raw_pixel(x as u16, y as u16, fill);
+            }
+        }
+    }
+
+    /// The raw content of the framebuffer, 2 bytes (big-endian RGB565) per pixel, row by row.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub(crate) fn as_raw(&self) -> *mut sys::disp_framebuffer {
+        // The buffer has the same size and layout as the `raw` variant of the union
+        &self.buffer as *const [u8; BUFFER_SIZE] as *mut sys::disp_framebuffer
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Framebuffer::new()
+    }
+}
@@ -0,0 +1,598 @@
+//! The `fs` module gives access to the FAT file system of card10.
+//!
+//! Files are opened with `File::open()`, `File::create()` or `OpenOptions` and implement the
+//! `Read`, `Write` and `Seek` traits of the `io` module. Errors reported by Epicardium are
+//! mapped to the corresponding `Error` variant, e.g. `ENOENT` to `Error::FileNotFound`.
+//!
+//! # Example
+//! ```
+//! use rustcardium::fs::{self, File};
+//! use rustcardium::io::{Read, Write};
+//!
+//! let mut file = File::create("/log.txt")?;
+//! file.write_all(b"temperature: 21")?;
+//! file.close()?;
+//!
+//! let mut content = [0; 32];
+//! let len = File::open("/log.txt")?.read(&mut content)?;
+//! assert_eq!(&content[..len], b"temperature: 21");
+//!
+//! fs::remove("/log.txt")?;
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::io::{Read, Seek, SeekFrom, Write};
+use arrayvec::ArrayVec;
+
+/// Maximum length of a path in bytes.
+pub const MAX_PATH: usize = sys::EPICSTAT_MAX_PATH as usize;
+
+// Values of `whence` for `epic_file_seek`, as in C's stdio.h
+const SEEK_SET: cty::c_int = 0;
+const SEEK_CUR: cty::c_int = 1;
+const SEEK_END: cty::c_int = 2;
+
+/// Creates a null-terminated copy of a path.
+///
+/// # Returns
+/// `Error::NameTooLong` if the path is longer than `MAX_PATH`.
+fn c_path(path: &str) -> Result<ArrayString<[u8; 1024]>> {
+    if path.trim_end_matches('\0').len() > MAX_PATH {
+        return Err(Error::NameTooLong);
+    }
+    Ok(create_nullterminated_str(path))
+}
+
+/// An open file on the file system.
+///
+/// The file is closed automatically when the variable gets out of scope. Use `close()` to
+/// check for errors when closing the file.
+pub struct File {
+    fd: cty::c_int,
+}
+
+impl File {
+    /// Opens an existing file in read-only mode.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the file
+    pub fn open(path: &str) -> Result<File> {
+        OpenOptions::new().read(true).open(path)
+    }
+
+    /// Opens a file in write-only mode. The file is created if it does not exist and truncated if it does.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the file
+    pub fn create(path: &str) -> Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+
+    /// Opens a file with the mode string of C's `fopen` (e.g. `"r"`, `"w+"` or `"a"`).
+    fn open_with_mode(path: &str, mode: &str) -> Result<File> {
+        let mut path = c_path(path)?;
+        let mut mode = create_nullterminated_str(mode);
+        let fd = unsafe {
+            check(sys::epic_file_open(
+                path.as_mut_ptr() as *const cty::c_char,
+                mode.as_mut_ptr() as *const cty::c_char,
+            ))?
+        };
+        Ok(File { fd })
+    }
+
+    /// Writes all buffered data of this file to the file system.
+    pub fn sync(&self) -> Result<()> {
+        unsafe { check(sys::epic_file_flush(self.fd))? };
+        Ok(())
+    }
+
+    /// Closes the file.
+    ///
+    /// In contrast to dropping the file, this reports errors which occur when writing out buffered data.
+    pub fn close(self) -> Result<()> {
+        let fd = self.fd;
+        // the file must not be closed a second time when dropped
+        core::mem::forget(self);
+        unsafe { check(sys::epic_file_close(fd))? };
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        unsafe {
+            sys::epic_file_close(self.fd);
+        }
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = unsafe {
+            check(sys::epic_file_read(
+                self.fd,
+                buf.as_mut_ptr() as *mut cty::c_void,
+                buf.len(),
+            ))?
+        };
+        Ok(read as usize)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = unsafe {
+            check(sys::epic_file_write(
+                self.fd,
+                buf.as_ptr() as *const cty::c_void,
+                buf.len(),
+            ))?
+        };
+        Ok(written as usize)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sync()
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u32> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => {
+                if offset > i32::MAX as u32 {
+                    return Err(Error::InvalidArgument);
+                }
+                (offset as i32, SEEK_SET)
+            }
+            SeekFrom::Current(offset) => (offset, SEEK_CUR),
+            SeekFrom::End(offset) => (offset, SEEK_END),
+        };
+        unsafe {
+            check(sys::epic_file_seek(self.fd, offset as cty::c_long, whence))?;
+            let position = check(sys::epic_file_tell(self.fd))?;
+            Ok(position as u32)
+        }
+    }
+}
+
+impl core::fmt::Write for File {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// Options which configure how a file is opened.
+///
+/// Epicardium opens files like C's `fopen`, so only the combinations which can be expressed
+/// with a `fopen` mode string are supported:
+///
+/// | Options                                 | Mode  |
+/// |-----------------------------------------|-------|
+/// | `read`                                  | `r`   |
+/// | `read`, `write`                         | `r+`  |
+/// | `write`, `create`, `truncate`           | `w`   |
+/// | `read`, `write`, `create`, `truncate`   | `w+`  |
+/// | `append`, `create`                      | `a`   |
+/// | `read`, `append`, `create`              | `a+`  |
+///
+/// `append` implies `write`. All other combinations result in `Error::InvalidArgument`.
+///
+/// # Example
+/// ```
+/// use rustcardium::fs::OpenOptions;
+///
+/// let log = OpenOptions::new().append(true).create(true).open("/sensors.csv")?;
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+}
+
+impl OpenOptions {
+    /// Creates options with all flags set to false.
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// Open the file for reading.
+    pub fn read(&mut self, read: bool) -> &mut OpenOptions {
+        self.read = read;
+        self
+    }
+
+    /// Open the file for writing.
+    pub fn write(&mut self, write: bool) -> &mut OpenOptions {
+        self.write = write;
+        self
+    }
+
+    /// Write all data at the end of the file.
+    pub fn append(&mut self, append: bool) -> &mut OpenOptions {
+        self.append = append;
+        self
+    }
+
+    /// Truncate an existing file to a length of 0.
+    pub fn truncate(&mut self, truncate: bool) -> &mut OpenOptions {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Create the file if it does not exist.
+    pub fn create(&mut self, create: bool) -> &mut OpenOptions {
+        self.create = create;
+        self
+    }
+
+    /// The `fopen` mode string for these options.
+    fn mode(&self) -> Result<&'static str> {
+        let write = self.write || self.append;
+        let mode = match (self.read, write, self.append, self.truncate, self.create) {
+            (true, false, false, false, false) => "r",
+            (true, true, false, false, false) => "r+",
+            (false, true, false, true, true) => "w",
+            (true, true, false, true, true) => "w+",
+            (false, true, true, false, true) => "a",
+            (true, true, true, false, true) => "a+",
+            _ => return Err(Error::InvalidArgument),
+        };
+        Ok(mode)
+    }
+
+    /// Opens the file at the given path with these options.
+    ///
+    /// # Arguments
+    /// * `path` - Absolute path of the file
+    pub fn open(&self, path: &str) -> Result<File> {
+        File::open_with_mode(path, self.mode()?)
+    }
+}
+
+/// Type of an entry in the file system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+}
+
+/// Information about a file or directory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    file_type: FileType,
+    len: u32,
+}
+
+impl Metadata {
+    /// Converts the result of `epic_file_stat` or `epic_file_readdir`.
+    ///
+    /// # Returns
+    /// None if the type is `EPICSTAT_NONE`.
+    fn from_stat(stat: &sys::epic_stat) -> Option<Metadata> {
+        let file_type = match stat.type_ {
+            sys::epic_stat_type_EPICSTAT_FILE => FileType::File,
+            sys::epic_stat_type_EPICSTAT_DIR => FileType::Dir,
+            _ => return None,
+        };
+        Some(Metadata {
+            file_type,
+            len: stat.size,
+        })
+    }
+
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type == FileType::Dir
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// True if the file has a size of 0 bytes.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Returns information about a file or directory.
+///
+/// # Arguments
+/// * `path` - Absolute path of the file or directory
+pub fn metadata(path: &str) -> Result<Metadata> {
+    let mut path = c_path(path)?;
+    let mut stat: sys::epic_stat = unsafe { core::mem::zeroed() };
+    unsafe {
+        check(sys::epic_file_stat(
+            path.as_mut_ptr() as *const cty::c_char,
+            &mut stat,
+        ))?;
+    }
+    Metadata::from_stat(&stat).ok_or(Error::FileNotFound)
+}
+
+/// Removes a file or an empty directory.
+///
+/// # Arguments
+/// * `path` - Absolute path of the file or directory
+pub fn remove(path: &str) -> Result<()> {
+    let mut path = c_path(path)?;
+    unsafe {
+        check(sys::epic_file_unlink(
+            path.as_mut_ptr() as *const cty::c_char
+        ))?
+    };
+    Ok(())
+}
+
+/// Renames or moves a file or directory.
+///
+/// # Arguments
+/// * `from` - Absolute path of the existing file or directory
+/// * `to` - New absolute path, must not exist yet
+pub fn rename(from: &str, to: &str) -> Result<()> {
+    let mut from = c_path(from)?;
+    let mut to = c_path(to)?;
+    unsafe {
+        check(sys::epic_file_rename(
+            from.as_mut_ptr() as *const cty::c_char,
+            to.as_mut_ptr() as *const cty::c_char,
+        ))?
+    };
+    Ok(())
+}
+
+/// Creates a new directory, the parent directory must exist.
+///
+/// # Arguments
+/// * `path` - Absolute path of the new directory
+pub fn create_dir(path: &str) -> Result<()> {
+    let mut path = c_path(path)?;
+    unsafe { check(sys::epic_file_mkdir(path.as_mut_ptr() as *const cty::c_char))? };
+    Ok(())
+}
+
+/// Creates or truncates a file and writes the data to it.
+///
+/// # Arguments
+/// * `path` - Absolute path of the file
+/// * `data` - New content of the file
+pub fn write(path: &str, data: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(data)?;
+    file.close()
+}
+
+/// A path or file name of at most `MAX_PATH` bytes.
+pub type PathBuf = ArrayString<[u8; MAX_PATH + 1]>;
+
+/// An entry of a directory, returned by `read_dir()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    /// Name of the entry without the path of the directory
+    pub name: PathBuf,
+    /// Size in bytes, 0 for directories
+    pub size: u32,
+    pub kind: FileType,
+}
+
+impl DirEntry {
+    /// Decodes the result of `epic_file_readdir`.
+    ///
+    /// # Returns
+    /// None if the type is `EPICSTAT_NONE`, which marks the end of the directory.
+    fn from_stat(stat: &sys::epic_stat) -> Option<DirEntry> {
+        let metadata = Metadata::from_stat(stat)?;
+        let bytes = unsafe { &*(&stat.name as *const [cty::c_char] as *const [u8]) };
+        let len = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        // FAT file names should always be valid UTF-8, but never fail because of a broken name
+        let name = match core::str::from_utf8(&bytes[..len]) {
+            Ok(name) => name,
+            Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+        };
+        Some(DirEntry {
+            name: PathBuf::from(&name[..name.len().min(MAX_PATH)]).unwrap_or_default(),
+            size: metadata.len(),
+            kind: metadata.file_type(),
+        })
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == FileType::File
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Dir
+    }
+}
+
+/// Iterator over the entries of a directory, created with `read_dir()`.
+///
+/// The directory is closed when the iterator gets out of scope.
+pub struct ReadDir {
+    fd: cty::c_int,
+    finished: bool,
+}
+
+impl ReadDir {
+    /// Starts the iteration over the entries from the beginning again.
+    pub fn rewind(&mut self) -> Result<()> {
+        unsafe { check(sys::epic_file_readdir(self.fd, core::ptr::null_mut()))? };
+        self.finished = false;
+        Ok(())
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Result<DirEntry>> {
+        if self.finished {
+            return None;
+        }
+        let mut stat: sys::epic_stat = unsafe { core::mem::zeroed() };
+        if let Err(e) = unsafe { check(sys::epic_file_readdir(self.fd, &mut stat)) } {
+            self.finished = true;
+            return Some(Err(e));
+        }
+        let entry = DirEntry::from_stat(&stat);
+        self.finished = entry.is_none();
+        entry.map(Ok)
+    }
+}
+
+impl Drop for ReadDir {
+    fn drop(&mut self) {
+        unsafe {
+            sys::epic_file_close(self.fd);
+        }
+    }
+}
+
+/// Returns an iterator over the entries of a directory.
+///
+/// The entries `.` and `..` are not included.
+///
+/// # Arguments
+/// * `path` - Absolute path of the directory
+///
+/// # Example
+/// ```
+/// use rustcardium::fs;
+///
+/// for entry in fs::read_dir("/")? {
+///     let entry = entry?;
+///     if entry.is_file() && entry.name.ends_with(".elf") {
+///         // found a l0dable
+///     }
+/// }
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+pub fn read_dir(path: &str) -> Result<ReadDir> {
+    let mut path = c_path(path)?;
+    let fd = unsafe {
+        check(sys::epic_file_opendir(
+            path.as_mut_ptr() as *const cty::c_char
+        ))?
+    };
+    Ok(ReadDir {
+        fd,
+        finished: false,
+    })
+}
+
+/// Maximum depth of `walk_dir()`.
+///
+/// Each level of the walk keeps its directory open and Epicardium only allows a few open
+/// files and directories at the same time.
+pub const MAX_WALK_DEPTH: usize = 8;
+
+/// An entry found by `walk_dir()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalkEntry {
+    /// Absolute path of the entry
+    pub path: PathBuf,
+    /// Depth of the entry, entries of the directory the walk started at have a depth of 1
+    pub depth: usize,
+    pub entry: DirEntry,
+}
+
+/// Recursive iterator over a directory tree, created with `walk_dir()`.
+///
+/// Directories are visited depth-first, each directory is returned before its content.
+pub struct WalkDir {
+    /// Open directories and the length of the path of their parent.
+    stack: ArrayVec<[(ReadDir, usize); MAX_WALK_DEPTH]>,
+    /// Path of the directory on the top of the stack.
+    path: PathBuf,
+    max_depth: usize,
+}
+
+impl Iterator for WalkDir {
+    type Item = Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Result<WalkEntry>> {
+        loop {
+            let depth = self.stack.len();
+            let entry = match self.stack.last_mut()?.0.next() {
+                Some(Ok(entry)) => entry,
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    if let Some((_, parent_len)) = self.stack.pop() {
+                        self.path.truncate(parent_len);
+                    }
+                    continue;
+                }
+            };
+
+            let mut path = self.path;
+            if (!path.ends_with('/') && path.try_push('/').is_err())
+                || path.try_push_str(&entry.name).is_err()
+            {
+                return Some(Err(Error::NameTooLong));
+            }
+
+            if entry.is_dir() && depth < self.max_depth {
+                match read_dir(&path) {
+                    Ok(dir) => {
+                        self.stack.push((dir, self.path.len()));
+                        self.path = path;
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            return Some(Ok(WalkEntry { path, depth, entry }));
+        }
+    }
+}
+
+/// Returns a recursive iterator over all files and directories below a directory.
+///
+/// # Arguments
+/// * `path` - Absolute path of the directory to start at
+/// * `max_depth` - Maximum depth of the returned entries, 1 only returns the content of `path`. Values larger than `MAX_WALK_DEPTH` are clamped.
+///
+/// # Example
+/// ```
+/// use rustcardium::fs;
+/// # fs::create_dir("/apps")?;
+///
+/// for entry in fs::walk_dir("/apps", 2)? {
+///     let entry = entry?;
+///     if entry.path.ends_with(".py") {
+///         // rustcardium::os::exec(&entry.path)?;
+///     }
+/// }
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+pub fn walk_dir(path: &str, max_depth: usize) -> Result<WalkDir> {
+    let mut stack = ArrayVec::new();
+    let root = PathBuf::from(path.trim_end_matches('\0')).map_err(|_| Error::NameTooLong)?;
+    if max_depth > 0 {
+        stack.push((read_dir(path)?, 0));
+    }
+    Ok(WalkDir {
+        stack,
+        path: root,
+        max_depth: max_depth.min(MAX_WALK_DEPTH),
+    })
+}
@@ -0,0 +1,446 @@
+//! Recognition of wrist gestures from the samples of the BHI160 accelerometer and gyroscope.
+//!
+//! The `GestureDetector` is fed with the samples of both sensors and returns a `Gesture` as soon
+//! as one is recognized. Gestures can be added to an `events::EventStream`, so they are handled
+//! together with the buttons.
+//!
+//! The axes are those of the card10 worn on the left wrist with the display facing up: x points
+//! along the forearm to the hand, y across the wrist and z out of the display.
+//!
+//! # Example
+//! ```
+//! use rustcardium::bhi160::{Sample, Sensor, SensorConfig, SensorType};
+//! use rustcardium::display::Display;
+//! use rustcardium::gesture::{Gesture, GestureConfig, GestureDetector};
+//!
+//! let config = SensorConfig {
+//!     sample_buffer_len: 120,
+//!     sample_rate: 25,
+//!     dynamic_range: 4,
+//! };
+//! let accel = Sensor::enable(SensorType::Accelerometer, config)?;
+//! let mut detector = GestureDetector::new(GestureConfig::default());
+//! let display = Display::open()?;
+//! # rustcardium::mock::push_bhi160_samples(0, &[(0, 0, 8192, 3); 25]);
+//! for sample in accel.samples() {
+//!     if let Sample::Acceleration(a) = sample? {
+//!         match detector.update_acceleration(&a) {
+//!             Some(Gesture::WristRaised) => display.backlight(100)?,
+//!             Some(Gesture::WristLowered) => display.backlight(0)?,
+//!             _ => {}
+//!         }
+//!     }
+//! }
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use crate::bhi160::{Acceleration, AngularRate};
+use crate::motion;
+use crate::time::Duration;
+
+/// Gestures recognized by the `GestureDetector`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    /// The display was turned towards the face
+    WristRaised,
+    /// The display was turned away from the face
+    WristLowered,
+    /// The card10 was shaken back and forth several times
+    Shake,
+    /// Two short taps on the card10
+    DoubleTap,
+    /// Quick rotation of the wrist to the left
+    FlickLeft,
+    /// Quick rotation of the wrist to the right
+    FlickRight,
+}
+
+/// Thresholds of the `GestureDetector`.
+///
+/// All thresholds are divided by `sensitivity`, so a higher sensitivity recognizes gestures
+/// with weaker movements.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GestureConfig {
+    /// Sample rate of the accelerometer and the gyroscope in Hz
+    pub sample_rate: u16,
+    /// Factor for all thresholds, 1.0 is the default sensitivity
+    pub sensitivity: f32,
+    /// Time the wrist needs to stay raised or lowered
+    pub hold_time: Duration,
+    /// Minimal acceleration in m/s² (without gravity) of a shake
+    pub shake_threshold: f32,
+    /// Number of direction changes within `shake_window` which is a shake
+    pub shake_count: u8,
+    pub shake_window: Duration,
+    /// Minimal change of the acceleration in m/s² between two samples of a tap
+    pub tap_threshold: f32,
+    /// Maximal time between the taps of a double tap
+    pub double_tap_window: Duration,
+    /// Minimal angular rate in deg/s of a flick
+    pub flick_threshold: f32,
+    /// Time after a gesture during which no other gesture of the same kind is recognized
+    pub cooldown: Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            sample_rate: 25,
+            sensitivity: 1.0,
+            hold_time: Duration::from_millis(200),
+            shake_threshold: 12.0,
+            shake_count: 4,
+            shake_window: Duration::from_millis(1000),
+            tap_threshold: 8.0,
+            double_tap_window: Duration::from_millis(500),
+            flick_threshold: 250.0,
+            cooldown: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Orientation of the wrist with hysteresis, z of the normalized gravity is above `RAISED_Z`
+/// when raised and below `LOWERED_Z` when lowered.
+const RAISED_Z: f32 = 0.7;
+const LOWERED_Z: f32 = 0.3;
+
+/// Incremental recognition of wrist gestures.
+pub struct GestureDetector {
+    config: GestureConfig,
+    hold: u32,
+    shake_window: u32,
+    double_tap_window: u32,
+    cooldown: u32,
+    /// Low-pass filtered acceleration, i.e. gravity
+    gravity: [f32; 3],
+    previous: Option<[f32; 3]>,
+    raised: Option<bool>,
+    /// Number of samples the wrist was in the other orientation
+    orientation_count: u32,
+    accel_samples: u32,
+    shake_sign: i8,
+    shake_changes: u8,
+    shake_start: u32,
+    last_shake: Option<u32>,
+    last_tap: Option<u32>,
+    tap_active: bool,
+    gyro_samples: u32,
+    last_flick: Option<u32>,
+}
+
+impl GestureDetector {
+    /// Creates a detector which assumes the wrist is neither raised nor lowered.
+    pub fn new(config: GestureConfig) -> GestureDetector {
+        let samples = |d: Duration| motion::samples(d, config.sample_rate).max(1);
+        GestureDetector {
+            config,
+            hold: samples(config.hold_time),
+            shake_window: samples(config.shake_window),
+            double_tap_window: samples(config.double_tap_window),
+            cooldown: samples(config.cooldown),
+            gravity: [0.0; 3],
+            previous: None,
+            raised: None,
+            orientation_count: 0,
+            accel_samples: 0,
+            shake_sign: 0,
+            shake_changes: 0,
+            shake_start: 0,
+            last_shake: None,
+            last_tap: None,
+            tap_active: false,
+            gyro_samples: 0,
+            last_flick: None,
+        }
+    }
+
+    /// The configuration of this detector.
+    pub fn config(&self) -> &GestureConfig {
+        &self.config
+    }
+
+    /// True if the wrist is currently raised.
+    pub fn is_raised(&self) -> bool {
+        self.raised == Some(true)
+    }
+
+    fn threshold(&self, threshold: f32) -> f32 {
+        threshold / self.config.sensitivity
+    }
+
+    /// Processes the next sample of the accelerometer.
+    ///
+    /// # Returns
+    /// The gesture completed by the sample, if any. Raising and lowering the wrist is reported
+    /// before the other gestures.
+    pub fn update_acceleration(&mut self, a: &Acceleration) -> Option<Gesture> {
+        let current = [a.x, a.y, a.z];
+        self.accel_samples = self.accel_samples.wrapping_add(1);
+        // gravity follows the orientation within a few hundred milliseconds
+        let alpha = (5.0 / f32::from(self.config.sample_rate.max(1))).min(1.0);
+        if self.previous.is_none() {
+            self.gravity = current;
+        }
+        for (g, c) in self.gravity.iter_mut().zip(current.iter()) {
+            *g += alpha * (c - *g);
+        }
+        let previous = self.previous.replace(current).unwrap_or(current);
+
+        let orientation = self.update_orientation();
+        let shake = self.update_shake(&current);
+        let tap = self.update_tap(&current, &previous);
+        orientation.or(shake).or(tap)
+    }
+
+    fn update_orientation(&mut self) -> Option<Gesture> {
+        let [x, y, z] = self.gravity;
+        let norm = libm::sqrtf(x * x + y * y + z * z);
+        if norm < 1.0 {
+            // free fall, the orientation is unknown
+            return None;
+        }
+        let z = z / norm;
+        let raised = if z > RAISED_Z {
+            true
+        } else if z < LOWERED_Z {
+            false
+        } else {
+            self.orientation_count = 0;
+            return None;
+        };
+        if self.raised == Some(raised) {
+            self.orientation_count = 0;
+            return None;
+        }
+        self.orientation_count += 1;
+        if self.orientation_count < self.hold {
+            return None;
+        }
+        self.orientation_count = 0;
+        let first = self.raised.is_none();
+        self.raised = Some(raised);
+        match (first, raised) {
+            // the initial orientation is no gesture
+            (true, _) => None,
+            (false, true) => Some(Gesture::WristRaised),
+            (false, false) => Some(Gesture::WristLowered),
+        }
+    }
+
+    fn update_shake(&mut self, current: &[f32; 3]) -> Option<Gesture> {
+        let now = self.accel_samples;
+        if self.in_cooldown(self.last_shake, now) {
+            return None;
+        }
+        // the strongest axis of the acceleration without gravity
+        let dynamic = (0..3)
+            .map(|i| current[i] - self.gravity[i])
+            .fold(0.0f32, |max, d| if d.abs() > max.abs() { d } else { max });
+        if dynamic.abs() < self.threshold(self.config.shake_threshold) {
+            return None;
+        }
+        let sign = if dynamic > 0.0 { 1 } else { -1 };
+        if now.wrapping_sub(self.shake_start) > self.shake_window {
+            self.shake_start = now;
+            self.shake_changes = 0;
+            self.shake_sign = sign;
+            return None;
+        }
+        if sign != self.shake_sign {
+            self.shake_sign = sign;
+            self.shake_changes += 1;
+            if self.shake_changes >= self.config.shake_count {
+                self.shake_changes = 0;
+                self.last_shake = Some(now);
+                return Some(Gesture::Shake);
+            }
+        }
+        None
+    }
+
+    fn update_tap(&mut self, current: &[f32; 3], previous: &[f32; 3]) -> Option<Gesture> {
+        let now = self.accel_samples;
+        let jerk = (0..3)
+            .map(|i| (current[i] - previous[i]).abs())
+            .fold(0.0f32, f32::max);
+        let threshold = self.threshold(self.config.tap_threshold);
+        let active = jerk >= threshold;
+        let started = active && !self.tap_active;
+        self.tap_active = active;
+        // a tap starts from rest, strong movements before are part of a shake
+        let movement = (0..3)
+            .map(|i| (previous[i] - self.gravity[i]).abs())
+            .fold(0.0f32, f32::max);
+        if !started || movement >= threshold / 2.0 || self.in_cooldown(self.last_shake, now) {
+            return None;
+        }
+        match self.last_tap {
+            Some(last) if now.wrapping_sub(last) <= self.double_tap_window => {
+                self.last_tap = None;
+                Some(Gesture::DoubleTap)
+            }
+            _ => {
+                self.last_tap = Some(now);
+                None
+            }
+        }
+    }
+
+    /// Processes the next sample of the gyroscope.
+    ///
+    /// # Returns
+    /// The flick completed by the sample, if any.
+    pub fn update_angular_rate(&mut self, rate: &AngularRate) -> Option<Gesture> {
+        let now = self.gyro_samples.wrapping_add(1);
+        self.gyro_samples = now;
+        if self.in_cooldown(self.last_flick, now) {
+            return None;
+        }
+        // a flick rotates the wrist around the forearm
+        let threshold = self.threshold(self.config.flick_threshold);
+        let gesture = if rate.x >= threshold {
+            Gesture::FlickRight
+        } else if rate.x <= -threshold {
+            Gesture::FlickLeft
+        } else {
+            return None;
+        };
+        self.last_flick = Some(now);
+        Some(gesture)
+    }
+
+    fn in_cooldown(&self, last: Option<u32>, now: u32) -> bool {
+        last.map(|last| now.wrapping_sub(last) < self.cooldown)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::bhi160::test_util::{accel, gyro};
+    use crate::bhi160::STANDARD_GRAVITY;
+
+    /// Samples per second of the default configuration.
+    const RATE: usize = 25;
+
+    /// Feeds the samples to the detector and collects the recognized gestures.
+    fn feed<I>(detector: &mut GestureDetector, samples: I) -> ([Option<Gesture>; 8], usize)
+    where
+        I: IntoIterator<Item = Acceleration>,
+    {
+        let mut gestures = [None; 8];
+        let mut n = 0;
+        for a in samples {
+            if let Some(gesture) = detector.update_acceleration(&a) {
+                gestures[n] = Some(gesture);
+                n += 1;
+            }
+        }
+        (gestures, n)
+    }
+
+    fn hold(a: Acceleration, seconds: usize) -> impl Iterator<Item = Acceleration> {
+        core::iter::repeat_n(a, seconds * RATE)
+    }
+
+    /// The display faces up.
+    fn raised() -> Acceleration {
+        accel(0.0, 0.0, STANDARD_GRAVITY)
+    }
+
+    /// The arm hangs down, the display faces sideways.
+    fn lowered() -> Acceleration {
+        accel(STANDARD_GRAVITY, 0.0, 0.0)
+    }
+
+    #[test]
+    fn raise_and_lower() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        // the initial orientation is no gesture
+        assert_eq!(feed(&mut detector, hold(lowered(), 1)).1, 0);
+        assert!(!detector.is_raised());
+
+        // turn the wrist within 200 ms and hold it
+        let turn = (0..5).map(|i| {
+            let angle = i as f32 / 4.0 * core::f32::consts::FRAC_PI_2;
+            accel(
+                STANDARD_GRAVITY * libm::cosf(angle),
+                0.0,
+                STANDARD_GRAVITY * libm::sinf(angle),
+            )
+        });
+        let (gestures, n) = feed(&mut detector, turn.chain(hold(raised(), 1)));
+        assert_eq!(&gestures[..n], &[Some(Gesture::WristRaised)]);
+        assert!(detector.is_raised());
+
+        let (gestures, n) = feed(&mut detector, hold(lowered(), 1));
+        assert_eq!(&gestures[..n], &[Some(Gesture::WristLowered)]);
+    }
+
+    #[test]
+    fn brief_turn_is_ignored() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        feed(&mut detector, hold(lowered(), 1));
+        // shorter than the hold time
+        let glance = core::iter::repeat_n(raised(), 3);
+        assert_eq!(feed(&mut detector, glance.chain(hold(lowered(), 1))).1, 0);
+    }
+
+    #[test]
+    fn shake() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        feed(&mut detector, hold(raised(), 1));
+        // back and forth along the forearm at about 6 Hz
+        let shaking = (0..RATE).map(|i| {
+            let x = if (i / 2) % 2 == 0 { 25.0 } else { -25.0 };
+            accel(x, 0.0, STANDARD_GRAVITY)
+        });
+        let (gestures, n) = feed(&mut detector, shaking.chain(hold(raised(), 1)));
+        assert!(n >= 1);
+        assert!(gestures[..n].iter().all(|g| *g == Some(Gesture::Shake)));
+    }
+
+    #[test]
+    fn double_tap() {
+        let tap = || core::iter::once(accel(0.0, 0.0, STANDARD_GRAVITY + 15.0));
+        let rest = |samples: usize| core::iter::repeat_n(raised(), samples);
+
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        feed(&mut detector, hold(raised(), 1));
+        let taps = tap().chain(rest(5)).chain(tap()).chain(rest(RATE));
+        let (gestures, n) = feed(&mut detector, taps);
+        assert_eq!(&gestures[..n], &[Some(Gesture::DoubleTap)]);
+
+        // the second tap comes too late
+        let taps = tap().chain(rest(2 * RATE)).chain(tap()).chain(rest(RATE));
+        assert_eq!(feed(&mut detector, taps).1, 0);
+    }
+
+    #[test]
+    fn flick() {
+        let mut detector = GestureDetector::new(GestureConfig::default());
+        assert_eq!(detector.update_angular_rate(&gyro(100.0, 0.0, 0.0)), None);
+        assert_eq!(
+            detector.update_angular_rate(&gyro(300.0, 0.0, 0.0)),
+            Some(Gesture::FlickRight)
+        );
+        // the same flick continues during the cooldown
+        assert_eq!(detector.update_angular_rate(&gyro(300.0, 0.0, 0.0)), None);
+        for _ in 0..RATE {
+            detector.update_angular_rate(&gyro(0.0, 0.0, 0.0));
+        }
+        assert_eq!(
+            detector.update_angular_rate(&gyro(-300.0, 0.0, 0.0)),
+            Some(Gesture::FlickLeft)
+        );
+
+        let mut sensitive = GestureDetector::new(GestureConfig {
+            sensitivity: 2.0,
+            ..GestureConfig::default()
+        });
+        assert_eq!(
+            sensitive.update_angular_rate(&gyro(150.0, 0.0, 0.0)),
+            Some(Gesture::FlickRight)
+        );
+    }
+}
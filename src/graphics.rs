@@ -0,0 +1,149 @@
+//! Implementation of the [embedded-graphics](https://docs.rs/embedded-graphics) `DrawTarget`
+//! trait for the display and the software framebuffer.
+//!
+//! This module is only available with the `embedded-graphics-core` feature. It allows to use
+//! all fonts, primitives and image formats of the embedded-graphics ecosystem on card10.
+//!
+//! # Example
+//! ```ignore
+//! use embedded_graphics::{
+//!     mono_font::{ascii::FONT_6X10, MonoTextStyle},
+//!     pixelcolor::Rgb565,
+//!     prelude::*,
+//!     text::Text,
+//! };
+//!
+//! let mut display = rustcardium::display::Display::open()?;
+//! Text::new("Hello card10", Point::new(10, 20), MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE))
+//!     .draw(&mut display)?;
+//! display.update()?;
+//! ```
+use super::*;
+use crate::display::Display;
+use crate::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{OriginDimensions, Point, Size};
+use embedded_graphics_core::pixelcolor::raw::RawU16;
+use embedded_graphics_core::pixelcolor::{IntoStorage, Rgb565};
+use embedded_graphics_core::primitives::Rectangle;
+use embedded_graphics_core::Pixel;
+
+impl From<Rgb565> for Color {
+    fn from(color: Rgb565) -> Self {
+        Color::from_rgb565(color.into_storage())
+    }
+}
+
+impl From<Color> for Rgb565 {
+    fn from(color: Color) -> Self {
+        Rgb565::from(RawU16::new(color.rgb565()))
+    }
+}
+
+/// Returns the coordinates of the point if it is located on the display.
+fn on_display(point: Point) -> Option<(u16, u16)> {
+    if point.x >= 0 && point.y >= 0 && point.x < i32::from(WIDTH) && point.y < i32::from(HEIGHT) {
+        Some((point.x as u16, point.y as u16))
+    } else {
+        None
+    }
+}
+
+/// Clips the rectangle to the display and returns its top left and bottom right corner.
+fn clip_to_display(area: &Rectangle) -> Option<(Point, Point)> {
+    let display = Rectangle::new(Point::zero(), Size::new(WIDTH.into(), HEIGHT.into()));
+    let area = area.intersection(&display);
+    area.bottom_right()
+        .map(|bottom_right| (area.top_left, bottom_right))
+}
+
+impl OriginDimensions for Display {
+    fn size(&self) -> Size {
+        Size::new(WIDTH.into(), HEIGHT.into())
+    }
+}
+
+/// Draws on the display using the immediate mode functions of Epicardium.
+///
+/// Single pixels are drawn with `epic_disp_pixel`, solid horizontal and vertical lines with
+/// `epic_disp_line` and all other solid areas with `epic_disp_rect`.
+/// Like with the other immediate mode functions, `Display::update()` needs to be called to show the result.
+impl DrawTarget for Display {
+    type Color = Rgb565;
+    type Error = Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<()>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some((x, y)) = on_display(point) {
+                self.pixel(x, y, color.into())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<()> {
+        if let Some((top_left, bottom_right)) = clip_to_display(area) {
+            let (xs, ys) = (top_left.x as u16, top_left.y as u16);
+            let (xe, ye) = (bottom_right.x as u16, bottom_right.y as u16);
+            if xs == xe || ys == ye {
+                self.line(xs, ys, xe, ye, color.into(), false, 1)?;
+            } else {
+                // The end coordinates of the rectangle are exclusive
+                self.rect(xs, ys, xe + 1, ye + 1, color.into(), true, 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<()> {
+        Display::clear(self, Some(color.into()))
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH.into(), HEIGHT.into())
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb565;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> core::result::Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let Some((x, y)) = on_display(point) {
+                self.set_raw_pixel(x, y, color.into_storage());
+            }
+        }
+        Ok(())
+    }
+
+    fn fill_solid(
+        &mut self,
+        area: &Rectangle,
+        color: Self::Color,
+    ) -> core::result::Result<(), Self::Error> {
+        if let Some((top_left, bottom_right)) = clip_to_display(area) {
+            let raw = color.into_storage();
+            for y in top_left.y..=bottom_right.y {
+                for x in top_left.x..=bottom_right.x {
+                    self.set_raw_pixel(x as u16, y as u16, raw);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> core::result::Result<(), Self::Error> {
+        self.fill(color.into());
+        Ok(())
+    }
+}
@@ -0,0 +1,426 @@
+//! The `interrupts` module allows to react to events signalled by Epicardium.
+//!
+//! This crate exports the interrupt service routines (`epic_isr_*`) for all interrupts of the
+//! Epicardium API and dispatches them to the handlers registered here. A handler is registered
+//! with `register()`, which unmasks the interrupt and returns a `Registration`. When the
+//! registration gets out of scope, the interrupt is masked again and the handler is removed.
+//!
+//! Handlers are called in interrupt context. They should return quickly and must not block,
+//! e.g. only set a flag or store a value which is processed by the main loop.
+//!
+//! # Example
+//! ```
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//! use rustcardium::interrupts::{self, Interrupt};
+//!
+//! static QUIT: AtomicBool = AtomicBool::new(false);
+//!
+//! fn on_ctrl_c() {
+//!     QUIT.store(true, Ordering::SeqCst);
+//! }
+//!
+//! let _ctrl_c = interrupts::register(Interrupt::CtrlC, on_ctrl_c)?;
+//! # rustcardium::mock::trigger_interrupt(1);
+//! while !QUIT.load(Ordering::SeqCst) {
+//!     // do something useful
+//! }
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::executor;
+
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::{Context, Poll};
+
+/// Number of `EPIC_INT_*` interrupts, including the reset interrupt which is handled by the runtime.
+const NUM_INTERRUPTS: usize = sys::EPIC_INT_NUM as usize;
+
+/// Interrupts of the Epicardium API which can be handled by an app.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Interrupt {
+    /// `^C` (`0x04`) was received on the serial console.
+    CtrlC,
+    /// A character was received on the serial console.
+    UartRx,
+    /// The RTC alarm triggered, see `time::Alarm`.
+    RtcAlarm,
+    /// New samples of the BHI160 accelerometer are available.
+    Bhi160Accelerometer,
+    /// New samples of the BHI160 orientation sensor are available.
+    Bhi160Orientation,
+    /// New samples of the BHI160 gyroscope are available.
+    Bhi160Gyroscope,
+}
+
+impl Interrupt {
+    /// All interrupts which can be handled by an app.
+    pub const ALL: [Interrupt; 6] = [
+        Interrupt::CtrlC,
+        Interrupt::UartRx,
+        Interrupt::RtcAlarm,
+        Interrupt::Bhi160Accelerometer,
+        Interrupt::Bhi160Orientation,
+        Interrupt::Bhi160Gyroscope,
+    ];
+
+    /// The `EPIC_INT_*` id of this interrupt.
+    pub fn id(self) -> sys::api_int_id_t {
+        match self {
+            Interrupt::CtrlC => sys::EPIC_INT_CTRL_C,
+            Interrupt::UartRx => sys::EPIC_INT_UART_RX,
+            Interrupt::RtcAlarm => sys::EPIC_INT_RTC_ALARM,
+            Interrupt::Bhi160Accelerometer => sys::EPIC_INT_BHI160_ACCELEROMETER,
+            Interrupt::Bhi160Orientation => sys::EPIC_INT_BHI160_ORIENTATION,
+            Interrupt::Bhi160Gyroscope => sys::EPIC_INT_BHI160_GYROSCOPE,
+        }
+    }
+
+    /// The interrupt with the given `EPIC_INT_*` id.
+    pub fn from_id(id: sys::api_int_id_t) -> Option<Interrupt> {
+        Interrupt::ALL.iter().cloned().find(|i| i.id() == id)
+    }
+}
+
+/// Function called when an interrupt occurs.
+enum Handler {
+    Fn(fn()),
+    Closure(&'static mut (dyn FnMut() + Send)),
+}
+
+/// Storage for the handler of an interrupt.
+///
+/// The handler is only modified while the interrupt is masked, so it is never accessed by the
+/// interrupt service routine and the main program at the same time.
+struct Slot {
+    handler: UnsafeCell<Option<Handler>>,
+    taken: AtomicBool,
+    count: AtomicU32,
+    /// True if the interrupt was unmasked with this module
+    enabled: AtomicBool,
+    /// Number of `WaitInterrupt` futures
+    waiters: AtomicU32,
+    /// True if the interrupt was unmasked by `wait()` and is masked again after the last waiter
+    restore_mask: AtomicBool,
+}
+
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    const fn new() -> Slot {
+        Slot {
+            handler: UnsafeCell::new(None),
+            taken: AtomicBool::new(false),
+            count: AtomicU32::new(0),
+            enabled: AtomicBool::new(false),
+            waiters: AtomicU32::new(0),
+            restore_mask: AtomicBool::new(false),
+        }
+    }
+}
+
+static SLOTS: [Slot; NUM_INTERRUPTS] = [
+    Slot::new(),
+    Slot::new(),
+    Slot::new(),
+    Slot::new(),
+    Slot::new(),
+    Slot::new(),
+    Slot::new(),
+];
+
+/// Removes all handlers and forgets the counts, see `mock::reset()`.
+#[cfg(feature = "mock")]
+pub(crate) fn reset() {
+    for slot in SLOTS.iter() {
+        unsafe { *slot.handler.get() = None };
+        slot.taken.store(false, Ordering::SeqCst);
+        slot.count.store(0, Ordering::SeqCst);
+        slot.enabled.store(false, Ordering::SeqCst);
+        slot.waiters.store(0, Ordering::SeqCst);
+        slot.restore_mask.store(false, Ordering::SeqCst);
+    }
+}
+
+fn slot(interrupt: Interrupt) -> &'static Slot {
+    &SLOTS[interrupt.id() as usize]
+}
+
+/// Counts the interrupt and calls its handler.
+unsafe fn dispatch(interrupt: Interrupt) {
+    let slot = slot(interrupt);
+    slot.count.fetch_add(1, Ordering::SeqCst);
+    executor::wake_all();
+    match &mut *slot.handler.get() {
+        Some(Handler::Fn(f)) => f(),
+        Some(Handler::Closure(f)) => f(),
+        None => {}
+    }
+}
+
+#[no_mangle]
+pub(crate) unsafe extern "C" fn epic_isr_ctrl_c() {
+    dispatch(Interrupt::CtrlC);
+}
+
+#[no_mangle]
+pub(crate) unsafe extern "C" fn epic_isr_uart_rx() {
+    dispatch(Interrupt::UartRx);
+}
+
+#[no_mangle]
+pub(crate) unsafe extern "C" fn epic_isr_rtc_alarm() {
+    dispatch(Interrupt::RtcAlarm);
+}
+
+#[no_mangle]
+pub(crate) unsafe extern "C" fn epic_isr_bhi160_accelerometer() {
+    dispatch(Interrupt::Bhi160Accelerometer);
+}
+
+#[no_mangle]
+pub(crate) unsafe extern "C" fn epic_isr_bhi160_orientation() {
+    dispatch(Interrupt::Bhi160Orientation);
+}
+
+#[no_mangle]
+pub(crate) unsafe extern "C" fn epic_isr_bhi160_gyroscope() {
+    dispatch(Interrupt::Bhi160Gyroscope);
+}
+
+/// Unmasks an interrupt.
+///
+/// Without a registered handler, the interrupt is only counted (see `count()`).
+pub fn enable(interrupt: Interrupt) -> Result<()> {
+    unmask(interrupt)?;
+    // the interrupt stays unmasked after pending calls of `wait()`
+    slot(interrupt).restore_mask.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Masks an interrupt, its handler is not called until the interrupt is enabled again.
+pub fn disable(interrupt: Interrupt) -> Result<()> {
+    unsafe { check(sys::epic_interrupt_disable(interrupt.id()))? };
+    slot(interrupt).enabled.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+fn unmask(interrupt: Interrupt) -> Result<()> {
+    unsafe { check(sys::epic_interrupt_enable(interrupt.id()))? };
+    slot(interrupt).enabled.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// How often an interrupt occurred since the app started, wrapping around on overflow.
+///
+/// Comparing the count with an earlier value allows to check for interrupts without a handler.
+pub fn count(interrupt: Interrupt) -> u32 {
+    slot(interrupt).count.load(Ordering::SeqCst)
+}
+
+/// True if a handler is registered for the interrupt.
+pub fn is_registered(interrupt: Interrupt) -> bool {
+    slot(interrupt).taken.load(Ordering::SeqCst)
+}
+
+#[cfg(not(feature = "mock"))]
+extern "C" {
+    fn rustcardium_sleep_while_equal(value: *const u32, current: u32);
+    fn rustcardium_mask_interrupts() -> u32;
+    fn rustcardium_restore_interrupts(primask: u32);
+}
+
+/// Runs `f` with all interrupts masked, so no handler can run in between.
+///
+/// Keep `f` short, interrupts which occur meanwhile are handled afterwards. Calls must not be
+/// nested.
+#[cfg(not(feature = "mock"))]
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    let primask = unsafe { rustcardium_mask_interrupts() };
+    let result = f();
+    unsafe { rustcardium_restore_interrupts(primask) };
+    result
+}
+
+/// Runs `f` exclusively. The mock has no real interrupts, but the tests run in several threads.
+#[cfg(feature = "mock")]
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    extern crate std;
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    f()
+}
+
+/// Puts the core to sleep until the next interrupt, unless `value` already differs from `current`.
+///
+/// The check and going to sleep are atomic with respect to interrupts, so an interrupt which
+/// changes `value` can't be missed.
+pub(crate) fn sleep_while_equal(value: &AtomicU32, current: u32) {
+    #[cfg(not(feature = "mock"))]
+    unsafe {
+        rustcardium_sleep_while_equal(value as *const AtomicU32 as *const u32, current)
+    };
+    // the mock has no real interrupts, they are triggered by the tests
+    #[cfg(feature = "mock")]
+    {
+        let _ = (value, current);
+        core::hint::spin_loop();
+    }
+}
+
+/// Future which completes when an interrupt occurs, created with `wait()`.
+pub struct WaitInterrupt {
+    interrupt: Interrupt,
+    count: u32,
+    enabled: Result<()>,
+}
+
+impl Drop for WaitInterrupt {
+    fn drop(&mut self) {
+        let slot = slot(self.interrupt);
+        if slot.waiters.fetch_sub(1, Ordering::SeqCst) == 1
+            && slot.restore_mask.swap(false, Ordering::SeqCst)
+        {
+            let _ = disable(self.interrupt);
+        }
+    }
+}
+
+impl Future for WaitInterrupt {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<()>> {
+        // the executor wakes all tasks for every interrupt, there is no need to store the waker
+        if self.enabled.is_err() {
+            Poll::Ready(self.enabled)
+        } else if count(self.interrupt) != self.count {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Waits asynchronously for an interrupt, see `executor`.
+///
+/// The interrupt is unmasked while the future exists. If it was masked before, it is masked
+/// again when the future completes or is dropped, unless it was unmasked with `enable()` or
+/// `register()` in the meantime. Interrupts which occur after calling this function complete
+/// the future, even if it is polled for the first time later on.
+///
+/// # Example
+/// ```
+/// use rustcardium::executor::block_on;
+/// use rustcardium::interrupts::{self, Interrupt};
+///
+/// block_on(async {
+///     let ctrl_c = interrupts::wait(Interrupt::CtrlC);
+/// #   rustcardium::mock::trigger_interrupt(1);
+///     ctrl_c.await?;
+///     // clean up and exit
+/// #   Ok::<(), rustcardium::Error>(())
+/// });
+/// ```
+pub fn wait(interrupt: Interrupt) -> WaitInterrupt {
+    let slot = slot(interrupt);
+    let was_enabled = slot.enabled.load(Ordering::SeqCst);
+    slot.waiters.fetch_add(1, Ordering::SeqCst);
+    let enabled = unmask(interrupt);
+    if enabled.is_ok() && !was_enabled {
+        slot.restore_mask.store(true, Ordering::SeqCst);
+    }
+    WaitInterrupt {
+        interrupt,
+        count: count(interrupt),
+        enabled,
+    }
+}
+
+/// Registers a function as handler of an interrupt and unmasks the interrupt.
+///
+/// # Arguments
+/// * `interrupt` - The interrupt to handle
+/// * `handler` - Function called in interrupt context when the interrupt occurs
+///
+/// # Returns
+/// `Error::DeviceOrResourceBusy` if there already is a handler for the interrupt.
+pub fn register(interrupt: Interrupt, handler: fn()) -> Result<Registration> {
+    Registration::new(interrupt, Handler::Fn(handler))
+}
+
+/// Registers a closure as handler of an interrupt and unmasks the interrupt.
+///
+/// Since the closure is called from the interrupt service routine, it needs to live as long
+/// as the app, e.g. in a `static`.
+///
+/// # Arguments
+/// * `interrupt` - The interrupt to handle
+/// * `handler` - Closure called in interrupt context when the interrupt occurs
+///
+/// # Returns
+/// `Error::DeviceOrResourceBusy` if there already is a handler for the interrupt.
+pub fn register_with(
+    interrupt: Interrupt,
+    handler: &'static mut (dyn FnMut() + Send),
+) -> Result<Registration> {
+    Registration::new(interrupt, Handler::Closure(handler))
+}
+
+/// A registered interrupt handler.
+///
+/// The interrupt is masked and the handler removed when the registration gets out of scope.
+#[must_use = "the interrupt handler is removed when the registration is dropped"]
+pub struct Registration {
+    interrupt: Interrupt,
+}
+
+impl Registration {
+    fn new(interrupt: Interrupt, handler: Handler) -> Result<Registration> {
+        let slot = slot(interrupt);
+        if slot.taken.swap(true, Ordering::SeqCst) {
+            return Err(Error::DeviceOrResourceBusy);
+        }
+        if let Err(e) = disable(interrupt) {
+            slot.taken.store(false, Ordering::SeqCst);
+            return Err(e);
+        }
+        unsafe {
+            *slot.handler.get() = Some(handler);
+        }
+        // on errors, the registration is dropped and the handler removed again
+        let registration = Registration { interrupt };
+        enable(interrupt)?;
+        Ok(registration)
+    }
+
+    /// The handled interrupt.
+    pub fn interrupt(&self) -> Interrupt {
+        self.interrupt
+    }
+
+    /// Unmasks the interrupt again after `disable()`.
+    pub fn enable(&self) -> Result<()> {
+        enable(self.interrupt)
+    }
+
+    /// Temporarily masks the interrupt without removing the handler.
+    pub fn disable(&self) -> Result<()> {
+        disable(self.interrupt)
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let slot = slot(self.interrupt);
+        // if the interrupt can't be masked, the handler may still be called, so it is kept and
+        // the slot stays taken
+        if disable(self.interrupt).is_ok() {
+            unsafe {
+                *slot.handler.get() = None;
+            }
+            slot.taken.store(false, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! Traits for reading, writing and seeking, modelled after `std::io` but without allocations.
+use super::*;
+
+/// Source of bytes, like `std::io::Read`.
+pub trait Read {
+    /// Reads bytes into the buffer and returns how many bytes were read.
+    ///
+    /// A return value of 0 means the end of the source was reached (or the buffer is empty).
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Reads exactly enough bytes to fill the buffer.
+    ///
+    /// # Returns
+    /// `Error::UnexpectedEof` if the end of the source was reached before the buffer was filled.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf)? {
+                0 => return Err(Error::UnexpectedEof),
+                n => buf = &mut buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads from the front of the slice, which is advanced past the read bytes.
+impl Read for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(self.len());
+        let (head, tail) = self.split_at(len);
+        buf[..len].copy_from_slice(head);
+        *self = tail;
+        Ok(len)
+    }
+}
+
+/// Sink for bytes, like `std::io::Write`.
+pub trait Write {
+    /// Writes bytes from the buffer and returns how many bytes were written.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Writes out all buffered data.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Writes the whole buffer.
+    ///
+    /// # Returns
+    /// `Error::WriteZero` if the sink stopped accepting data.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf)? {
+                0 => return Err(Error::WriteZero),
+                n => buf = &buf[n..],
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Position to seek to, relative to the start, the end or the current position.
+///
+/// Since FAT file systems can't have files larger than 4 GiB, positions are 32 bit values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u32),
+    End(i32),
+    Current(i32),
+}
+
+/// A cursor which can be moved, like `std::io::Seek`.
+pub trait Seek {
+    /// Moves the cursor and returns the new position from the start.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u32>;
+
+    /// Moves the cursor back to the start.
+    fn rewind(&mut self) -> Result<()> {
+        self.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    /// The current position from the start.
+    fn stream_position(&mut self) -> Result<u32> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
@@ -0,0 +1,317 @@
+//! The `leds` module allows to control the RGB LEDs, the rocket LEDs and the flashlight of card10.
+//!
+//! Setting LEDs one by one with `set()` is slow. When changing several LEDs at once,
+//! prepare them with `prep()` and apply all changes with `update()`, or use a `Batch`.
+//!
+//! # Example
+//! ```
+//! use rustcardium::leds::{self, Led};
+//! use rustcardium::Color;
+//!
+//! let mut batch = leds::Batch::new();
+//! for i in 0..11 {
+//!     batch.set(Led::top(i).unwrap(), Color { r: 0, g: 0, b: 20 * i });
+//! }
+//! batch.set(Led::BOTTOM_LEFT, Color { r: 255, g: 0, b: 0 });
+//! batch.update();
+//! ```
+use super::*;
+
+pub mod animation;
+
+pub use animation::Animation;
+
+/// Number of RGB LEDs (11 on the top and 4 ambient LEDs at the bottom).
+pub const NUM_LEDS: usize = 15;
+
+/// Number of RGB LEDs on the top.
+pub const NUM_TOP_LEDS: u8 = 11;
+
+/// One of the 15 RGB LEDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Led(u8);
+
+impl Led {
+    /// Ambient LED at the bottom left.
+    pub const BOTTOM_LEFT: Led = Led(11);
+    /// Ambient LED at the bottom right.
+    pub const BOTTOM_RIGHT: Led = Led(12);
+    /// Ambient LED at the top right.
+    pub const TOP_RIGHT: Led = Led(13);
+    /// Ambient LED at the top left.
+    pub const TOP_LEFT: Led = Led(14);
+
+    /// One of the LEDs on the top.
+    ///
+    /// # Arguments
+    /// * `index` - Index of the LED, 0 <= index <= 10, starting from the left
+    pub fn top(index: u8) -> Result<Led> {
+        if index < NUM_TOP_LEDS {
+            Ok(Led(index))
+        } else {
+            Err(Error::InvalidLed)
+        }
+    }
+
+    /// Get an LED by its Epicardium index.
+    ///
+    /// # Arguments
+    /// * `index` - 0-10 are the LEDs on the top and 11-14 are the 4 ambient LEDs at the bottom.
+    pub fn from_index(index: u8) -> Result<Led> {
+        if usize::from(index) < NUM_LEDS {
+            Ok(Led(index))
+        } else {
+            Err(Error::InvalidLed)
+        }
+    }
+
+    /// The Epicardium index of this LED.
+    pub fn index(self) -> u8 {
+        self.0
+    }
+
+    /// True if this is one of the 4 ambient LEDs at the bottom.
+    pub fn is_bottom(self) -> bool {
+        self.0 >= NUM_TOP_LEDS
+    }
+
+    /// Iterates over all 15 LEDs in the order of their index.
+    pub fn all() -> impl Iterator<Item = Led> {
+        (0..NUM_LEDS as u8).map(Led)
+    }
+}
+
+/// One of the three rocket LEDs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rocket {
+    /// Blue LED on the left.
+    Blue = 0,
+    /// Yellow LED on the top.
+    Yellow = 1,
+    /// Green LED on the right.
+    Green = 2,
+}
+
+/// Color channel for gamma tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Red = 0,
+    Green = 1,
+    Blue = 2,
+}
+
+/// Representation of a color in HSV format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hsv {
+    /// Hue, 0 <= h < 360
+    pub h: f32,
+    /// Saturation, 0 <= s <= 1
+    pub s: f32,
+    /// Value/Brightness, 0 <= v <= 1
+    pub v: f32,
+}
+
+impl From<Hsv> for Color {
+    fn from(col: Hsv) -> Self {
+        let h = ((col.h % 360.0) + 360.0) % 360.0 / 60.0;
+        let s = col.s.clamp(0.0, 1.0);
+        let v = col.v.clamp(0.0, 1.0);
+
+        let c = v * s;
+        let x = c * (1.0 - ((h % 2.0) - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = v - c;
+        let to_u8 = |f: f32| ((f + m) * 255.0 + 0.5) as u8;
+        Color {
+            r: to_u8(r),
+            g: to_u8(g),
+            b: to_u8(b),
+        }
+    }
+}
+
+impl From<Color> for Hsv {
+    fn from(col: Color) -> Self {
+        let r = f32::from(col.r) / 255.0;
+        let g = f32::from(col.g) / 255.0;
+        let b = f32::from(col.b) / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let delta = max - min;
+
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        Hsv {
+            h: if h < 0.0 { h + 360.0 } else { h },
+            s: if max == 0.0 { 0.0 } else { delta / max },
+            v: max,
+        }
+    }
+}
+
+/// Sets one LED to a color.
+///
+/// This function is rather slow when setting multiple LEDs, use `set_all()`,
+/// `prep()` and `update()` or a `Batch` instead.
+pub fn set(led: Led, col: Color) {
+    unsafe { sys::epic_leds_set(led.0.into(), col.r, col.g, col.b) }
+}
+
+/// Sets one LED to a color given in HSV format.
+///
+/// This function is rather slow when setting multiple LEDs, use `set_all_hsv()`,
+/// `prep_hsv()` and `update()` or a `Batch` instead.
+pub fn set_hsv(led: Led, col: Hsv) {
+    unsafe { sys::epic_leds_set_hsv(led.0.into(), col.h, col.s, col.v) }
+}
+
+/// Sets the first LEDs to the given colors, the remaining ones are not modified.
+///
+/// # Arguments
+/// * `colors` - Colors of the LEDs, starting with index 0. Colors for more than 15 LEDs are ignored.
+pub fn set_all(colors: &[Color]) {
+    let mut pattern = [[0_u8; 3]; NUM_LEDS];
+    let len = colors.len().min(NUM_LEDS);
+    for (p, col) in pattern.iter_mut().zip(colors) {
+        *p = [col.r, col.g, col.b];
+    }
+    unsafe { sys::epic_leds_set_all(pattern.as_mut_ptr() as *mut u8, len as u8) }
+}
+
+/// Sets the first LEDs to the given HSV colors, the remaining ones are not modified.
+///
+/// # Arguments
+/// * `colors` - Colors of the LEDs, starting with index 0. Colors for more than 15 LEDs are ignored.
+pub fn set_all_hsv(colors: &[Hsv]) {
+    let mut pattern = [[0_f32; 3]; NUM_LEDS];
+    let len = colors.len().min(NUM_LEDS);
+    for (p, col) in pattern.iter_mut().zip(colors) {
+        *p = [col.h, col.s, col.v];
+    }
+    unsafe { sys::epic_leds_set_all_hsv(pattern.as_mut_ptr() as *mut f32, len as u8) }
+}
+
+/// Prepares one LED to be set to a color, use `update()` to apply the changes.
+pub fn prep(led: Led, col: Color) {
+    unsafe { sys::epic_leds_prep(led.0.into(), col.r, col.g, col.b) }
+}
+
+/// Prepares one LED to be set to a HSV color, use `update()` to apply the changes.
+pub fn prep_hsv(led: Led, col: Hsv) {
+    unsafe { sys::epic_leds_prep_hsv(led.0.into(), col.h, col.s, col.v) }
+}
+
+/// Applies all changes previously made with `prep()` or `prep_hsv()`.
+pub fn update() {
+    unsafe { sys::epic_leds_update() }
+}
+
+/// Sets all LEDs to the same color.
+pub fn clear_all(col: Color) {
+    unsafe { sys::epic_leds_clear_all(col.r, col.g, col.b) }
+}
+
+/// Sets the global brightness of the top LEDs using the current limiter.
+///
+/// # Arguments
+/// * `value` - Global brightness, 1 <= value <= 8 (default 1). Values outside this range are clamped.
+pub fn dim_top(value: u8) {
+    unsafe { sys::epic_leds_dim_top(value.clamp(1, 8)) }
+}
+
+/// Sets the global brightness of the bottom LEDs using the current limiter.
+///
+/// # Arguments
+/// * `value` - Global brightness, 1 <= value <= 8 (default 8). Values outside this range are clamped.
+pub fn dim_bottom(value: u8) {
+    unsafe { sys::epic_leds_dim_bottom(value.clamp(1, 8)) }
+}
+
+/// Enables or disables the powersave mode of the LEDs (enabled by default).
+///
+/// Powersave introduces delays of ~10µs, so it can be disabled for high speed applications like POV.
+pub fn set_powersave(eco: bool) {
+    unsafe { sys::epic_leds_set_powersave(eco) }
+}
+
+/// Sets the brightness of one of the rocket LEDs.
+///
+/// # Arguments
+/// * `rocket` - Which rocket LED to set
+/// * `value` - Brightness, 0 <= value <= 31. Larger values are clamped.
+pub fn set_rocket(rocket: Rocket, value: u8) {
+    unsafe { sys::epic_leds_set_rocket(rocket as cty::c_int, value.min(31)) }
+}
+
+/// Turns the bright side LED (flashlight) on or off.
+pub fn set_flashlight(on: bool) {
+    unsafe { sys::epic_set_flashlight(on) }
+}
+
+/// Sets the gamma lookup table for one of the color channels.
+///
+/// # Arguments
+/// * `channel` - Color channel the table applies to
+/// * `table` - Lookup table, maps each PWM value to a corrected one
+pub fn set_gamma_table(channel: Channel, table: &[u8; 256]) {
+    let mut table = *table;
+    unsafe { sys::epic_leds_set_gamma_table(channel as u8, table.as_mut_ptr()) }
+}
+
+/// Collects changes for several LEDs and applies them at once.
+///
+/// No API call is made before `update()` is called, which then prepares only the
+/// LEDs that were set and applies them with a single bus transfer.
+#[must_use = "changes of a batch are only applied when calling update()"]
+#[derive(Clone, Default)]
+pub struct Batch {
+    colors: [Option<Color>; NUM_LEDS],
+}
+
+impl Batch {
+    /// Creates an empty batch.
+    pub fn new() -> Batch {
+        Batch::default()
+    }
+
+    /// Sets the color of an LED in this batch.
+    pub fn set(&mut self, led: Led, col: Color) -> &mut Batch {
+        self.colors[usize::from(led.0)] = Some(col);
+        self
+    }
+
+    /// Sets all LEDs in this batch to the same color.
+    pub fn fill(&mut self, col: Color) -> &mut Batch {
+        self.colors = [Some(col); NUM_LEDS];
+        self
+    }
+
+    /// The color set for an LED in this batch.
+    pub fn get(&self, led: Led) -> Option<Color> {
+        self.colors[usize::from(led.0)]
+    }
+
+    /// Prepares all LEDs set in this batch and applies the changes.
+    pub fn update(self) {
+        for (led, col) in Led::all().zip(self.colors.iter()) {
+            if let Some(col) = col {
+                prep(led, *col);
+            }
+        }
+        update();
+    }
+}
@@ -0,0 +1,526 @@
+//! Keyframe based animations for the RGB LEDs.
+//!
+//! An `Animation` consists of one `Track` per LED. Each track is a timeline of keyframes,
+//! the colors in between are interpolated either in RGB or HSV space using an `Easing` function.
+//! Playing back an animation prepares all animated LEDs with `epic_leds_prep` (or `epic_leds_prep_hsv`)
+//! and applies them with a single `epic_leds_update` for each frame.
+//!
+//! The `presets` module contains ready-made animations like a rainbow chase or a breathing effect.
+//!
+//! # Example
+//! ```
+//! use rustcardium::leds::animation::{presets, FrameClock};
+//!
+//! let mut animation = presets::rainbow_chase(3000);
+//! let mut clock = FrameClock::new(30);
+//! animation.start();
+//! # rustcardium::mock::with(|s| s.rtc_auto_advance = 10);
+//! # let mut frames = 0;
+//! loop {
+//!     clock.wait();
+//!     animation.tick();
+//! #   frames += 1;
+//! #   if frames > 10 { break; }
+//! }
+//! ```
+use super::*;
+
+use crate::time::{Duration, Instant};
+use arrayvec::ArrayVec;
+
+/// Maximum number of keyframes per track.
+pub const MAX_KEYFRAMES: usize = 16;
+
+/// Milliseconds passed since `started`, which are not affected by changes of the RTC.
+fn elapsed_millis(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
+
+/// How the color changes from one keyframe to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    /// Constant speed.
+    Linear,
+    /// Starts slow and accelerates.
+    EaseIn,
+    /// Starts fast and decelerates.
+    EaseOut,
+    /// Starts and ends slow.
+    EaseInOut,
+    /// Keeps the color of the keyframe until the next keyframe is reached.
+    Step,
+}
+
+impl Easing {
+    /// Maps the linear progress `t` (0 <= t <= 1) between two keyframes to the eased progress.
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Step => 0.0,
+        }
+    }
+}
+
+/// Color space the colors between two keyframes are interpolated in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    Rgb,
+    /// Interpolates hue, saturation and value. The hue is not wrapped around, so
+    /// interpolating from 0 to 360 runs through the whole color wheel.
+    Hsv,
+}
+
+/// What happens when the end of an animation is reached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Playback {
+    /// Play the animation once and keep the last frame.
+    Once,
+    /// Start again from the beginning.
+    Loop,
+    /// Play the animation backwards, then forwards again and so on.
+    PingPong,
+}
+
+/// Color of a keyframe.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyColor {
+    Rgb(Color),
+    Hsv(Hsv),
+}
+
+impl KeyColor {
+    fn to_rgb(self) -> Color {
+        match self {
+            KeyColor::Rgb(col) => col,
+            KeyColor::Hsv(col) => col.into(),
+        }
+    }
+
+    fn to_hsv(self) -> Hsv {
+        match self {
+            KeyColor::Rgb(col) => col.into(),
+            KeyColor::Hsv(col) => col,
+        }
+    }
+}
+
+impl From<Color> for KeyColor {
+    fn from(col: Color) -> Self {
+        KeyColor::Rgb(col)
+    }
+}
+
+impl From<Hsv> for KeyColor {
+    fn from(col: Hsv) -> Self {
+        KeyColor::Hsv(col)
+    }
+}
+
+/// A color at a point in time of a track.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    /// Time in milliseconds from the start of the animation
+    pub time: u32,
+    pub color: KeyColor,
+    /// Easing used to get from this keyframe to the next one
+    pub easing: Easing,
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+/// Timeline of keyframes for a single LED.
+#[derive(Clone, Debug)]
+pub struct Track {
+    keyframes: ArrayVec<[Keyframe; MAX_KEYFRAMES]>,
+    space: ColorSpace,
+    offset: u32,
+}
+
+impl Track {
+    /// Creates an empty track which interpolates in the given color space.
+    pub fn new(space: ColorSpace) -> Track {
+        Track {
+            keyframes: ArrayVec::new(),
+            space,
+            offset: 0,
+        }
+    }
+
+    /// Adds a keyframe, keyframes must be added in the order of their time.
+    ///
+    /// # Arguments
+    /// * `time` - Time in milliseconds from the start of the animation
+    /// * `color` - Color at this time, either a `Color` or `Hsv`
+    /// * `easing` - Easing used to get from this keyframe to the next one
+    ///
+    /// # Returns
+    /// An error if the track already has `MAX_KEYFRAMES` keyframes or the time is before the last keyframe.
+    pub fn keyframe<C: Into<KeyColor>>(
+        &mut self,
+        time: u32,
+        color: C,
+        easing: Easing,
+    ) -> Result<&mut Track> {
+        if self
+            .keyframes
+            .last()
+            .map(|k| k.time > time)
+            .unwrap_or(false)
+        {
+            return Err(Error::InvalidKeyframe);
+        }
+        self.keyframes
+            .try_push(Keyframe {
+                time,
+                color: color.into(),
+                easing,
+            })
+            .map_err(|_| Error::TooManyKeyframes)?;
+        Ok(self)
+    }
+
+    /// Shifts the track in time, e.g. to let the same effect start later on neighbouring LEDs.
+    ///
+    /// # Arguments
+    /// * `offset` - Time in milliseconds the track is ahead of the animation
+    pub fn offset(&mut self, offset: u32) -> &mut Track {
+        self.offset = offset;
+        self
+    }
+
+    /// Time of the last keyframe.
+    pub fn duration(&self) -> u32 {
+        self.keyframes.last().map(|k| k.time).unwrap_or(0)
+    }
+
+    /// Interpolated color at the given time of the track.
+    pub fn color_at(&self, time: u32) -> Option<KeyColor> {
+        let first = self.keyframes.first()?;
+        if time <= first.time {
+            return Some(first.color);
+        }
+        for segment in self.keyframes.windows(2) {
+            let (from, to) = (segment[0], segment[1]);
+            if time >= to.time {
+                continue;
+            }
+            let t = from
+                .easing
+                .apply((time - from.time) as f32 / (to.time - from.time) as f32);
+            return Some(match self.space {
+                ColorSpace::Rgb => {
+                    let (a, b) = (from.color.to_rgb(), to.color.to_rgb());
+                    let mix = |x: u8, y: u8| (lerp(f32::from(x), f32::from(y), t) + 0.5) as u8;
+                    KeyColor::Rgb(Color {
+                        r: mix(a.r, b.r),
+                        g: mix(a.g, b.g),
+                        b: mix(a.b, b.b),
+                    })
+                }
+                ColorSpace::Hsv => {
+                    let (a, b) = (from.color.to_hsv(), to.color.to_hsv());
+                    KeyColor::Hsv(Hsv {
+                        h: lerp(a.h, b.h, t) % 360.0,
+                        s: lerp(a.s, b.s, t),
+                        v: lerp(a.v, b.v, t),
+                    })
+                }
+            });
+        }
+        self.keyframes.last().map(|k| k.color)
+    }
+}
+
+/// A set of tracks for the LEDs which are played back together.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    tracks: [Option<Track>; NUM_LEDS],
+    playback: Playback,
+    duration: u32,
+    started: Option<Instant>,
+}
+
+impl Animation {
+    /// Creates an animation without any tracks.
+    pub fn new(playback: Playback) -> Animation {
+        Animation {
+            tracks: Default::default(),
+            playback,
+            duration: 0,
+            started: None,
+        }
+    }
+
+    /// Sets the track for an LED, replacing the previous one.
+    ///
+    /// The duration of the animation is extended to the duration of the track if necessary.
+    pub fn track(&mut self, led: Led, track: Track) -> &mut Animation {
+        self.duration = self.duration.max(track.duration());
+        self.tracks[usize::from(led.index())] = Some(track);
+        self
+    }
+
+    /// Sets the duration of the animation, by default this is the time of the last keyframe of all tracks.
+    pub fn duration(&mut self, duration: u32) -> &mut Animation {
+        self.duration = duration;
+        self
+    }
+
+    /// Starts (or restarts) the animation at the current time.
+    pub fn start(&mut self) {
+        self.started = Some(Instant::now());
+    }
+
+    /// Stops the animation, the LEDs keep their current color.
+    pub fn stop(&mut self) {
+        self.started = None;
+    }
+
+    /// True if the animation has been started and not reached its end yet.
+    /// Animations which loop or ping-pong are running until stopped.
+    pub fn is_running(&self) -> bool {
+        match self.started {
+            Some(started) => {
+                self.playback != Playback::Once
+                    || elapsed_millis(started) <= u64::from(self.duration)
+            }
+            None => false,
+        }
+    }
+
+    /// Maps the time since the start of the animation to the position on the timeline.
+    fn position(&self, elapsed: u64) -> u32 {
+        let duration = u64::from(self.duration);
+        if duration == 0 {
+            return 0;
+        }
+        let position = match self.playback {
+            Playback::Once => elapsed.min(duration),
+            Playback::Loop => elapsed % duration,
+            Playback::PingPong => {
+                let position = elapsed % (2 * duration);
+                if position > duration {
+                    2 * duration - position
+                } else {
+                    position
+                }
+            }
+        };
+        position as u32
+    }
+
+    /// Colors of all LEDs at the given time since the start of the animation.
+    ///
+    /// LEDs without a track are `None`.
+    pub fn render(&self, elapsed: u64) -> [Option<KeyColor>; NUM_LEDS] {
+        let mut colors = [None; NUM_LEDS];
+        for (color, track) in colors.iter_mut().zip(self.tracks.iter()) {
+            if let Some(track) = track {
+                let position = self.position(elapsed + u64::from(track.offset));
+                *color = track.color_at(position);
+            }
+        }
+        colors
+    }
+
+    /// Shows the frame for the given time since the start of the animation.
+    pub fn show(&self, elapsed: u64) {
+        for (led, color) in Led::all().zip(self.render(elapsed).iter()) {
+            match color {
+                Some(KeyColor::Rgb(col)) => prep(led, *col),
+                Some(KeyColor::Hsv(col)) => prep_hsv(led, *col),
+                None => {}
+            }
+        }
+        update();
+    }
+
+    /// Shows the frame for the current time, does nothing if the animation was not started.
+    ///
+    /// # Returns
+    /// True if the animation is still running.
+    pub fn tick(&mut self) -> bool {
+        if let Some(started) = self.started {
+            let elapsed = elapsed_millis(started);
+            self.show(elapsed);
+            if self.playback == Playback::Once && elapsed > u64::from(self.duration) {
+                self.started = None;
+            }
+        }
+        self.started.is_some()
+    }
+}
+
+/// Limits the frame rate of an animation loop.
+pub struct FrameClock {
+    interval: Duration,
+    next: Option<Instant>,
+}
+
+impl FrameClock {
+    /// Creates a clock with the given number of frames per second.
+    pub fn new(fps: u16) -> FrameClock {
+        FrameClock {
+            interval: Duration::from_millis(1000 / u64::from(fps.max(1))),
+            next: None,
+        }
+    }
+
+    /// Waits until the next frame is due.
+    ///
+    /// If the frame is already late, e.g. because drawing took too long, this returns immediately
+    /// and the next frame is scheduled relative to the current time, so frames are skipped.
+    pub fn wait(&mut self) {
+        let mut current = Instant::now();
+        if let Some(next) = self.next {
+            while current < next {
+                current = Instant::now();
+            }
+        }
+        let next = self.next.unwrap_or(current) + self.interval;
+        self.next = Some(if next <= current {
+            current + self.interval
+        } else {
+            next
+        });
+    }
+}
+
+/// Ready-made animations.
+pub mod presets {
+    use super::*;
+
+    fn top_leds() -> impl Iterator<Item = Led> {
+        Led::all().filter(|led| !led.is_bottom())
+    }
+
+    /// A rainbow running over the top LEDs.
+    ///
+    /// # Arguments
+    /// * `period` - Time in milliseconds for the rainbow to run through all colors once
+    pub fn rainbow_chase(period: u32) -> Animation {
+        let mut animation = Animation::new(Playback::Loop);
+        for led in top_leds() {
+            let mut track = Track::new(ColorSpace::Hsv);
+            track
+                .keyframe(
+                    0,
+                    Hsv {
+                        h: 0.0,
+                        s: 1.0,
+                        v: 1.0,
+                    },
+                    Easing::Linear,
+                )
+                .and_then(|t| {
+                    t.keyframe(
+                        period,
+                        Hsv {
+                            h: 360.0,
+                            s: 1.0,
+                            v: 1.0,
+                        },
+                        Easing::Linear,
+                    )
+                })
+                .expect("two keyframes always fit into a track");
+            track.offset(period / u32::from(NUM_TOP_LEDS) * u32::from(led.index()));
+            animation.track(led, track);
+        }
+        animation
+    }
+
+    /// All LEDs slowly fading in and out.
+    ///
+    /// # Arguments
+    /// * `color` - Color at full brightness
+    /// * `period` - Time in milliseconds for one breath (fading in and out)
+    pub fn breathing(color: Color, period: u32) -> Animation {
+        let mut animation = Animation::new(Playback::PingPong);
+        for led in Led::all() {
+            let mut track = Track::new(ColorSpace::Rgb);
+            track
+                .keyframe(0, Color { r: 0, g: 0, b: 0 }, Easing::EaseInOut)
+                .and_then(|t| t.keyframe(period / 2, color, Easing::EaseInOut))
+                .expect("two keyframes always fit into a track");
+            animation.track(led, track);
+        }
+        animation
+    }
+
+    /// A bright spot with a fading tail running over the top LEDs.
+    ///
+    /// # Arguments
+    /// * `color` - Color of the head of the comet
+    /// * `period` - Time in milliseconds for the comet to run over all LEDs once
+    pub fn comet(color: Color, period: u32) -> Animation {
+        let mut animation = Animation::new(Playback::Loop);
+        let step = period / u32::from(NUM_TOP_LEDS);
+        let black = Color { r: 0, g: 0, b: 0 };
+        for led in top_leds() {
+            let mut track = Track::new(ColorSpace::Rgb);
+            track
+                .keyframe(0, color, Easing::EaseOut)
+                .and_then(|t| t.keyframe(step * 4, black, Easing::Step))
+                .and_then(|t| t.keyframe(period, black, Easing::Step))
+                .expect("three keyframes always fit into a track");
+            // the LED is lit when the comet arrives at its position
+            let position = u32::from(NUM_TOP_LEDS - 1 - led.index());
+            track.offset(step * position);
+            animation.track(led, track);
+        }
+        animation
+    }
+
+    /// Flickering in red, orange and yellow on all LEDs.
+    ///
+    /// # Arguments
+    /// * `seed` - Seed for the random flickering, e.g. from `epic_trng_read`
+    pub fn fire(seed: u32) -> Animation {
+        const PERIOD: u32 = 2000;
+        const FLICKERS: u32 = 8;
+
+        let mut state = seed | 1;
+        let mut random = move |max: u32| {
+            // linear congruential generator, good enough for flickering LEDs
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) % max
+        };
+
+        let mut animation = Animation::new(Playback::Loop);
+        for led in Led::all() {
+            let mut track = Track::new(ColorSpace::Rgb);
+            let first = Color {
+                r: 255,
+                g: random(100) as u8,
+                b: 0,
+            };
+            track
+                .keyframe(0, first, Easing::EaseInOut)
+                .expect("a track has space for a keyframe");
+            for i in 1..FLICKERS {
+                let time = i * PERIOD / FLICKERS + random(PERIOD / FLICKERS / 2);
+                let color = Color {
+                    r: 155 + random(100) as u8,
+                    g: random(120) as u8,
+                    b: random(10) as u8,
+                };
+                track
+                    .keyframe(time, color, Easing::EaseInOut)
+                    .expect("the number of flickers is below MAX_KEYFRAMES");
+            }
+            // end with the first color so the loop is seamless
+            track
+                .keyframe(PERIOD, first, Easing::EaseInOut)
+                .expect("the number of flickers is below MAX_KEYFRAMES");
+            animation.track(led, track);
+        }
+        animation
+    }
+}
@@ -1,17 +1,38 @@
 #![no_std]
-#![feature(global_asm)]
+#![cfg_attr(not(feature = "mock"), feature(global_asm))]
 
 pub mod bhi160;
+pub mod bme680;
 pub mod buttons;
+pub mod chart;
 pub mod display;
+pub mod events;
+pub mod executor;
+pub mod framebuffer;
+pub mod fs;
+pub mod gesture;
+#[cfg(feature = "embedded-graphics-core")]
+pub mod graphics;
+pub mod interrupts;
+pub mod io;
+pub mod leds;
+pub mod light_sensor;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod motion;
 pub mod os;
+pub mod power;
+pub mod recorder;
 mod sys;
+pub mod time;
 pub mod uart;
 
 use arrayvec::ArrayString;
 
+#[cfg(not(feature = "mock"))]
 use panic_abort as _;
 
+#[cfg(not(feature = "mock"))]
 global_asm!(include_str!("crt.s"));
 
 #[macro_export]
@@ -27,6 +48,7 @@ macro_rules! main {
     };
 }
 
+#[cfg(not(feature = "mock"))]
 #[link_section = ".text.boot"]
 #[no_mangle]
 pub unsafe extern "C" fn Reset_Handler() -> ! {
@@ -52,6 +74,7 @@ pub unsafe extern "C" fn Reset_Handler() -> ! {
 pub const UART: uart::Uart = uart::Uart;
 
 /// Representation of a RGB color value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub g: u8,
@@ -59,11 +82,26 @@ pub struct Color {
 }
 
 impl Color {
-    fn rgb565(self) -> u16 {
-        let raw = ((u16::from(self.r) & 0xF8) << 8)
-            | ((u16::from(self.g) & 0xFA) << 3)
-            | (u16::from(self.b) & 0xF8);
-        raw
+    /// Converts the color to the RGB565 format used by the display.
+    pub fn rgb565(self) -> u16 {
+        ((u16::from(self.r) & 0xF8) << 8)
+            | ((u16::from(self.g) & 0xFC) << 3)
+            | (u16::from(self.b) >> 3)
+    }
+
+    /// Creates a color from a RGB565 value.
+    ///
+    /// The missing lower bits of each component are filled with the upper bits,
+    /// so that e.g. white stays white.
+    pub fn from_rgb565(raw: u16) -> Color {
+        let r = ((raw >> 11) & 0x1F) as u8;
+        let g = ((raw >> 5) & 0x3F) as u8;
+        let b = (raw & 0x1F) as u8;
+        Color {
+            r: (r << 3) | (r >> 2),
+            g: (g << 2) | (g >> 4),
+            b: (b << 3) | (b >> 2),
+        }
     }
 }
 
@@ -78,14 +116,113 @@ fn create_nullterminated_str(text: &str) -> ArrayString<[u8; 1024]> {
 }
 
 /// Custom error variants for Epicardium.
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Error {
     DisplayClosed,
     OutsideDisplay,
+    /// `EBUSY`
     DeviceOrResourceBusy,
+    /// `ENOENT`
     FileNotFound,
+    /// `ENOEXEC`
     FileNotInLoadableFormat,
+    InvalidLed,
+    InvalidKeyframe,
+    TooManyKeyframes,
+    TooManyTasks,
+    UnsupportedSensor,
+    SensorBusy,
+    /// `EPERM`
+    OperationNotPermitted,
+    /// `EIO`
+    IoError,
+    /// `EBADF`
+    BadFileDescriptor,
+    /// `EAGAIN`
+    TryAgain,
+    /// `ENOMEM`
+    OutOfMemory,
+    /// `EACCES`
+    PermissionDenied,
+    /// `EFAULT`
+    BadAddress,
+    /// `EEXIST`
+    FileExists,
+    /// `ENODEV`
+    NoSuchDevice,
+    /// `ENOTDIR`
+    NotADirectory,
+    /// `EISDIR`
+    IsADirectory,
+    /// `EINVAL`
+    InvalidArgument,
+    /// `ENFILE`
+    TooManyOpenFilesInSystem,
+    /// `EMFILE`
+    TooManyOpenFiles,
+    /// `ENOSPC`
+    NoSpaceLeft,
+    /// `ESPIPE`
+    IllegalSeek,
+    /// `EROFS`
+    ReadOnlyFileSystem,
+    /// `ENODATA`
+    NoData,
+    /// `ENOTEMPTY`
+    DirectoryNotEmpty,
+    /// `ENAMETOOLONG`
+    NameTooLong,
+    /// `ETIMEDOUT`
+    TimedOut,
+    /// The end of a file was reached before a buffer could be filled.
+    UnexpectedEof,
+    /// Writing returned without writing any data.
+    WriteZero,
     UnknownError,
 }
 
+impl Error {
+    /// Maps an errno value as returned by the Epicardium API (positive or negative) to an error.
+    pub fn from_errno(errno: i32) -> Error {
+        match errno.wrapping_abs() as u32 {
+            sys::EPERM => Error::OperationNotPermitted,
+            sys::ENOENT => Error::FileNotFound,
+            sys::EIO => Error::IoError,
+            sys::ENOEXEC => Error::FileNotInLoadableFormat,
+            sys::EBADF => Error::BadFileDescriptor,
+            sys::EAGAIN => Error::TryAgain,
+            sys::ENOMEM => Error::OutOfMemory,
+            sys::EACCES => Error::PermissionDenied,
+            sys::EFAULT => Error::BadAddress,
+            sys::EBUSY => Error::DeviceOrResourceBusy,
+            sys::EEXIST => Error::FileExists,
+            sys::ENODEV => Error::NoSuchDevice,
+            sys::ENOTDIR => Error::NotADirectory,
+            sys::EISDIR => Error::IsADirectory,
+            sys::EINVAL => Error::InvalidArgument,
+            sys::ENFILE => Error::TooManyOpenFilesInSystem,
+            sys::EMFILE => Error::TooManyOpenFiles,
+            sys::ENOSPC => Error::NoSpaceLeft,
+            sys::ESPIPE => Error::IllegalSeek,
+            sys::EROFS => Error::ReadOnlyFileSystem,
+            sys::ENODATA => Error::NoData,
+            sys::ENOTEMPTY => Error::DirectoryNotEmpty,
+            sys::ENAMETOOLONG => Error::NameTooLong,
+            sys::ETIMEDOUT => Error::TimedOut,
+            _ => Error::UnknownError,
+        }
+    }
+}
+
+/// Converts the return value of an Epicardium function into a `Result`.
+///
+/// Negative values are errno values, all other values are returned as they are.
+fn check(result: cty::c_int) -> Result<cty::c_int> {
+    if result < 0 {
+        Err(Error::from_errno(result))
+    } else {
+        Ok(result)
+    }
+}
+
 pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,390 @@
+//! Ambient light sensor of card10.
+//!
+//! The sensor runs while there is a `LightSensor` handle, several handles share it. Besides the
+//! raw value, a handle smoothes the readings with a `Smoothing` filter, approximates the
+//! illuminance in lux and classifies the ambient light as a `LightLevel`. The thresholds of the
+//! levels have a hysteresis, so a flickering light doesn't toggle the level.
+//!
+//! # Example
+//! ```
+//! use rustcardium::light_sensor::{LightLevel, LightSensor, Smoothing};
+//!
+//! let mut sensor = LightSensor::start()?;
+//! sensor.set_smoothing(Smoothing::MovingAverage(8))?;
+//! if let Some(level) = sensor.update()? {
+//!     let night_mode = level == LightLevel::Dark;
+//! }
+//! let lux = sensor.lux();
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Maximal window of `Smoothing::MovingAverage`.
+pub const MAX_WINDOW: usize = 32;
+
+/// Approximate illuminance in lux of one step of the raw value. The sensor isn't calibrated
+/// and sits behind the display glass, so this is only a rough estimate.
+pub const LUX_PER_STEP: f32 = 2.0;
+
+/// Number of `LightSensor` handles, the sensor runs while it isn't zero.
+static HANDLES: AtomicUsize = AtomicUsize::new(0);
+
+/// Forgets all handles, see `mock::reset()`.
+#[cfg(feature = "mock")]
+pub(crate) fn reset() {
+    HANDLES.store(0, Ordering::SeqCst);
+}
+
+/// Illuminance in lux for a raw or smoothed value, a rough approximation.
+pub fn to_lux(value: f32) -> f32 {
+    value.max(0.0) * LUX_PER_STEP
+}
+
+/// Filter for the readings of a `LightSensor`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// The raw values are used
+    None,
+    /// Exponential moving average, the value is the weight of a new reading (0 < weight <= 1)
+    Exponential(f32),
+    /// Mean of the last readings, the value is the number of readings (1 to `MAX_WINDOW`)
+    MovingAverage(usize),
+}
+
+impl Default for Smoothing {
+    fn default() -> Self {
+        Smoothing::Exponential(0.2)
+    }
+}
+
+/// Classification of the ambient light.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LightLevel {
+    Dark,
+    Dim,
+    Bright,
+}
+
+/// Thresholds between the `LightLevel`s, in raw values of the sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    /// Values below are `LightLevel::Dark`
+    pub dark: f32,
+    /// Values at and above are `LightLevel::Bright`
+    pub bright: f32,
+    /// A level is only left when the value crosses its threshold by this margin
+    pub hysteresis: f32,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            dark: 10.0,
+            bright: 100.0,
+            hysteresis: 3.0,
+        }
+    }
+}
+
+impl Thresholds {
+    /// The level of a value, without hysteresis.
+    pub fn level(&self, value: f32) -> LightLevel {
+        if value < self.dark {
+            LightLevel::Dark
+        } else if value < self.bright {
+            LightLevel::Dim
+        } else {
+            LightLevel::Bright
+        }
+    }
+
+    /// The level of a value when the current level is `current`.
+    pub fn next_level(&self, current: LightLevel, value: f32) -> LightLevel {
+        let up = |threshold: f32| value >= threshold + self.hysteresis;
+        let down = |threshold: f32| value < threshold - self.hysteresis;
+        match current {
+            LightLevel::Dark if up(self.bright) => LightLevel::Bright,
+            LightLevel::Dark if up(self.dark) => LightLevel::Dim,
+            LightLevel::Dim if up(self.bright) => LightLevel::Bright,
+            LightLevel::Dim if down(self.dark) => LightLevel::Dark,
+            LightLevel::Bright if down(self.dark) => LightLevel::Dark,
+            LightLevel::Bright if down(self.bright) => LightLevel::Dim,
+            _ => current,
+        }
+    }
+}
+
+/// Handle of the running light sensor, the sensor is stopped when the last handle is dropped.
+pub struct LightSensor {
+    smoothing: Smoothing,
+    thresholds: Thresholds,
+    value: Option<f32>,
+    level: Option<LightLevel>,
+    window: [u16; MAX_WINDOW],
+    window_pos: usize,
+    window_len: usize,
+}
+
+impl LightSensor {
+    /// Starts the sensor, if it isn't running yet, with the default smoothing and thresholds.
+    pub fn start() -> Result<LightSensor> {
+        if HANDLES.fetch_add(1, Ordering::SeqCst) == 0 {
+            if let Err(e) = unsafe { check(sys::epic_light_sensor_run()) } {
+                HANDLES.fetch_sub(1, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(LightSensor {
+            smoothing: Smoothing::default(),
+            thresholds: Thresholds::default(),
+            value: None,
+            level: None,
+            window: [0; MAX_WINDOW],
+            window_pos: 0,
+            window_len: 0,
+        })
+    }
+
+    /// True if there is a `LightSensor` handle.
+    pub fn is_running() -> bool {
+        HANDLES.load(Ordering::SeqCst) > 0
+    }
+
+    /// Reads the raw value of the sensor, which doesn't affect the smoothed value.
+    pub fn read(&self) -> Result<u16> {
+        let mut value = 0u16;
+        unsafe { check(sys::epic_light_sensor_get(&mut value))? };
+        Ok(value)
+    }
+
+    /// The filter of the readings.
+    pub fn smoothing(&self) -> Smoothing {
+        self.smoothing
+    }
+
+    /// Sets the filter of the readings and discards the previous readings.
+    ///
+    /// # Returns
+    /// `Error::InvalidArgument` if the weight is not in (0, 1] or the window not in
+    /// 1..=`MAX_WINDOW`.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) -> Result<()> {
+        match smoothing {
+            Smoothing::Exponential(weight) if !(weight > 0.0 && weight <= 1.0) => {
+                return Err(Error::InvalidArgument)
+            }
+            Smoothing::MovingAverage(window) if window == 0 || window > MAX_WINDOW => {
+                return Err(Error::InvalidArgument)
+            }
+            _ => (),
+        }
+        self.smoothing = smoothing;
+        self.value = None;
+        self.window_pos = 0;
+        self.window_len = 0;
+        Ok(())
+    }
+
+    /// The thresholds of the light levels.
+    pub fn thresholds(&self) -> &Thresholds {
+        &self.thresholds
+    }
+
+    /// Sets the thresholds of the light levels, the level is determined again by the next update.
+    pub fn set_thresholds(&mut self, thresholds: Thresholds) {
+        self.thresholds = thresholds;
+        self.level = None;
+    }
+
+    /// Reads the sensor and updates the smoothed value.
+    ///
+    /// # Returns
+    /// The smoothed value.
+    pub fn read_smoothed(&mut self) -> Result<f32> {
+        let raw = self.read()?;
+        let value = match self.smoothing {
+            Smoothing::None => f32::from(raw),
+            Smoothing::Exponential(weight) => match self.value {
+                Some(value) => value + weight * (f32::from(raw) - value),
+                None => f32::from(raw),
+            },
+            Smoothing::MovingAverage(window) => {
+                self.window[self.window_pos] = raw;
+                self.window_pos = (self.window_pos + 1) % window;
+                self.window_len = (self.window_len + 1).min(window);
+                let sum: u32 = self.window[..self.window_len]
+                    .iter()
+                    .map(|&v| u32::from(v))
+                    .sum();
+                sum as f32 / self.window_len as f32
+            }
+        };
+        self.value = Some(value);
+        Ok(value)
+    }
+
+    /// The last smoothed value, None before the first reading.
+    pub fn value(&self) -> Option<f32> {
+        self.value
+    }
+
+    /// The last smoothed value as approximate illuminance in lux, None before the first reading.
+    pub fn lux(&self) -> Option<f32> {
+        self.value.map(to_lux)
+    }
+
+    /// The level of the last update, None before the first update.
+    pub fn level(&self) -> Option<LightLevel> {
+        self.level
+    }
+
+    /// Reads the sensor and updates the smoothed value and the light level.
+    ///
+    /// # Returns
+    /// The new level if it changed, including the first level after the start.
+    pub fn update(&mut self) -> Result<Option<LightLevel>> {
+        let value = self.read_smoothed()?;
+        let level = match self.level {
+            Some(current) => self.thresholds.next_level(current, value),
+            None => self.thresholds.level(value),
+        };
+        if self.level == Some(level) {
+            return Ok(None);
+        }
+        self.level = Some(level);
+        Ok(Some(level))
+    }
+}
+
+impl Drop for LightSensor {
+    fn drop(&mut self) {
+        if HANDLES.fetch_sub(1, Ordering::SeqCst) == 1 {
+            unsafe {
+                sys::epic_light_sensor_stop();
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    fn read(sensor: &mut LightSensor, value: u16) -> f32 {
+        mock::with(|s| s.light_sensor_value = value);
+        sensor.read_smoothed().unwrap()
+    }
+
+    #[test]
+    fn levels_have_a_hysteresis() {
+        use LightLevel::*;
+        let t = Thresholds::default();
+        assert_eq!(t.level(9.9), Dark);
+        assert_eq!(t.level(10.0), Dim);
+        assert_eq!(t.level(99.9), Dim);
+        assert_eq!(t.level(100.0), Bright);
+
+        // a level is left 3 beyond its threshold
+        assert_eq!(t.next_level(Dark, 12.9), Dark);
+        assert_eq!(t.next_level(Dark, 13.0), Dim);
+        assert_eq!(t.next_level(Dim, 7.0), Dim);
+        assert_eq!(t.next_level(Dim, 6.9), Dark);
+        assert_eq!(t.next_level(Dim, 102.9), Dim);
+        assert_eq!(t.next_level(Dim, 103.0), Bright);
+        assert_eq!(t.next_level(Bright, 97.0), Bright);
+        assert_eq!(t.next_level(Bright, 96.9), Dim);
+        // jumps over a level
+        assert_eq!(t.next_level(Dark, 103.0), Bright);
+        assert_eq!(t.next_level(Bright, 6.9), Dark);
+        assert_eq!(t.next_level(Bright, 7.0), Dim);
+
+        // a flickering light around a threshold keeps the level
+        let mut level = Dim;
+        for &value in &[98.0, 102.0, 99.0, 101.5, 97.5] {
+            level = t.next_level(level, value);
+            assert_eq!(level, Dim);
+        }
+    }
+
+    #[test]
+    fn moving_average_window() {
+        mock::reset();
+        let mut sensor = LightSensor::start().unwrap();
+        sensor.set_smoothing(Smoothing::MovingAverage(4)).unwrap();
+        // the mean of the readings so far until the window is full
+        assert_eq!(read(&mut sensor, 10), 10.0);
+        assert_eq!(read(&mut sensor, 20), 15.0);
+        assert_eq!(read(&mut sensor, 30), 20.0);
+        assert_eq!(read(&mut sensor, 40), 25.0);
+        // then the oldest reading is replaced
+        assert_eq!(read(&mut sensor, 50), 35.0);
+        assert_eq!(read(&mut sensor, 50), 42.5);
+        assert_eq!(read(&mut sensor, 50), 47.5);
+        assert_eq!(read(&mut sensor, 50), 50.0);
+        assert_eq!(sensor.value(), Some(50.0));
+        assert_eq!(sensor.lux(), Some(100.0));
+
+        // a new window discards the readings
+        sensor.set_smoothing(Smoothing::MovingAverage(2)).unwrap();
+        assert_eq!(sensor.value(), None);
+        assert_eq!(read(&mut sensor, 0), 0.0);
+        assert_eq!(read(&mut sensor, 10), 5.0);
+        assert_eq!(read(&mut sensor, 30), 20.0);
+
+        let mut max = LightSensor::start().unwrap();
+        max.set_smoothing(Smoothing::MovingAverage(MAX_WINDOW))
+            .unwrap();
+        for i in 0..MAX_WINDOW as u16 {
+            read(&mut max, i);
+        }
+        assert_eq!(
+            read(&mut max, MAX_WINDOW as u16),
+            (MAX_WINDOW + 1) as f32 / 2.0
+        );
+    }
+
+    #[test]
+    fn invalid_smoothing() {
+        mock::reset();
+        let mut sensor = LightSensor::start().unwrap();
+        for &smoothing in &[
+            Smoothing::MovingAverage(0),
+            Smoothing::MovingAverage(MAX_WINDOW + 1),
+            Smoothing::Exponential(0.0),
+            Smoothing::Exponential(1.5),
+        ] {
+            assert_eq!(sensor.set_smoothing(smoothing), Err(Error::InvalidArgument));
+        }
+        assert_eq!(sensor.smoothing(), Smoothing::default());
+    }
+
+    #[test]
+    fn update_reports_changed_levels() {
+        mock::reset();
+        let mut sensor = LightSensor::start().unwrap();
+        sensor.set_smoothing(Smoothing::None).unwrap();
+        mock::with(|s| s.light_sensor_value = 50);
+        assert_eq!(sensor.update().unwrap(), Some(LightLevel::Dim));
+        mock::with(|s| s.light_sensor_value = 101);
+        assert_eq!(sensor.update().unwrap(), None);
+        mock::with(|s| s.light_sensor_value = 103);
+        assert_eq!(sensor.update().unwrap(), Some(LightLevel::Bright));
+        assert_eq!(sensor.level(), Some(LightLevel::Bright));
+        mock::with(|s| s.light_sensor_value = 5);
+        assert_eq!(sensor.update().unwrap(), Some(LightLevel::Dark));
+    }
+
+    #[test]
+    fn sensor_runs_while_there_is_a_handle() {
+        mock::reset();
+        let first = LightSensor::start().unwrap();
+        let second = LightSensor::start().unwrap();
+        assert!(mock::with(|s| s.light_sensor_running));
+        drop(first);
+        assert!(LightSensor::is_running());
+        assert!(mock::with(|s| s.light_sensor_running));
+        drop(second);
+        assert!(!LightSensor::is_running());
+        assert!(!mock::with(|s| s.light_sensor_running));
+    }
+}
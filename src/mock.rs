@@ -0,0 +1,1724 @@
+//! Host-side mock of the Epicardium API.
+//!
+//! When the crate is compiled with the `mock` feature, it is not linked against the firmware.
+//! Instead, every `epic_*` function of `epicardium.h` is implemented in this module in plain Rust.
+//! Each call is recorded and the simulated card10 can be inspected and scripted from tests,
+//! so l0dables can be tested on a normal host with `cargo test --features mock`.
+//!
+//! The state of the mock is kept per thread, but the crate itself keeps some state in
+//! process-wide statics: the interrupt handlers, the executor's ready tasks and timer, the
+//! active BHI160 sensors, the `Bme680` and `LightSensor` handles, the UTC offset and the clock
+//! adjustment of `SystemTime::set()`. `reset()` resets these as well, so run the tests one at a
+//! time with `cargo test --features mock -- --test-threads=1`.
+//!
+//! # Example
+//! ```
+//! use rustcardium::mock;
+//!
+//! mock::reset();
+//! // Let the next call to `epic_disp_open` fail as if another task holds the display
+//! mock::push_return("epic_disp_open", -(mock::errno::EBUSY as i32));
+//!
+//! assert!(rustcardium::display::Display::open().is_err());
+//! assert_eq!(mock::calls()[0].name, "epic_disp_open");
+//! ```
+extern crate std;
+
+use crate::sys;
+use core::cell::RefCell;
+use cty::{c_char, c_int, c_long, c_void};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::string::{String, ToString};
+use std::vec::Vec;
+
+/// Error numbers used by the Epicardium API, e.g. for scripting return values.
+pub mod errno {
+    pub use crate::sys::{
+        EACCES, EBADF, EBUSY, EEXIST, EFAULT, EINVAL, EIO, EISDIR, ENODATA, ENODEV, ENOENT,
+        ENOEXEC, ENOTDIR,
+    };
+}
+
+/// Width of the simulated display.
+pub const DISP_WIDTH: usize = sys::DISP_WIDTH as usize;
+/// Height of the simulated display.
+pub const DISP_HEIGHT: usize = sys::DISP_HEIGHT as usize;
+
+/// Number of RGB LEDs (11 on top, 4 ambient LEDs at the bottom).
+pub const NUM_LEDS: usize = 15;
+
+/// Number of BHI160 virtual sensor types.
+pub const NUM_BHI160_SENSORS: usize = 11;
+
+/// Argument of a recorded call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i64),
+    Float(f32),
+    Str(String),
+    Bytes(Vec<u8>),
+}
+
+/// A recorded call to one of the Epicardium API functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    /// Name of the C function, e.g. `"epic_disp_pixel"`
+    pub name: &'static str,
+    pub args: Vec<Arg>,
+}
+
+/// Kind of a file system entry of the simulated FAT file system.
+enum Handle {
+    File {
+        path: String,
+        pos: usize,
+        read: bool,
+        write: bool,
+        append: bool,
+    },
+    Dir {
+        entries: Vec<(String, u32, u32)>,
+        next: usize,
+    },
+}
+
+/// Complete state of the simulated card10.
+///
+/// All fields can be changed with `with()` to prepare a test scenario or inspected afterwards.
+pub struct Epicardium {
+    /// All API calls in the order they were made.
+    pub calls: Vec<Call>,
+    /// Scripted return values, they take precedence over the simulated behavior.
+    pub returns: HashMap<&'static str, VecDeque<i32>>,
+
+    /// Return code if the payload called `epic_exit`.
+    pub exit_code: Option<i32>,
+    /// Name of the payload given to `epic_exec`.
+    pub exec_name: Option<String>,
+    /// Number of `epic_system_reset` calls.
+    pub resets: usize,
+    /// Which of the `EPIC_INT_*` interrupts are enabled.
+    pub interrupts: [bool; sys::EPIC_INT_NUM as usize],
+
+    pub battery_voltage: f32,
+    pub battery_current: f32,
+    pub chargein_voltage: f32,
+    pub chargein_current: f32,
+    pub system_voltage: f32,
+    pub thermistor_voltage: f32,
+
+    /// Everything written to the UART.
+    pub uart_output: Vec<u8>,
+    /// Bytes which can be read from the UART.
+    pub uart_input: VecDeque<u8>,
+
+    /// Currently pressed buttons as bit mask.
+    pub buttons: u8,
+
+    /// Mode of the wristband GPIO pins (index 1 to 4).
+    pub gpio_modes: [u8; 5],
+    /// Value of the wristband GPIO pins (index 1 to 4).
+    pub gpio_values: [bool; 5],
+
+    /// RGB values currently shown by the LEDs.
+    pub leds: [[u8; 3]; NUM_LEDS],
+    /// RGB values prepared for the next `epic_leds_update`.
+    pub leds_prepared: [[u8; 3]; NUM_LEDS],
+    /// Number of `epic_leds_update` calls.
+    pub leds_updates: usize,
+    pub leds_dim_top: u8,
+    pub leds_dim_bottom: u8,
+    pub leds_powersave: bool,
+    pub leds_gamma_tables: [[u8; 256]; 3],
+    pub rockets: [u8; 3],
+    pub flashlight: bool,
+
+    pub bme680_initialized: bool,
+    pub bme680_data: sys::bme680_sensor_data,
+
+    pub personal_state: u8,
+    pub personal_state_persistent: bool,
+
+    /// Configuration of the enabled BHI160 sensors, indexed by sensor type.
+    pub bhi160_enabled: [Option<sys::bhi160_sensor_config>; NUM_BHI160_SENSORS],
+    /// Samples waiting to be read with `epic_stream_read`, indexed by sensor type.
+    pub bhi160_samples: [VecDeque<sys::bhi160_data_vector>; NUM_BHI160_SENSORS],
+
+    pub vibra: bool,
+    pub vibra_millis: i32,
+
+    /// True if the display is locked by the payload.
+    pub display_locked: bool,
+    /// RGB565 pixels drawn by the immediate mode functions but not shown yet.
+    pub canvas: Vec<u16>,
+    /// RGB565 pixels currently shown on the display.
+    pub screen: Vec<u16>,
+    /// Texts printed with `epic_disp_print` since the last `epic_disp_clear`.
+    pub texts: Vec<(u16, u16, String)>,
+    pub backlight: u16,
+
+    pub light_sensor_running: bool,
+    pub light_sensor_value: u16,
+
+    /// Files of the simulated file system, the path never starts with a `/`.
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Directories of the simulated file system, the path never starts with a `/`.
+    pub dirs: BTreeSet<String>,
+    handles: Vec<Option<Handle>>,
+
+    /// Current RTC value.
+    pub rtc_milliseconds: u64,
+    /// Amount of milliseconds the RTC advances each time it is read.
+    pub rtc_auto_advance: u64,
+    /// Timestamp of the scheduled RTC alarm.
+    pub rtc_alarm: Option<u32>,
+
+    /// State of the pseudo random generator used for `epic_trng_read`.
+    pub trng_state: u32,
+}
+
+impl Default for Epicardium {
+    fn default() -> Self {
+        Epicardium {
+            calls: Vec::new(),
+            returns: HashMap::new(),
+            exit_code: None,
+            exec_name: None,
+            resets: 0,
+            interrupts: [false; sys::EPIC_INT_NUM as usize],
+            battery_voltage: 3.9,
+            battery_current: -0.05,
+            chargein_voltage: 0.0,
+            chargein_current: 0.0,
+            system_voltage: 3.8,
+            thermistor_voltage: 0.9,
+            uart_output: Vec::new(),
+            uart_input: VecDeque::new(),
+            buttons: 0,
+            gpio_modes: [0; 5],
+            gpio_values: [false; 5],
+            leds: [[0; 3]; NUM_LEDS],
+            leds_prepared: [[0; 3]; NUM_LEDS],
+            leds_updates: 0,
+            leds_dim_top: 1,
+            leds_dim_bottom: 8,
+            leds_powersave: true,
+            leds_gamma_tables: [[0; 256]; 3],
+            rockets: [0; 3],
+            flashlight: false,
+            bme680_initialized: false,
+            bme680_data: sys::bme680_sensor_data {
+                temperature: 21.0,
+                humidity: 45.0,
+                pressure: 1013.25,
+                gas_resistance: 100_000.0,
+            },
+            personal_state: 0,
+            personal_state_persistent: false,
+            bhi160_enabled: [None; NUM_BHI160_SENSORS],
+            bhi160_samples: Default::default(),
+            vibra: false,
+            vibra_millis: 0,
+            display_locked: false,
+            canvas: std::vec![0; DISP_WIDTH * DISP_HEIGHT],
+            screen: std::vec![0; DISP_WIDTH * DISP_HEIGHT],
+            texts: Vec::new(),
+            backlight: 100,
+            light_sensor_running: false,
+            light_sensor_value: 0,
+            files: BTreeMap::new(),
+            dirs: BTreeSet::new(),
+            handles: Vec::new(),
+            rtc_milliseconds: 0,
+            rtc_auto_advance: 0,
+            rtc_alarm: None,
+            trng_state: 0x1234_5678,
+        }
+    }
+}
+
+impl Epicardium {
+    /// Color of a pixel on the display in RGB565 format.
+    pub fn screen_pixel(&self, x: usize, y: usize) -> u16 {
+        self.screen[y * DISP_WIDTH + x]
+    }
+
+    fn record(&mut self, name: &'static str, args: Vec<Arg>) -> Option<i32> {
+        self.calls.push(Call { name, args });
+        self.returns.get_mut(name).and_then(|r| r.pop_front())
+    }
+
+    fn draw_pixel(&mut self, x: i32, y: i32, color: u16) {
+        if x >= 0 && y >= 0 && (x as usize) < DISP_WIDTH && (y as usize) < DISP_HEIGHT {
+            self.canvas[y as usize * DISP_WIDTH + x as usize] = color;
+        }
+    }
+
+    fn draw_dot(&mut self, x: i32, y: i32, color: u16, size: u16) {
+        let size = i32::from(size.max(1));
+        for dy in 0..size {
+            for dx in 0..size {
+                self.draw_pixel(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    fn draw_line(&mut self, xs: i32, ys: i32, xe: i32, ye: i32, color: u16, size: u16) {
+        // Bresenham's line algorithm
+        let dx = (xe - xs).abs();
+        let dy = -(ye - ys).abs();
+        let sx = if xs < xe { 1 } else { -1 };
+        let sy = if ys < ye { 1 } else { -1 };
+        let mut err = dx + dy;
+        let (mut x, mut y) = (xs, ys);
+        loop {
+            self.draw_dot(x, y, color, size);
+            if x == xe && y == ye {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn file_entry(&self, path: &str) -> Option<(u32, u32)> {
+        if let Some(content) = self.files.get(path) {
+            Some((sys::epic_stat_type_EPICSTAT_FILE, content.len() as u32))
+        } else if path.is_empty() || self.dirs.contains(path) {
+            Some((sys::epic_stat_type_EPICSTAT_DIR, 0))
+        } else {
+            None
+        }
+    }
+
+    fn parent_exists(&self, path: &str) -> bool {
+        match path.rfind('/') {
+            Some(idx) => self.dirs.contains(&path[0..idx]),
+            None => true,
+        }
+    }
+
+    fn add_handle(&mut self, handle: Handle) -> c_int {
+        if let Some(idx) = self.handles.iter().position(Option::is_none) {
+            self.handles[idx] = Some(handle);
+            idx as c_int
+        } else {
+            self.handles.push(Some(handle));
+            (self.handles.len() - 1) as c_int
+        }
+    }
+
+    fn handle(&mut self, fd: c_int) -> Option<&mut Handle> {
+        if fd < 0 {
+            return None;
+        }
+        self.handles.get_mut(fd as usize).and_then(Option::as_mut)
+    }
+}
+
+std::thread_local! {
+    static STATE: RefCell<Epicardium> = RefCell::new(Epicardium::default());
+}
+
+/// Gives access to the state of the simulated card10 of the current thread.
+pub fn with<R>(f: impl FnOnce(&mut Epicardium) -> R) -> R {
+    STATE.with(|s| f(&mut s.borrow_mut()))
+}
+
+/// Resets the simulated card10 to its initial state and forgets all recorded calls.
+///
+/// The state the crate keeps in process-wide statics is reset as well, as if the l0dable was
+/// started again. Drop all handles, e.g. a `LightSensor`, before calling this.
+pub fn reset() {
+    with(|s| *s = Epicardium::default());
+    crate::bhi160::reset();
+    crate::bme680::reset();
+    crate::executor::reset();
+    crate::interrupts::reset();
+    crate::light_sensor::reset();
+    crate::time::reset();
+}
+
+/// Returns all recorded calls.
+pub fn calls() -> Vec<Call> {
+    with(|s| s.calls.clone())
+}
+
+/// Returns all recorded calls and clears the record.
+pub fn take_calls() -> Vec<Call> {
+    with(|s| core::mem::take(&mut s.calls))
+}
+
+/// Number of calls made to the function with the given name.
+pub fn call_count(name: &str) -> usize {
+    with(|s| s.calls.iter().filter(|c| c.name == name).count())
+}
+
+/// Let the next call to the function `name` return `value` instead of the simulated result.
+///
+/// Multiple values for the same function are returned in the order they were pushed.
+/// The call is still recorded, but does not change the state of the mock.
+pub fn push_return(name: &'static str, value: i32) {
+    with(|s| s.returns.entry(name).or_default().push_back(value));
+}
+
+/// Everything the payload has written to the UART so far, lossy decoded as UTF-8.
+pub fn uart_output() -> String {
+    with(|s| String::from_utf8_lossy(&s.uart_output).to_string())
+}
+
+/// Makes the given text available for reading from the UART and triggers `EPIC_INT_UART_RX`.
+pub fn push_uart_input(text: &str) {
+    with(|s| s.uart_input.extend(text.bytes()));
+    trigger_interrupt(sys::EPIC_INT_UART_RX);
+}
+
+/// Sets the bit mask of the currently pressed buttons.
+pub fn set_buttons(mask: u8) {
+    with(|s| s.buttons = mask);
+}
+
+/// Sets the RTC to the given Unix time in milliseconds.
+pub fn set_rtc_milliseconds(milliseconds: u64) {
+    with(|s| s.rtc_milliseconds = milliseconds);
+}
+
+/// Adds samples to the stream of the BHI160 sensor with the given type and triggers its interrupt.
+pub fn push_bhi160_samples(sensor_type: u32, samples: &[(i16, i16, i16, u8)]) {
+    with(|s| {
+        let queue = &mut s.bhi160_samples[sensor_type as usize];
+        for &(x, y, z, status) in samples {
+            queue.push_back(sys::bhi160_data_vector {
+                data_type: sys::bhi160_data_type_BHI160_DATA_TYPE_VECTOR,
+                x,
+                y,
+                z,
+                status,
+            });
+        }
+    });
+    let interrupt = match sensor_type {
+        sys::bhi160_sensor_type_BHI160_ACCELEROMETER => sys::EPIC_INT_BHI160_ACCELEROMETER,
+        sys::bhi160_sensor_type_BHI160_ORIENTATION => sys::EPIC_INT_BHI160_ORIENTATION,
+        sys::bhi160_sensor_type_BHI160_GYROSCOPE => sys::EPIC_INT_BHI160_GYROSCOPE,
+        _ => return,
+    };
+    trigger_interrupt(interrupt);
+}
+
+/// Adds a file with the given content to the simulated file system.
+pub fn add_file(path: &str, content: &[u8]) {
+    with(|s| {
+        s.files
+            .insert(path.trim_start_matches('/').to_string(), content.to_vec())
+    });
+}
+
+/// Content of a file of the simulated file system.
+pub fn file(path: &str) -> Option<Vec<u8>> {
+    with(|s| s.files.get(path.trim_start_matches('/')).cloned())
+}
+
+/// Raises the interrupt with the given `EPIC_INT_*` id.
+///
+/// If the interrupt is enabled, its interrupt service routine is called.
+///
+/// # Returns
+/// True if the interrupt was enabled by the payload.
+pub fn trigger_interrupt(id: u32) -> bool {
+    let enabled = with(|s| s.interrupts.get(id as usize).cloned().unwrap_or(false));
+    if enabled {
+        use crate::interrupts::*;
+        let isr = match Interrupt::from_id(id) {
+            Some(Interrupt::CtrlC) => epic_isr_ctrl_c,
+            Some(Interrupt::UartRx) => epic_isr_uart_rx,
+            Some(Interrupt::RtcAlarm) => epic_isr_rtc_alarm,
+            Some(Interrupt::Bhi160Accelerometer) => epic_isr_bhi160_accelerometer,
+            Some(Interrupt::Bhi160Orientation) => epic_isr_bhi160_orientation,
+            Some(Interrupt::Bhi160Gyroscope) => epic_isr_bhi160_gyroscope,
+            None => return enabled,
+        };
+        unsafe { isr() };
+    }
+    enabled
+}
+
+unsafe fn c_str(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    let bytes = core::ffi::CStr::from_ptr(ptr).to_bytes();
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+fn path_arg(path: &str) -> String {
+    path.trim_start_matches('/')
+        .trim_end_matches('/')
+        .to_string()
+}
+
+fn int(v: impl Into<i64>) -> Arg {
+    Arg::Int(v.into())
+}
+
+fn neg(errno: u32) -> c_int {
+    -(errno as c_int)
+}
+
+// Interrupts
+
+#[no_mangle]
+unsafe extern "C" fn epic_interrupt_enable(int_id: sys::api_int_id_t) -> c_int {
+    with(|s| {
+        s.record("epic_interrupt_enable", std::vec![int(int_id)])
+            .unwrap_or_else(|| match s.interrupts.get_mut(int_id as usize) {
+                Some(enabled) => {
+                    *enabled = true;
+                    0
+                }
+                None => neg(sys::EINVAL),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_interrupt_disable(int_id: sys::api_int_id_t) -> c_int {
+    with(|s| {
+        s.record("epic_interrupt_disable", std::vec![int(int_id)])
+            .unwrap_or_else(|| match s.interrupts.get_mut(int_id as usize) {
+                Some(enabled) => {
+                    *enabled = false;
+                    0
+                }
+                None => neg(sys::EINVAL),
+            })
+    })
+}
+
+// Core API
+
+#[no_mangle]
+unsafe extern "C" fn epic_exit(ret: c_int) {
+    with(|s| {
+        s.record("epic_exit", std::vec![int(ret)]);
+        s.exit_code = Some(ret);
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_exec(name: *mut c_char) -> c_int {
+    let name = c_str(name);
+    with(|s| {
+        s.record("epic_exec", std::vec![Arg::Str(name.clone())])
+            .unwrap_or_else(|| {
+                let path = path_arg(&name);
+                if s.files.contains_key(&path) || s.dirs.contains(&path) {
+                    s.exec_name = Some(name);
+                    0
+                } else {
+                    neg(sys::ENOENT)
+                }
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_system_reset() {
+    with(|s| {
+        s.record("epic_system_reset", Vec::new());
+        s.resets += 1;
+    });
+}
+
+// PMIC API
+
+macro_rules! pmic_reading {
+    ($name:ident, $field:ident) => {
+        #[no_mangle]
+        unsafe extern "C" fn $name(result: *mut f32) -> c_int {
+            with(|s| {
+                s.record(stringify!($name), Vec::new()).unwrap_or_else(|| {
+                    if result.is_null() {
+                        return neg(sys::EFAULT);
+                    }
+                    *result = s.$field;
+                    0
+                })
+            })
+        }
+    };
+}
+
+pmic_reading!(epic_read_battery_voltage, battery_voltage);
+pmic_reading!(epic_read_battery_current, battery_current);
+pmic_reading!(epic_read_chargein_voltage, chargein_voltage);
+pmic_reading!(epic_read_chargein_current, chargein_current);
+pmic_reading!(epic_read_system_voltage, system_voltage);
+pmic_reading!(epic_read_thermistor_voltage, thermistor_voltage);
+
+// UART
+
+#[no_mangle]
+unsafe extern "C" fn epic_uart_write_str(str: *const c_char, length: sys::intptr_t) {
+    let bytes = core::slice::from_raw_parts(str as *const u8, length.max(0) as usize);
+    with(|s| {
+        s.record(
+            "epic_uart_write_str",
+            std::vec![Arg::Str(String::from_utf8_lossy(bytes).to_string())],
+        );
+        s.uart_output.extend_from_slice(bytes);
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_uart_read_char() -> c_int {
+    with(|s| {
+        s.record("epic_uart_read_char", Vec::new())
+            .unwrap_or_else(|| s.uart_input.pop_front().map(c_int::from).unwrap_or(-1))
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_uart_read_str(buf: *mut c_char, cnt: sys::size_t) -> c_int {
+    with(|s| {
+        s.record("epic_uart_read_str", std::vec![int(cnt as i64)])
+            .unwrap_or_else(|| {
+                let n = cnt.min(s.uart_input.len());
+                for i in 0..n {
+                    *buf.add(i) = s.uart_input.pop_front().unwrap_or(0) as c_char;
+                }
+                n as c_int
+            })
+    })
+}
+
+// Buttons
+
+#[no_mangle]
+unsafe extern "C" fn epic_buttons_read(mask: u8) -> u8 {
+    with(|s| {
+        s.record("epic_buttons_read", std::vec![int(mask)])
+            .map(|r| r as u8)
+            .unwrap_or(s.buttons & mask)
+    })
+}
+
+// Wristband GPIO
+
+#[no_mangle]
+unsafe extern "C" fn epic_gpio_set_pin_mode(pin: u8, mode: u8) -> c_int {
+    with(|s| {
+        s.record("epic_gpio_set_pin_mode", std::vec![int(pin), int(mode)])
+            .unwrap_or_else(|| match s.gpio_modes.get_mut(pin as usize) {
+                Some(m) if pin > 0 => {
+                    *m = mode;
+                    0
+                }
+                _ => neg(sys::EINVAL),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_gpio_get_pin_mode(pin: u8) -> c_int {
+    with(|s| {
+        s.record("epic_gpio_get_pin_mode", std::vec![int(pin)])
+            .unwrap_or_else(|| match s.gpio_modes.get(pin as usize) {
+                Some(m) if pin > 0 => c_int::from(*m),
+                _ => neg(sys::EINVAL),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_gpio_write_pin(pin: u8, on: bool) -> c_int {
+    with(|s| {
+        s.record("epic_gpio_write_pin", std::vec![int(pin), int(on)])
+            .unwrap_or_else(|| match s.gpio_values.get_mut(pin as usize) {
+                Some(v) if pin > 0 => {
+                    *v = on;
+                    0
+                }
+                _ => neg(sys::EINVAL),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_gpio_read_pin(pin: u8) -> c_int {
+    with(|s| {
+        s.record("epic_gpio_read_pin", std::vec![int(pin)])
+            .unwrap_or_else(|| match s.gpio_values.get(pin as usize) {
+                Some(v) if pin > 0 => c_int::from(*v),
+                _ => neg(sys::EINVAL),
+            })
+    })
+}
+
+// LEDs
+
+/// Converts a HSV color with 0 <= h < 360 and 0 <= s, v <= 1 to RGB.
+fn hsv_to_rgb(h: f32, s: f32, v: f32) -> [u8; 3] {
+    let h = ((h % 360.0) + 360.0) % 360.0 / 60.0;
+    let c = v * s;
+    let x = c * (1.0 - ((h % 2.0) - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    let to_u8 = |f: f32| ((f + m) * 255.0 + 0.5) as u8;
+    [to_u8(r), to_u8(g), to_u8(b)]
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set(led: c_int, r: u8, g: u8, b: u8) {
+    with(|s| {
+        s.record("epic_leds_set", std::vec![int(led), int(r), int(g), int(b)]);
+        if let Some(l) = s.leds.get_mut(led as usize) {
+            *l = [r, g, b];
+            s.leds_prepared[led as usize] = [r, g, b];
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set_hsv(led: c_int, h: f32, sat: f32, v: f32) {
+    with(|s| {
+        s.record(
+            "epic_leds_set_hsv",
+            std::vec![int(led), Arg::Float(h), Arg::Float(sat), Arg::Float(v)],
+        );
+        if let Some(l) = s.leds.get_mut(led as usize) {
+            *l = hsv_to_rgb(h, sat, v);
+            s.leds_prepared[led as usize] = *l;
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set_all(pattern: *mut u8, len: u8) {
+    let pattern = core::slice::from_raw_parts(pattern, len as usize * 3);
+    with(|s| {
+        s.record(
+            "epic_leds_set_all",
+            std::vec![Arg::Bytes(pattern.to_vec()), int(len)],
+        );
+        for (i, rgb) in pattern.chunks(3).take(NUM_LEDS).enumerate() {
+            s.leds[i] = [rgb[0], rgb[1], rgb[2]];
+            s.leds_prepared[i] = s.leds[i];
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set_all_hsv(pattern: *mut f32, len: u8) {
+    let pattern = core::slice::from_raw_parts(pattern, len as usize * 3);
+    with(|s| {
+        s.record("epic_leds_set_all_hsv", std::vec![int(len)]);
+        for (i, hsv) in pattern.chunks(3).take(NUM_LEDS).enumerate() {
+            s.leds[i] = hsv_to_rgb(hsv[0], hsv[1], hsv[2]);
+            s.leds_prepared[i] = s.leds[i];
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_prep(led: c_int, r: u8, g: u8, b: u8) {
+    with(|s| {
+        s.record(
+            "epic_leds_prep",
+            std::vec![int(led), int(r), int(g), int(b)],
+        );
+        if let Some(l) = s.leds_prepared.get_mut(led as usize) {
+            *l = [r, g, b];
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_prep_hsv(led: c_int, h: f32, sat: f32, v: f32) {
+    with(|s| {
+        s.record(
+            "epic_leds_prep_hsv",
+            std::vec![int(led), Arg::Float(h), Arg::Float(sat), Arg::Float(v)],
+        );
+        if let Some(l) = s.leds_prepared.get_mut(led as usize) {
+            *l = hsv_to_rgb(h, sat, v);
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_dim_bottom(value: u8) {
+    with(|s| {
+        s.record("epic_leds_dim_bottom", std::vec![int(value)]);
+        s.leds_dim_bottom = value;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_dim_top(value: u8) {
+    with(|s| {
+        s.record("epic_leds_dim_top", std::vec![int(value)]);
+        s.leds_dim_top = value;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set_powersave(eco: bool) {
+    with(|s| {
+        s.record("epic_leds_set_powersave", std::vec![int(eco)]);
+        s.leds_powersave = eco;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_update() {
+    with(|s| {
+        s.record("epic_leds_update", Vec::new());
+        s.leds = s.leds_prepared;
+        s.leds_updates += 1;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set_rocket(led: c_int, value: u8) {
+    with(|s| {
+        s.record("epic_leds_set_rocket", std::vec![int(led), int(value)]);
+        if let Some(r) = s.rockets.get_mut(led as usize) {
+            *r = value;
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_set_flashlight(power: bool) {
+    with(|s| {
+        s.record("epic_set_flashlight", std::vec![int(power)]);
+        s.flashlight = power;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_set_gamma_table(rgb_channel: u8, gamma_table: *mut u8) {
+    let table = core::slice::from_raw_parts(gamma_table, 256);
+    with(|s| {
+        s.record(
+            "epic_leds_set_gamma_table",
+            std::vec![int(rgb_channel), Arg::Bytes(table.to_vec())],
+        );
+        if let Some(t) = s.leds_gamma_tables.get_mut(rgb_channel as usize) {
+            t.copy_from_slice(table);
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_leds_clear_all(r: u8, g: u8, b: u8) {
+    with(|s| {
+        s.record("epic_leds_clear_all", std::vec![int(r), int(g), int(b)]);
+        s.leds = [[r, g, b]; NUM_LEDS];
+        s.leds_prepared = s.leds;
+    });
+}
+
+// BME680
+
+#[no_mangle]
+unsafe extern "C" fn epic_bme680_init() -> c_int {
+    with(|s| {
+        s.record("epic_bme680_init", Vec::new()).unwrap_or_else(|| {
+            s.bme680_initialized = true;
+            0
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_bme680_deinit() -> c_int {
+    with(|s| {
+        s.record("epic_bme680_deinit", Vec::new())
+            .unwrap_or_else(|| {
+                s.bme680_initialized = false;
+                0
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_bme680_read_sensors(data: *mut sys::bme680_sensor_data) -> c_int {
+    with(|s| {
+        s.record("epic_bme680_read_sensors", Vec::new())
+            .unwrap_or_else(|| {
+                if data.is_null() {
+                    neg(sys::EFAULT)
+                } else if !s.bme680_initialized {
+                    neg(sys::EINVAL)
+                } else {
+                    *data = s.bme680_data;
+                    0
+                }
+            })
+    })
+}
+
+// Personal State
+
+#[no_mangle]
+unsafe extern "C" fn epic_personal_state_set(state: u8, persistent: bool) -> c_int {
+    with(|s| {
+        s.record(
+            "epic_personal_state_set",
+            std::vec![int(state), int(persistent)],
+        )
+        .unwrap_or_else(|| {
+            if state > sys::personal_state_STATE_CAMP as u8 {
+                return neg(sys::EINVAL);
+            }
+            s.personal_state = state;
+            s.personal_state_persistent = persistent;
+            0
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_personal_state_get() -> c_int {
+    with(|s| {
+        s.record("epic_personal_state_get", Vec::new())
+            .unwrap_or_else(|| c_int::from(s.personal_state))
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_personal_state_is_persistent() -> c_int {
+    with(|s| {
+        s.record("epic_personal_state_is_persistent", Vec::new())
+            .unwrap_or_else(|| c_int::from(s.personal_state_persistent))
+    })
+}
+
+// Sensor Data Streams
+
+#[no_mangle]
+unsafe extern "C" fn epic_stream_read(sd: c_int, buf: *mut c_void, count: sys::size_t) -> c_int {
+    let sample_size = core::mem::size_of::<sys::bhi160_data_vector>();
+    with(|s| {
+        s.record("epic_stream_read", std::vec![int(sd), int(count as i64)])
+            .unwrap_or_else(|| {
+                // The mock uses the sensor type as sensor descriptor
+                if sd < 0 || sd as usize >= NUM_BHI160_SENSORS {
+                    return neg(sys::EBADF);
+                }
+                if s.bhi160_enabled[sd as usize].is_none() {
+                    return neg(sys::ENODEV);
+                }
+                if count % sample_size != 0 {
+                    return neg(sys::EINVAL);
+                }
+                let buf = buf as *mut sys::bhi160_data_vector;
+                let queue = &mut s.bhi160_samples[sd as usize];
+                let n = (count / sample_size).min(queue.len());
+                for i in 0..n {
+                    if let Some(sample) = queue.pop_front() {
+                        *buf.add(i) = sample;
+                    }
+                }
+                n as c_int
+            })
+    })
+}
+
+// BHI160
+
+#[no_mangle]
+unsafe extern "C" fn epic_bhi160_enable_sensor(
+    sensor_type: sys::bhi160_sensor_type,
+    config: *mut sys::bhi160_sensor_config,
+) -> c_int {
+    with(|s| {
+        s.record("epic_bhi160_enable_sensor", std::vec![int(sensor_type)])
+            .unwrap_or_else(|| {
+                if config.is_null() {
+                    return neg(sys::EFAULT);
+                }
+                match s.bhi160_enabled.get_mut(sensor_type as usize) {
+                    Some(enabled) => {
+                        *enabled = Some(*config);
+                        sensor_type as c_int
+                    }
+                    None => neg(sys::ENODEV),
+                }
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_bhi160_disable_sensor(sensor_type: sys::bhi160_sensor_type) -> c_int {
+    with(|s| {
+        s.record("epic_bhi160_disable_sensor", std::vec![int(sensor_type)])
+            .unwrap_or_else(|| match s.bhi160_enabled.get_mut(sensor_type as usize) {
+                Some(enabled) => {
+                    *enabled = None;
+                    0
+                }
+                None => neg(sys::ENODEV),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_bhi160_disable_all_sensors() {
+    with(|s| {
+        s.record("epic_bhi160_disable_all_sensors", Vec::new());
+        s.bhi160_enabled = [None; NUM_BHI160_SENSORS];
+    });
+}
+
+// Vibration Motor
+
+#[no_mangle]
+unsafe extern "C" fn epic_vibra_set(status: c_int) {
+    with(|s| {
+        s.record("epic_vibra_set", std::vec![int(status)]);
+        s.vibra = status != 0;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_vibra_vibrate(millis: c_int) {
+    with(|s| {
+        s.record("epic_vibra_vibrate", std::vec![int(millis)]);
+        s.vibra_millis = millis;
+    });
+}
+
+// Display
+
+/// Records a display call and executes `f` if the display is locked and no value was scripted.
+fn display_call(
+    name: &'static str,
+    args: Vec<Arg>,
+    f: impl FnOnce(&mut Epicardium) -> c_int,
+) -> c_int {
+    with(|s| {
+        s.record(name, args).unwrap_or_else(|| {
+            if s.display_locked {
+                f(s)
+            } else {
+                neg(sys::EBUSY)
+            }
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_open() -> c_int {
+    with(|s| {
+        s.record("epic_disp_open", Vec::new()).unwrap_or_else(|| {
+            if s.display_locked {
+                neg(sys::EBUSY)
+            } else {
+                s.display_locked = true;
+                0
+            }
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_close() -> c_int {
+    display_call("epic_disp_close", Vec::new(), |s| {
+        s.display_locked = false;
+        0
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_update() -> c_int {
+    display_call("epic_disp_update", Vec::new(), |s| {
+        s.screen.copy_from_slice(&s.canvas);
+        0
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_print(
+    posx: u16,
+    posy: u16,
+    p_string: *const c_char,
+    fg: u16,
+    bg: u16,
+) -> c_int {
+    let text = c_str(p_string);
+    display_call(
+        "epic_disp_print",
+        std::vec![
+            int(posx),
+            int(posy),
+            Arg::Str(text.clone()),
+            int(fg),
+            int(bg)
+        ],
+        |s| {
+            s.texts.push((posx, posy, text));
+            0
+        },
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_clear(color: u16) -> c_int {
+    display_call("epic_disp_clear", std::vec![int(color)], |s| {
+        for p in s.canvas.iter_mut() {
+            *p = color;
+        }
+        s.texts.clear();
+        0
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_pixel(x: u16, y: u16, color: u16) -> c_int {
+    display_call(
+        "epic_disp_pixel",
+        std::vec![int(x), int(y), int(color)],
+        |s| {
+            s.draw_pixel(i32::from(x), i32::from(y), color);
+            0
+        },
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_line(
+    xstart: u16,
+    ystart: u16,
+    xend: u16,
+    yend: u16,
+    color: u16,
+    linestyle: sys::disp_linestyle,
+    pixelsize: u16,
+) -> c_int {
+    display_call(
+        "epic_disp_line",
+        std::vec![
+            int(xstart),
+            int(ystart),
+            int(xend),
+            int(yend),
+            int(color),
+            int(linestyle),
+            int(pixelsize)
+        ],
+        |s| {
+            s.draw_line(
+                i32::from(xstart),
+                i32::from(ystart),
+                i32::from(xend),
+                i32::from(yend),
+                color,
+                pixelsize,
+            );
+            0
+        },
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_rect(
+    xstart: u16,
+    ystart: u16,
+    xend: u16,
+    yend: u16,
+    color: u16,
+    fillstyle: sys::disp_fillstyle,
+    pixelsize: u16,
+) -> c_int {
+    display_call(
+        "epic_disp_rect",
+        std::vec![
+            int(xstart),
+            int(ystart),
+            int(xend),
+            int(yend),
+            int(color),
+            int(fillstyle),
+            int(pixelsize)
+        ],
+        |s| {
+            let (xs, ys, xe, ye) = (
+                i32::from(xstart),
+                i32::from(ystart),
+                i32::from(xend),
+                i32::from(yend),
+            );
+            if fillstyle == sys::disp_fillstyle_FILLSTYLE_FILLED {
+                // like in the firmware, the end coordinates of filled rectangles are exclusive
+                for y in ys..ye {
+                    for x in xs..xe {
+                        s.draw_pixel(x, y, color);
+                    }
+                }
+            } else {
+                s.draw_line(xs, ys, xe, ys, color, pixelsize);
+                s.draw_line(xe, ys, xe, ye, color, pixelsize);
+                s.draw_line(xe, ye, xs, ye, color, pixelsize);
+                s.draw_line(xs, ye, xs, ys, color, pixelsize);
+            }
+            0
+        },
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_circ(
+    x: u16,
+    y: u16,
+    rad: u16,
+    color: u16,
+    fillstyle: sys::disp_fillstyle,
+    pixelsize: u16,
+) -> c_int {
+    display_call(
+        "epic_disp_circ",
+        std::vec![
+            int(x),
+            int(y),
+            int(rad),
+            int(color),
+            int(fillstyle),
+            int(pixelsize)
+        ],
+        |s| {
+            let (cx, cy, r) = (i32::from(x), i32::from(y), i32::from(rad));
+            let outer = r * r;
+            let inner = (r - i32::from(pixelsize.max(1))).max(0).pow(2);
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    let d = dx * dx + dy * dy;
+                    let filled = fillstyle == sys::disp_fillstyle_FILLSTYLE_FILLED;
+                    if d <= outer && (filled || d >= inner) {
+                        s.draw_pixel(cx + dx, cy + dy, color);
+                    }
+                }
+            }
+            0
+        },
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_framebuffer(fb: *mut sys::disp_framebuffer) -> c_int {
+    display_call("epic_disp_framebuffer", Vec::new(), |s| {
+        let raw = &(*fb).raw;
+        for (i, p) in s.screen.iter_mut().enumerate() {
+            // the framebuffer is byte swapped
+            *p = u16::from_be_bytes([raw[2 * i], raw[2 * i + 1]]);
+        }
+        0
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_disp_backlight(brightness: u16) -> c_int {
+    display_call("epic_disp_backlight", std::vec![int(brightness)], |s| {
+        s.backlight = brightness;
+        0
+    })
+}
+
+// Light Sensor
+
+#[no_mangle]
+unsafe extern "C" fn epic_light_sensor_run() -> c_int {
+    with(|s| {
+        s.record("epic_light_sensor_run", Vec::new())
+            .unwrap_or_else(|| {
+                s.light_sensor_running = true;
+                0
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_light_sensor_get(value: *mut u16) -> c_int {
+    with(|s| {
+        s.record("epic_light_sensor_get", Vec::new())
+            .unwrap_or_else(|| {
+                if !s.light_sensor_running {
+                    return neg(sys::ENODATA);
+                }
+                *value = s.light_sensor_value;
+                0
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_light_sensor_stop() -> c_int {
+    with(|s| {
+        s.record("epic_light_sensor_stop", Vec::new())
+            .unwrap_or_else(|| {
+                s.light_sensor_running = false;
+                0
+            })
+    })
+}
+
+// File
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_open(filename: *const c_char, mode_string: *const c_char) -> c_int {
+    let (filename, mode) = (c_str(filename), c_str(mode_string));
+    with(|s| {
+        s.record(
+            "epic_file_open",
+            std::vec![Arg::Str(filename.clone()), Arg::Str(mode.clone())],
+        )
+        .unwrap_or_else(|| {
+            let path = path_arg(&filename);
+            let plus = mode.contains('+');
+            let (read, write, append, create, truncate) = match mode.chars().next() {
+                Some('r') => (true, plus, false, false, false),
+                Some('w') => (plus, true, false, true, true),
+                Some('a') => (plus, true, true, true, false),
+                _ => return neg(sys::EINVAL),
+            };
+            if s.dirs.contains(&path) {
+                return neg(sys::EISDIR);
+            }
+            if !s.files.contains_key(&path) {
+                if !create {
+                    return neg(sys::ENOENT);
+                }
+                if !s.parent_exists(&path) {
+                    return neg(sys::ENOENT);
+                }
+                s.files.insert(path.clone(), Vec::new());
+            } else if truncate {
+                s.files.insert(path.clone(), Vec::new());
+            }
+            s.add_handle(Handle::File {
+                path,
+                pos: 0,
+                read,
+                write,
+                append,
+            })
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_close(fd: c_int) -> c_int {
+    with(|s| {
+        s.record("epic_file_close", std::vec![int(fd)])
+            .unwrap_or_else(|| {
+                if s.handle(fd).is_none() {
+                    return neg(sys::EBADF);
+                }
+                s.handles[fd as usize] = None;
+                0
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_read(fd: c_int, buf: *mut c_void, nbytes: sys::size_t) -> c_int {
+    with(|s| {
+        s.record("epic_file_read", std::vec![int(fd), int(nbytes as i64)])
+            .unwrap_or_else(|| {
+                let (path, pos) = match s.handle(fd) {
+                    Some(Handle::File {
+                        path,
+                        pos,
+                        read: true,
+                        ..
+                    }) => (path.clone(), *pos),
+                    Some(_) => return neg(sys::EACCES),
+                    None => return neg(sys::EBADF),
+                };
+                let content = match s.files.get(&path) {
+                    Some(content) => content,
+                    None => return neg(sys::ENOENT),
+                };
+                let n = nbytes.min(content.len().saturating_sub(pos));
+                let rest = content.get(pos..).unwrap_or(&[]);
+                core::ptr::copy_nonoverlapping(rest.as_ptr(), buf as *mut u8, n);
+                if let Some(Handle::File { pos, .. }) = s.handle(fd) {
+                    *pos += n;
+                }
+                n as c_int
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_write(fd: c_int, buf: *const c_void, nbytes: sys::size_t) -> c_int {
+    let data = core::slice::from_raw_parts(buf as *const u8, nbytes);
+    with(|s| {
+        s.record(
+            "epic_file_write",
+            std::vec![int(fd), Arg::Bytes(data.to_vec())],
+        )
+        .unwrap_or_else(|| {
+            let (path, pos, append) = match s.handle(fd) {
+                Some(Handle::File {
+                    path,
+                    pos,
+                    write: true,
+                    append,
+                    ..
+                }) => (path.clone(), *pos, *append),
+                Some(_) => return neg(sys::EACCES),
+                None => return neg(sys::EBADF),
+            };
+            let content = match s.files.get_mut(&path) {
+                Some(content) => content,
+                None => return neg(sys::ENOENT),
+            };
+            let start = if append { content.len() } else { pos };
+            if content.len() < start + data.len() {
+                content.resize(start + data.len(), 0);
+            }
+            content[start..start + data.len()].copy_from_slice(data);
+            if let Some(Handle::File { pos, .. }) = s.handle(fd) {
+                *pos = start + data.len();
+            }
+            nbytes as c_int
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_flush(fd: c_int) -> c_int {
+    with(|s| {
+        s.record("epic_file_flush", std::vec![int(fd)])
+            .unwrap_or_else(|| match s.handle(fd) {
+                Some(Handle::File { .. }) => 0,
+                _ => neg(sys::EBADF),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_seek(fd: c_int, offset: c_long, whence: c_int) -> c_int {
+    with(|s| {
+        s.record(
+            "epic_file_seek",
+            std::vec![int(fd), int(i64::from(offset)), int(whence)],
+        )
+        .unwrap_or_else(|| {
+            let (path, pos) = match s.handle(fd) {
+                Some(Handle::File { path, pos, .. }) => (path.clone(), *pos),
+                _ => return neg(sys::EBADF),
+            };
+            let len = s.files.get(&path).map(Vec::len).unwrap_or(0);
+            let base = match whence {
+                0 => 0,
+                1 => pos as i64,
+                2 => len as i64,
+                _ => return neg(sys::EINVAL),
+            };
+            let new_pos = base + i64::from(offset);
+            if new_pos < 0 {
+                return neg(sys::EINVAL);
+            }
+            if let Some(Handle::File { pos, .. }) = s.handle(fd) {
+                *pos = new_pos as usize;
+            }
+            0
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_tell(fd: c_int) -> c_int {
+    with(|s| {
+        s.record("epic_file_tell", std::vec![int(fd)])
+            .unwrap_or_else(|| match s.handle(fd) {
+                Some(Handle::File { pos, .. }) => *pos as c_int,
+                _ => neg(sys::EBADF),
+            })
+    })
+}
+
+unsafe fn fill_stat(stat: *mut sys::epic_stat, name: &str, type_: u32, size: u32) {
+    (*stat).type_ = type_;
+    (*stat).size = size;
+    (*stat).name = [0; 256];
+    for (i, b) in name
+        .bytes()
+        .take(sys::EPICSTAT_MAX_PATH as usize)
+        .enumerate()
+    {
+        (*stat).name[i] = b as c_char;
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_stat(path: *const c_char, stat: *mut sys::epic_stat) -> c_int {
+    let path = c_str(path);
+    with(|s| {
+        s.record("epic_file_stat", std::vec![Arg::Str(path.clone())])
+            .unwrap_or_else(|| {
+                let path = path_arg(&path);
+                let name = path.rsplit('/').next().unwrap_or("");
+                match s.file_entry(&path) {
+                    Some((type_, size)) => {
+                        fill_stat(stat, name, type_, size);
+                        0
+                    }
+                    None => {
+                        fill_stat(stat, name, sys::epic_stat_type_EPICSTAT_NONE, 0);
+                        neg(sys::ENOENT)
+                    }
+                }
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_opendir(path: *const c_char) -> c_int {
+    let path = c_str(path);
+    with(|s| {
+        s.record("epic_file_opendir", std::vec![Arg::Str(path.clone())])
+            .unwrap_or_else(|| {
+                let path = path_arg(&path);
+                match s.file_entry(&path) {
+                    Some((t, _)) if t == sys::epic_stat_type_EPICSTAT_DIR => {}
+                    Some(_) => return neg(sys::ENOTDIR),
+                    None => return neg(sys::ENOENT),
+                }
+                let prefix = if path.is_empty() {
+                    String::new()
+                } else {
+                    std::format!("{}/", path)
+                };
+                let direct_child = |p: &String| {
+                    p.starts_with(&prefix) && !p[prefix.len()..].contains('/') && p != &path
+                };
+                let mut entries: Vec<(String, u32, u32)> = s
+                    .dirs
+                    .iter()
+                    .filter(|p| direct_child(p))
+                    .map(|p| {
+                        (
+                            p[prefix.len()..].to_string(),
+                            sys::epic_stat_type_EPICSTAT_DIR,
+                            0,
+                        )
+                    })
+                    .collect();
+                entries.extend(s.files.iter().filter(|(p, _)| direct_child(p)).map(
+                    |(p, content)| {
+                        (
+                            p[prefix.len()..].to_string(),
+                            sys::epic_stat_type_EPICSTAT_FILE,
+                            content.len() as u32,
+                        )
+                    },
+                ));
+                // Like the firmware, descriptors are always > 0 on success
+                if s.handles.is_empty() {
+                    s.handles.push(None);
+                }
+                s.add_handle(Handle::Dir { entries, next: 0 })
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_readdir(fd: c_int, stat: *mut sys::epic_stat) -> c_int {
+    with(|s| {
+        s.record("epic_file_readdir", std::vec![int(fd)])
+            .unwrap_or_else(|| match s.handle(fd) {
+                Some(Handle::Dir { entries, next }) => {
+                    if stat.is_null() {
+                        *next = 0;
+                    } else if let Some((name, type_, size)) = entries.get(*next) {
+                        fill_stat(stat, name, *type_, *size);
+                        *next += 1;
+                    } else {
+                        fill_stat(stat, "", sys::epic_stat_type_EPICSTAT_NONE, 0);
+                    }
+                    0
+                }
+                _ => neg(sys::EBADF),
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_unlink(path: *const c_char) -> c_int {
+    let path = c_str(path);
+    with(|s| {
+        s.record("epic_file_unlink", std::vec![Arg::Str(path.clone())])
+            .unwrap_or_else(|| {
+                let path = path_arg(&path);
+                if s.files.remove(&path).is_some() {
+                    return 0;
+                }
+                let prefix = std::format!("{}/", path);
+                let has_children = s.files.keys().any(|p| p.starts_with(&prefix))
+                    || s.dirs.iter().any(|p| p.starts_with(&prefix));
+                if has_children {
+                    neg(sys::EACCES)
+                } else if s.dirs.remove(&path) {
+                    0
+                } else {
+                    neg(sys::ENOENT)
+                }
+            })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_rename(oldp: *const c_char, newp: *const c_char) -> c_int {
+    let (oldp, newp) = (c_str(oldp), c_str(newp));
+    with(|s| {
+        s.record(
+            "epic_file_rename",
+            std::vec![Arg::Str(oldp.clone()), Arg::Str(newp.clone())],
+        )
+        .unwrap_or_else(|| {
+            let (oldp, newp) = (path_arg(&oldp), path_arg(&newp));
+            if s.file_entry(&newp).is_some() {
+                return neg(sys::EEXIST);
+            }
+            if !s.parent_exists(&newp) {
+                return neg(sys::ENOENT);
+            }
+            if let Some(content) = s.files.remove(&oldp) {
+                s.files.insert(newp, content);
+                return 0;
+            }
+            if !s.dirs.contains(&oldp) {
+                return neg(sys::ENOENT);
+            }
+            let prefix = std::format!("{}/", oldp);
+            let moved = |p: &String| std::format!("{}{}", newp, &p[oldp.len()..]);
+            s.dirs = s
+                .dirs
+                .iter()
+                .map(|p| {
+                    if p == &oldp || p.starts_with(&prefix) {
+                        moved(p)
+                    } else {
+                        p.clone()
+                    }
+                })
+                .collect();
+            s.files = s
+                .files
+                .iter()
+                .map(|(p, c)| {
+                    if p.starts_with(&prefix) {
+                        (moved(p), c.clone())
+                    } else {
+                        (p.clone(), c.clone())
+                    }
+                })
+                .collect();
+            0
+        })
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_file_mkdir(dirname: *const c_char) -> c_int {
+    let dirname = c_str(dirname);
+    with(|s| {
+        s.record("epic_file_mkdir", std::vec![Arg::Str(dirname.clone())])
+            .unwrap_or_else(|| {
+                let path = path_arg(&dirname);
+                if s.file_entry(&path).is_some() {
+                    neg(sys::EEXIST)
+                } else if !s.parent_exists(&path) {
+                    neg(sys::ENOENT)
+                } else {
+                    s.dirs.insert(path);
+                    0
+                }
+            })
+    })
+}
+
+// RTC
+
+#[no_mangle]
+unsafe extern "C" fn epic_rtc_get_seconds() -> u32 {
+    with(|s| {
+        s.record("epic_rtc_get_seconds", Vec::new());
+        s.rtc_milliseconds += s.rtc_auto_advance;
+        (s.rtc_milliseconds / 1000) as u32
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_rtc_get_milliseconds() -> u64 {
+    with(|s| {
+        s.record("epic_rtc_get_milliseconds", Vec::new());
+        s.rtc_milliseconds += s.rtc_auto_advance;
+        s.rtc_milliseconds
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_rtc_set_milliseconds(milliseconds: u64) {
+    with(|s| {
+        s.record(
+            "epic_rtc_set_milliseconds",
+            std::vec![int(milliseconds as i64)],
+        );
+        s.rtc_milliseconds = milliseconds;
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn epic_rtc_schedule_alarm(timestamp: u32) -> c_int {
+    with(|s| {
+        s.record("epic_rtc_schedule_alarm", std::vec![int(timestamp)])
+            .unwrap_or_else(|| {
+                s.rtc_alarm = Some(timestamp);
+                0
+            })
+    })
+}
+
+// TRNG
+
+#[no_mangle]
+unsafe extern "C" fn epic_trng_read(dest: *mut u8, size: sys::size_t) -> c_int {
+    with(|s| {
+        s.record("epic_trng_read", std::vec![int(size as i64)])
+            .unwrap_or_else(|| {
+                if dest.is_null() {
+                    return neg(sys::EFAULT);
+                }
+                for i in 0..size {
+                    // xorshift32, deterministic so tests are reproducible
+                    let mut x = s.trng_state;
+                    x ^= x << 13;
+                    x ^= x >> 17;
+                    x ^= x << 5;
+                    s.trng_state = x;
+                    *dest.add(i) = x as u8;
+                }
+                0
+            })
+    })
+}
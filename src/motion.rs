@@ -0,0 +1,438 @@
+//! Step counting and activity detection based on the samples of the BHI160 accelerometer.
+//!
+//! The `StepCounter` processes the samples incrementally, e.g. every batch returned by
+//! `bhi160::Sensor::next_batch()`, and doesn't allocate. Since it only needs `Acceleration`
+//! values, it can also be fed with recorded traces on the host.
+//!
+//! Steps are detected as peaks of the acceleration magnitude, after removing gravity and
+//! smoothing the signal. A peak counts as step if it is above the threshold and not too close
+//! to the previous step.
+//!
+//! # Example
+//! ```
+//! use rustcardium::bhi160::{Sample, Sensor, SensorConfig, SensorType};
+//! use rustcardium::motion::{Activity, StepConfig, StepCounter};
+//!
+//! let accel = Sensor::enable(
+//!     SensorType::Accelerometer,
+//!     SensorConfig {
+//!         sample_buffer_len: 120,
+//!         sample_rate: 25,
+//!         dynamic_range: 4,
+//!     },
+//! )?;
+//! let mut counter = StepCounter::new(StepConfig {
+//!     sample_rate: 25,
+//!     ..StepConfig::default()
+//! });
+//! # rustcardium::mock::push_bhi160_samples(0, &[(0, 0, 8192, 3); 25]);
+//! for sample in accel.samples() {
+//!     if let Sample::Acceleration(a) = sample? {
+//!         counter.update(&a);
+//!     }
+//! }
+//! assert_eq!(counter.activity(), Activity::Still);
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use crate::bhi160::{Acceleration, STANDARD_GRAVITY};
+use crate::time::Duration;
+
+/// Number of step intervals used to calculate the cadence.
+const CADENCE_WINDOW: usize = 8;
+
+/// Activity of the wearer, see `StepCounter::activity()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Activity {
+    Still,
+    Walking,
+    Running,
+    /// Strong movement without regular steps, e.g. shaking the card10
+    Shaking,
+}
+
+/// Thresholds and parameters of the `StepCounter`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StepConfig {
+    /// Sample rate of the accelerometer in Hz, as given in `bhi160::SensorConfig`
+    pub sample_rate: u16,
+    /// Minimal acceleration in m/s² (without gravity) of a step
+    pub threshold: f32,
+    /// Minimal time between two steps, shorter peaks are ignored
+    pub min_step_interval: Duration,
+    /// Maximal time between two steps of a walk, after a longer break the cadence starts anew
+    pub max_step_interval: Duration,
+    /// Weight of a new sample in the smoothed signal, 0 < smoothing <= 1
+    pub smoothing: f32,
+    /// Stride length in meters when walking
+    pub stride_length: f32,
+    /// Stride length in meters when running
+    pub running_stride_length: f32,
+    /// Minimal cadence in steps per minute which is considered running
+    pub running_cadence: f32,
+    /// Maximal RMS acceleration in m/s² (without gravity) when still
+    pub still_threshold: f32,
+    /// Minimal RMS acceleration in m/s² (without gravity) which is considered shaking
+    pub shaking_threshold: f32,
+}
+
+impl Default for StepConfig {
+    fn default() -> Self {
+        StepConfig {
+            sample_rate: 25,
+            threshold: 1.2,
+            min_step_interval: Duration::from_millis(250),
+            max_step_interval: Duration::from_millis(2000),
+            smoothing: 0.4,
+            stride_length: 0.75,
+            running_stride_length: 1.2,
+            running_cadence: 140.0,
+            still_threshold: 0.3,
+            shaking_threshold: 15.0,
+        }
+    }
+}
+
+/// Incremental step counter and activity classifier.
+pub struct StepCounter {
+    config: StepConfig,
+    min_interval: u32,
+    max_interval: u32,
+    /// Slow moving average of the magnitude, i.e. gravity
+    gravity: f32,
+    /// Smoothed magnitude without gravity
+    smoothed: f32,
+    /// Moving average of the squared acceleration without gravity
+    energy: f32,
+    above_threshold: bool,
+    samples: u32,
+    last_step: Option<u32>,
+    intervals: [u32; CADENCE_WINDOW],
+    num_intervals: usize,
+    next_interval: usize,
+    steps: u32,
+    distance: f32,
+}
+
+impl StepCounter {
+    /// Creates a step counter without any steps.
+    pub fn new(config: StepConfig) -> StepCounter {
+        StepCounter {
+            config,
+            min_interval: samples(config.min_step_interval, config.sample_rate).max(1),
+            max_interval: samples(config.max_step_interval, config.sample_rate).max(1),
+            gravity: STANDARD_GRAVITY,
+            smoothed: 0.0,
+            energy: 0.0,
+            above_threshold: false,
+            samples: 0,
+            last_step: None,
+            intervals: [0; CADENCE_WINDOW],
+            num_intervals: 0,
+            next_interval: 0,
+            steps: 0,
+            distance: 0.0,
+        }
+    }
+
+    /// The configuration of this step counter.
+    pub fn config(&self) -> &StepConfig {
+        &self.config
+    }
+
+    /// Processes the next sample of the accelerometer.
+    ///
+    /// # Returns
+    /// True if the sample completed a step.
+    pub fn update(&mut self, a: &Acceleration) -> bool {
+        let magnitude = libm::sqrtf(a.x * a.x + a.y * a.y + a.z * a.z);
+        // gravity changes slowly, average it over about one second
+        let alpha = 1.0 / f32::from(self.config.sample_rate.max(1));
+        self.gravity += alpha * (magnitude - self.gravity);
+        let dynamic = magnitude - self.gravity;
+        self.smoothed += self.config.smoothing * (dynamic - self.smoothed);
+        self.energy += alpha * (dynamic * dynamic - self.energy);
+        self.samples = self.samples.wrapping_add(1);
+
+        if let Some(last) = self.last_step {
+            if self.since(last) > self.max_interval {
+                // the walk was interrupted
+                self.last_step = None;
+                self.num_intervals = 0;
+            }
+        }
+
+        if self.above_threshold {
+            // hysteresis, the signal needs to fall back before the next step
+            if self.smoothed < self.config.threshold / 2.0 {
+                self.above_threshold = false;
+            }
+            return false;
+        }
+        if self.smoothed < self.config.threshold {
+            return false;
+        }
+        self.above_threshold = true;
+        match self.last_step {
+            Some(last) if self.since(last) < self.min_interval => false,
+            last => {
+                if let Some(last) = last {
+                    self.intervals[self.next_interval] = self.since(last);
+                    self.next_interval = (self.next_interval + 1) % CADENCE_WINDOW;
+                    self.num_intervals = (self.num_intervals + 1).min(CADENCE_WINDOW);
+                }
+                self.last_step = Some(self.samples);
+                self.steps += 1;
+                self.distance += if self.cadence() >= self.config.running_cadence {
+                    self.config.running_stride_length
+                } else {
+                    self.config.stride_length
+                };
+                true
+            }
+        }
+    }
+
+    /// Processes several samples of the accelerometer, e.g. a batch read from the sensor.
+    ///
+    /// # Returns
+    /// The number of steps completed by the samples.
+    pub fn update_all<'a, I>(&mut self, samples: I) -> u32
+    where
+        I: IntoIterator<Item = &'a Acceleration>,
+    {
+        samples.into_iter().filter(|a| self.update(a)).count() as u32
+    }
+
+    fn since(&self, sample: u32) -> u32 {
+        self.samples.wrapping_sub(sample)
+    }
+
+    /// Number of steps since the counter was created or reset.
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Estimated distance in meters since the counter was created or reset.
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Current cadence in steps per minute, 0 when not walking.
+    pub fn cadence(&self) -> f32 {
+        if self.num_intervals == 0 {
+            return 0.0;
+        }
+        let total: u32 = self.intervals[..self.num_intervals].iter().sum();
+        let mean = total as f32 / self.num_intervals as f32;
+        60.0 * f32::from(self.config.sample_rate) / mean
+    }
+
+    /// Classifies the current activity, based on the cadence and the strength of the movement.
+    pub fn activity(&self) -> Activity {
+        let rms = libm::sqrtf(self.energy);
+        let cadence = self.cadence();
+        if rms < self.config.still_threshold && self.last_step.is_none() {
+            Activity::Still
+        } else if rms >= self.config.shaking_threshold || !self.is_regular() {
+            Activity::Shaking
+        } else if cadence == 0.0 {
+            if rms < self.config.threshold / 2.0 {
+                Activity::Still
+            } else {
+                Activity::Shaking
+            }
+        } else if cadence >= self.config.running_cadence {
+            Activity::Running
+        } else {
+            Activity::Walking
+        }
+    }
+
+    /// True if the recent steps have similar intervals, as they have when walking or running.
+    fn is_regular(&self) -> bool {
+        let intervals = &self.intervals[..self.num_intervals];
+        if intervals.len() < 2 {
+            return true;
+        }
+        let n = intervals.len() as f32;
+        let mean = intervals.iter().sum::<u32>() as f32 / n;
+        let variance = intervals
+            .iter()
+            .map(|&i| (i as f32 - mean) * (i as f32 - mean))
+            .sum::<f32>()
+            / n;
+        // the standard deviation of the intervals is less than a third of their mean
+        variance * 9.0 < mean * mean
+    }
+
+    /// Resets the step count and distance, the activity detection is not affected.
+    pub fn reset(&mut self) {
+        self.steps = 0;
+        self.distance = 0.0;
+    }
+}
+
+/// Number of samples at `sample_rate` Hz within `duration`, saturating at `u32::MAX`.
+pub(crate) fn samples(duration: Duration, sample_rate: u16) -> u32 {
+    let ms = duration.as_millis().min(u128::from(u64::MAX)) as u64;
+    let samples = ms.saturating_mul(u64::from(sample_rate)) / 1000;
+    samples.min(u64::from(u32::MAX)) as u32
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::bhi160::test_util::accel;
+    use crate::bhi160::SensorType;
+    use crate::recorder::{Decoder, Source};
+    use core::f32::consts::PI;
+
+    const SAMPLE_RATE: u16 = 50;
+
+    /// Vertical acceleration of a gait with one step per period, the card10 lies flat.
+    fn gait(frequency: f32, amplitude: f32, seconds: u32) -> impl Iterator<Item = Acceleration> {
+        let n = seconds * u32::from(SAMPLE_RATE);
+        (0..n).map(move |i| {
+            let t = i as f32 / f32::from(SAMPLE_RATE);
+            let vertical = amplitude * libm::sinf(2.0 * PI * frequency * t);
+            accel(0.2, -0.1, STANDARD_GRAVITY + vertical)
+        })
+    }
+
+    fn counter() -> StepCounter {
+        StepCounter::new(StepConfig {
+            sample_rate: SAMPLE_RATE,
+            ..StepConfig::default()
+        })
+    }
+
+    #[test]
+    fn still() {
+        let mut counter = counter();
+        for _ in 0..10 * SAMPLE_RATE {
+            counter.update(&accel(0.0, 0.0, STANDARD_GRAVITY));
+        }
+        assert_eq!(counter.steps(), 0);
+        assert_eq!(counter.cadence(), 0.0);
+        assert_eq!(counter.activity(), Activity::Still);
+    }
+
+    #[test]
+    fn walking() {
+        let mut counter = counter();
+        let steps = gait(2.0, 3.0, 30).fold(0, |n, a| n + counter.update(&a) as u32);
+        // 60 steps at 120 steps per minute
+        assert!((58..=60).contains(&steps), "{} steps", steps);
+        assert_eq!(counter.steps(), steps);
+        assert!((counter.cadence() - 120.0).abs() < 3.0);
+        assert_eq!(counter.activity(), Activity::Walking);
+        assert!((counter.distance() - steps as f32 * 0.75).abs() < 0.01);
+    }
+
+    #[test]
+    fn running() {
+        let mut counter = counter();
+        // in batches, as read from the sensor
+        let mut trace = gait(3.0, 8.0, 20);
+        let mut batch = [accel(0.0, 0.0, 0.0); 25];
+        let mut steps = 0;
+        loop {
+            let n = batch
+                .iter_mut()
+                .zip(&mut trace)
+                .map(|(b, a)| *b = a)
+                .count();
+            if n == 0 {
+                break;
+            }
+            steps += counter.update_all(&batch[..n]);
+        }
+        // 60 steps at 180 steps per minute
+        assert!((58..=60).contains(&steps), "{} steps", steps);
+        assert_eq!(counter.steps(), steps);
+        assert!((counter.cadence() - 180.0).abs() < 5.0);
+        assert_eq!(counter.activity(), Activity::Running);
+    }
+
+    #[test]
+    fn walk_then_rest() {
+        let mut counter = counter();
+        for a in gait(2.0, 3.0, 10) {
+            counter.update(&a);
+        }
+        let steps = counter.steps();
+        for _ in 0..5 * SAMPLE_RATE {
+            counter.update(&accel(0.2, -0.1, STANDARD_GRAVITY));
+        }
+        assert_eq!(counter.steps(), steps);
+        assert_eq!(counter.cadence(), 0.0);
+        assert_eq!(counter.activity(), Activity::Still);
+        counter.reset();
+        assert_eq!(counter.steps(), 0);
+    }
+
+    #[test]
+    fn shaking() {
+        let mut counter = counter();
+        // strong and irregular movement of a pseudo random generator
+        let mut seed = 1u32;
+        for _ in 0..5 * SAMPLE_RATE {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let random = (seed >> 16) as f32 / 65536.0 - 0.5;
+            counter.update(&accel(
+                150.0 * random,
+                0.0,
+                STANDARD_GRAVITY - 100.0 * random,
+            ));
+        }
+        assert_eq!(counter.activity(), Activity::Shaking);
+    }
+
+    #[test]
+    fn long_intervals_saturate() {
+        assert_eq!(samples(Duration::from_millis(2000), 25), 50);
+        assert_eq!(samples(Duration::from_secs(100_000), 1000), 100_000_000);
+        assert_eq!(samples(Duration::from_secs(10_000_000), 1000), u32::MAX);
+        assert_eq!(samples(Duration::from_secs(u64::MAX), u16::MAX), u32::MAX);
+        let counter = StepCounter::new(StepConfig {
+            sample_rate: 1000,
+            max_step_interval: Duration::from_secs(10_000_000),
+            ..StepConfig::default()
+        });
+        assert_eq!(counter.max_interval, u32::MAX);
+        assert_eq!(counter.min_interval, 250);
+    }
+
+    /// Binary recording of the accelerometer at 25 Hz, see `testdata/README.md`.
+    const WALK: &[u8] = include_bytes!("../testdata/walk_25hz.bin");
+
+    #[test]
+    fn recorded_walk() {
+        let decoder = Decoder::new(WALK).unwrap();
+        assert_eq!(
+            decoder.channels(),
+            &[Source::Bhi160(SensorType::Accelerometer)]
+        );
+        let mut counter = StepCounter::new(StepConfig::default());
+        let mut samples = 0;
+        let mut steps_at_pause = 0;
+        for record in decoder {
+            let record = record.unwrap();
+            let v = record.values();
+            counter.update(&accel(v[0], v[1], v[2]));
+            samples += 1;
+            if samples == 34 * 25 {
+                steps_at_pause = counter.steps();
+            }
+            if samples == 40 * 25 {
+                assert_eq!(counter.steps(), steps_at_pause);
+            }
+        }
+        assert_eq!(samples, 64 * 25);
+        // 54 steps at 108 steps per minute and 41 steps at 124 steps per minute, the weakest
+        // steps stay below the threshold
+        let steps = counter.steps();
+        assert!((86..=95).contains(&steps), "{} steps", steps);
+        assert_eq!(counter.activity(), Activity::Still);
+    }
+}
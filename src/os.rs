@@ -28,15 +28,7 @@ pub fn exit(ret: Option<i32>) -> ! {
 pub fn exec(name: &str) -> Result<()> {
     let mut name = create_nullterminated_str(name);
     unsafe {
-        let result = sys::epic_exec(name.as_mut_ptr());
-        if result >= 0 {
-            Ok(())
-        } else {
-            match (-result) as u32 {
-                sys::ENOENT => Err(Error::FileNotFound),
-                sys::ENOEXEC => Err(Error::FileNotInLoadableFormat),
-                _ => Err(Error::UnknownError),
-            }
-        }
+        check(sys::epic_exec(name.as_mut_ptr() as *mut cty::c_char))?;
     }
+    Ok(())
 }
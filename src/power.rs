@@ -0,0 +1,1055 @@
+//! Battery and power supply of card10, measured by the PMIC.
+//!
+//! `read()` returns all measurements at once, the functions like `battery_voltage()` read a
+//! single one. The state of charge is estimated from the battery voltage with the discharge
+//! curve of a LiPo cell. A `Monitor` reports when the charger is connected or disconnected and
+//! when the battery is charged or low, and a `BatteryIndicator` draws the battery symbol of a
+//! status bar. Apps can opt into the power saving of a `Policy`, which controls the backlight
+//! and the LEDs.
+//!
+//! # Example
+//! ```
+//! use rustcardium::display::Display;
+//! use rustcardium::power::{self, BatteryIndicator, Monitor, PowerEvent};
+//! use rustcardium::Color;
+//!
+//! let mut display = Display::open()?;
+//! let mut monitor = Monitor::new(10.0);
+//! let indicator = BatteryIndicator::new(140, 0, 20, 10);
+//!
+//! let reading = power::read()?;
+//! if let Some(PowerEvent::BatteryLow) = monitor.update(&reading) {
+//!     // save the state and warn the user
+//! }
+//! indicator.draw(&mut display, &reading)?;
+//! display.update()?;
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::bhi160::Acceleration;
+use crate::buttons;
+use crate::chart::Canvas;
+use crate::display::Display;
+use crate::leds;
+use crate::light_sensor::LightSensor;
+use crate::time::{Duration, Instant};
+
+/// Minimal voltage of the charge input when a charger is connected.
+const CHARGER_VOLTAGE: f32 = 4.0;
+
+/// Battery voltage above which the battery is charged, when the charge current has stopped.
+const FULL_VOLTAGE: f32 = 4.1;
+
+/// Charge current in A below which charging has stopped.
+const FULL_CURRENT: f32 = 0.01;
+
+/// Internal resistance of the battery in Ω, used to estimate the open-circuit voltage.
+const INTERNAL_RESISTANCE: f32 = 0.15;
+
+/// Open-circuit voltage of a LiPo cell and the corresponding state of charge in %.
+const DISCHARGE_CURVE: [(f32, f32); 21] = [
+    (3.27, 0.0),
+    (3.61, 5.0),
+    (3.69, 10.0),
+    (3.71, 15.0),
+    (3.73, 20.0),
+    (3.75, 25.0),
+    (3.77, 30.0),
+    (3.79, 35.0),
+    (3.80, 40.0),
+    (3.82, 45.0),
+    (3.84, 50.0),
+    (3.85, 55.0),
+    (3.87, 60.0),
+    (3.91, 65.0),
+    (3.95, 70.0),
+    (3.98, 75.0),
+    (4.02, 80.0),
+    (4.08, 85.0),
+    (4.11, 90.0),
+    (4.15, 95.0),
+    (4.20, 100.0),
+];
+
+fn read_pmic(f: unsafe extern "C" fn(*mut f32) -> cty::c_int) -> Result<f32> {
+    let mut value = 0.0;
+    unsafe { check(f(&mut value))? };
+    Ok(value)
+}
+
+/// Voltage of the battery in V.
+pub fn battery_voltage() -> Result<f32> {
+    read_pmic(sys::epic_read_battery_voltage)
+}
+
+/// Current of the battery in A, positive while charging and negative while discharging.
+pub fn battery_current() -> Result<f32> {
+    read_pmic(sys::epic_read_battery_current)
+}
+
+/// Voltage of the charge input (USB) in V.
+pub fn chargein_voltage() -> Result<f32> {
+    read_pmic(sys::epic_read_chargein_voltage)
+}
+
+/// Current of the charge input (USB) in A.
+pub fn chargein_current() -> Result<f32> {
+    read_pmic(sys::epic_read_chargein_current)
+}
+
+/// Voltage of the system supply in V.
+pub fn system_voltage() -> Result<f32> {
+    read_pmic(sys::epic_read_system_voltage)
+}
+
+/// Voltage of the battery thermistor in V.
+pub fn thermistor_voltage() -> Result<f32> {
+    read_pmic(sys::epic_read_thermistor_voltage)
+}
+
+/// Reads all measurements of the PMIC.
+pub fn read() -> Result<Reading> {
+    Ok(Reading {
+        battery_voltage: battery_voltage()?,
+        battery_current: battery_current()?,
+        chargein_voltage: chargein_voltage()?,
+        chargein_current: chargein_current()?,
+        system_voltage: system_voltage()?,
+        thermistor_voltage: thermistor_voltage()?,
+    })
+}
+
+/// Estimates the state of charge of a LiPo cell.
+///
+/// # Arguments
+/// * `voltage` - Open-circuit voltage of the cell in V
+///
+/// # Returns
+/// The state of charge in %, 0 <= state of charge <= 100.
+pub fn state_of_charge(voltage: f32) -> f32 {
+    let (first_voltage, first_charge) = DISCHARGE_CURVE[0];
+    if voltage <= first_voltage {
+        return first_charge;
+    }
+    let mut previous = (first_voltage, first_charge);
+    for &(v, charge) in DISCHARGE_CURVE.iter().skip(1) {
+        if voltage <= v {
+            let (previous_voltage, previous_charge) = previous;
+            return previous_charge
+                + (voltage - previous_voltage) / (v - previous_voltage)
+                    * (charge - previous_charge);
+        }
+        previous = (v, charge);
+    }
+    100.0
+}
+
+/// State of the battery charger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChargeState {
+    /// No charger is connected
+    Discharging,
+    Charging,
+    /// A charger is connected, but the battery is charged
+    Full,
+}
+
+/// Measurements of the PMIC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reading {
+    /// Voltage of the battery in V
+    pub battery_voltage: f32,
+    /// Current of the battery in A, positive while charging
+    pub battery_current: f32,
+    /// Voltage of the charge input in V
+    pub chargein_voltage: f32,
+    /// Current of the charge input in A
+    pub chargein_current: f32,
+    /// Voltage of the system supply in V
+    pub system_voltage: f32,
+    /// Voltage of the battery thermistor in V
+    pub thermistor_voltage: f32,
+}
+
+impl Reading {
+    /// True if a charger is connected.
+    pub fn is_charger_connected(&self) -> bool {
+        self.chargein_voltage >= CHARGER_VOLTAGE
+    }
+
+    /// The state of the charger.
+    pub fn charge_state(&self) -> ChargeState {
+        if !self.is_charger_connected() {
+            ChargeState::Discharging
+        } else if self.battery_current < FULL_CURRENT && self.battery_voltage >= FULL_VOLTAGE {
+            ChargeState::Full
+        } else {
+            ChargeState::Charging
+        }
+    }
+
+    /// Estimated state of charge of the battery in %.
+    ///
+    /// The voltage drop at the internal resistance of the battery is removed from the battery
+    /// voltage, so the estimate doesn't jump when the load changes or a charger is connected.
+    pub fn state_of_charge(&self) -> f32 {
+        state_of_charge(self.battery_voltage - self.battery_current * INTERNAL_RESISTANCE)
+    }
+}
+
+/// Changes of the power supply, see `Monitor`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerEvent {
+    ChargerConnected,
+    ChargerDisconnected,
+    /// Charging stopped because the battery is full
+    Charged,
+    /// The state of charge fell below the threshold of the `Monitor`
+    BatteryLow,
+}
+
+/// Detects changes of the power supply in successive readings.
+pub struct Monitor {
+    low_threshold: f32,
+    charger_connected: Option<bool>,
+    full: bool,
+    low: bool,
+}
+
+impl Monitor {
+    /// Creates a monitor, the charger state of the first reading is not reported as a change.
+    ///
+    /// # Arguments
+    /// * `low_threshold` - State of charge in % below which the battery is low
+    pub fn new(low_threshold: f32) -> Monitor {
+        Monitor {
+            low_threshold,
+            charger_connected: None,
+            full: false,
+            low: false,
+        }
+    }
+
+    /// Processes the next reading.
+    ///
+    /// # Returns
+    /// The first change since the previous reading, further changes are reported by the next
+    /// calls.
+    pub fn update(&mut self, reading: &Reading) -> Option<PowerEvent> {
+        let connected = reading.is_charger_connected();
+        // the battery is low until it is charged a few percent above the threshold
+        let charge = reading.state_of_charge();
+        if self.low && (connected || charge >= self.low_threshold + 5.0) {
+            self.low = false;
+        }
+
+        match self.charger_connected.replace(connected) {
+            Some(previous) if previous != connected => {
+                return Some(if connected {
+                    PowerEvent::ChargerConnected
+                } else {
+                    PowerEvent::ChargerDisconnected
+                });
+            }
+            _ => {}
+        }
+
+        let full = reading.charge_state() == ChargeState::Full;
+        if full != self.full {
+            self.full = full;
+            if full {
+                return Some(PowerEvent::Charged);
+            }
+        }
+
+        if !self.low && !connected && charge < self.low_threshold {
+            self.low = true;
+            return Some(PowerEvent::BatteryLow);
+        }
+        None
+    }
+
+    /// Reads the PMIC and processes the reading, see `update()`.
+    pub fn poll(&mut self) -> Result<Option<PowerEvent>> {
+        Ok(self.update(&read()?))
+    }
+
+    /// True if the last reading was below the threshold and no charger is connected.
+    pub fn is_low(&self) -> bool {
+        self.low
+    }
+}
+
+/// Battery symbol for a status bar, filled according to the state of charge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatteryIndicator {
+    /// X coordinate of the left edge
+    pub x: u16,
+    /// Y coordinate of the top edge
+    pub y: u16,
+    /// Width including the tip, at least 6 pixels
+    pub width: u16,
+    /// Height, at least 5 pixels
+    pub height: u16,
+    /// Color of the outline
+    pub outline: Color,
+    /// Color of the empty part
+    pub background: Color,
+    /// Color of the filled part while charging
+    pub charging: Color,
+}
+
+impl BatteryIndicator {
+    /// Creates an indicator with a white outline on black background.
+    ///
+    /// # Arguments
+    /// * `x` - X coordinate of the left edge
+    /// * `y` - Y coordinate of the top edge
+    /// * `width` - Width including the tip, at least 6 pixels
+    /// * `height` - Height, at least 5 pixels
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> BatteryIndicator {
+        BatteryIndicator {
+            x,
+            y,
+            width,
+            height,
+            outline: Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+            background: Color { r: 0, g: 0, b: 0 },
+            charging: Color {
+                r: 0,
+                g: 160,
+                b: 255,
+            },
+        }
+    }
+
+    /// Color of the filled part while discharging, from green to red.
+    pub fn level_color(state_of_charge: f32) -> Color {
+        if state_of_charge >= 50.0 {
+            Color { r: 0, g: 200, b: 0 }
+        } else if state_of_charge >= 20.0 {
+            Color {
+                r: 255,
+                g: 180,
+                b: 0,
+            }
+        } else {
+            Color { r: 255, g: 0, b: 0 }
+        }
+    }
+
+    /// Draws the indicator for a reading.
+    pub fn draw<C: Canvas>(&self, canvas: &mut C, reading: &Reading) -> Result<()> {
+        let charging = reading.charge_state() != ChargeState::Discharging;
+        self.draw_level(canvas, reading.state_of_charge(), charging)
+    }
+
+    /// Draws the indicator for a state of charge.
+    ///
+    /// # Arguments
+    /// * `canvas` - The display or framebuffer to draw on
+    /// * `state_of_charge` - State of charge in %
+    /// * `charging` - Whether a charger is connected
+    pub fn draw_level<C: Canvas>(
+        &self,
+        canvas: &mut C,
+        state_of_charge: f32,
+        charging: bool,
+    ) -> Result<()> {
+        if self.width < 6 || self.height < 5 {
+            return Err(Error::InvalidArgument);
+        }
+        let (x, y) = (self.x, self.y);
+        // the body without the tip of 2 pixels
+        let right = x + self.width - 3;
+        let bottom = y + self.height - 1;
+        canvas.fill_rect(x, y, right, y, self.outline)?;
+        canvas.fill_rect(x, bottom, right, bottom, self.outline)?;
+        canvas.fill_rect(x, y, x, bottom, self.outline)?;
+        canvas.fill_rect(right, y, right, bottom, self.outline)?;
+        let tip = self.height / 4;
+        canvas.fill_rect(right + 1, y + tip, right + 2, bottom - tip, self.outline)?;
+
+        // the inside has a margin of 1 pixel to the outline
+        let (left, top) = (x + 2, y + 2);
+        let inner_right = right - 2;
+        let inner_bottom = bottom - 2;
+        if inner_right < left || inner_bottom < top {
+            return Ok(());
+        }
+        let width = inner_right - left + 1;
+        let charge = state_of_charge.clamp(0.0, 100.0);
+        let filled = libm::roundf(charge / 100.0 * f32::from(width)) as u16;
+        canvas.fill_rect(x + 1, y + 1, right - 1, bottom - 1, self.background)?;
+        if filled > 0 {
+            let color = if charging {
+                self.charging
+            } else {
+                BatteryIndicator::level_color(charge)
+            };
+            canvas.fill_rect(left, top, left + filled - 1, inner_bottom, color)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parameters of a power `Policy`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolicyConfig {
+    /// Backlight brightness (0 to 100) in darkness
+    pub min_backlight: u16,
+    /// Backlight brightness (0 to 100) in bright light
+    pub max_backlight: u16,
+    /// Light sensor value at and below which `min_backlight` is used
+    pub dark_light: u16,
+    /// Light sensor value at and above which `max_backlight` is used
+    pub bright_light: u16,
+    /// Time without activity after which the display is dimmed
+    pub dim_timeout: Duration,
+    /// Backlight brightness (0 to 100) of the dimmed display
+    pub dim_backlight: u16,
+    /// Time without activity after which the backlight is turned off
+    pub dark_timeout: Duration,
+    /// Minimal change of the acceleration in m/s² between two samples which counts as activity
+    pub motion_threshold: f32,
+    /// State of charge in % below which the battery is low
+    pub low_battery: f32,
+    /// Global brightness of the top and bottom LEDs (1 to 8) while the battery is not low
+    pub led_dim: (u8, u8),
+    /// Global brightness of the top and bottom LEDs (1 to 8) while the battery is low
+    pub low_battery_led_dim: (u8, u8),
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        PolicyConfig {
+            min_backlight: 10,
+            max_backlight: 100,
+            dark_light: 5,
+            bright_light: 200,
+            dim_timeout: Duration::from_secs(20),
+            dim_backlight: 5,
+            dark_timeout: Duration::from_secs(60),
+            motion_threshold: 1.5,
+            low_battery: 20.0,
+            led_dim: (1, 8),
+            low_battery_led_dim: (1, 1),
+        }
+    }
+}
+
+/// State of the display in a power `Policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayState {
+    /// The backlight follows the ambient light
+    Active,
+    /// The backlight is dimmed after `dim_timeout` without activity
+    Dimmed,
+    /// The backlight is off after `dark_timeout` without activity
+    Dark,
+}
+
+/// State of a power `Policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PolicyState {
+    pub display: DisplayState,
+    /// True while the battery is low and the LEDs are dimmed
+    pub battery_low: bool,
+}
+
+/// Buttons which count as activity, the reset button ends the l0dable anyway.
+const ACTIVITY_BUTTONS: u8 = buttons::LEFT_BOTTOM | buttons::RIGHT_BOTTOM | buttons::RIGHT_TOP;
+
+/// Power saving an app can opt into: the backlight follows the ambient light and is dimmed and
+/// turned off after inactivity, and the LEDs are dimmed while the battery is low.
+///
+/// Buttons count as activity, motion is detected in the samples passed to `update_motion()`
+/// and other activity is reported with `activity()`. Call `update()` regularly, e.g. in the main
+/// loop, to apply the policy. The light sensor is running while the policy exists.
+///
+/// When the policy is dropped, the backlight is set to the brightness for the ambient light of
+/// the last update and the LEDs get the default brightness and powersave mode of the firmware
+/// again. Drop the policy before the display is closed, otherwise the backlight can't be set.
+///
+/// # Example
+/// ```
+/// use rustcardium::display::Display;
+/// use rustcardium::power::{DisplayState, Policy, PolicyConfig, PolicyState};
+///
+/// fn changed(state: PolicyState) {
+///     if state.display == DisplayState::Dark {
+///         // stop the animations
+///     }
+/// }
+///
+/// let display = Display::open()?;
+/// let mut policy = Policy::new(PolicyConfig::default())?;
+/// policy.on_change(changed);
+/// loop {
+///     policy.update(&display)?;
+///     // draw the next frame
+/// #   break;
+/// }
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+pub struct Policy {
+    config: PolicyConfig,
+    state: PolicyState,
+    last_activity: Instant,
+    previous_motion: Option<[f32; 3]>,
+    light_sensor: LightSensor,
+    battery: Monitor,
+    ambient: Option<u16>,
+    backlight: Option<u16>,
+    handler: Option<fn(PolicyState)>,
+}
+
+impl Policy {
+    /// Creates a policy, starts the light sensor and dims the LEDs to `led_dim`, the display is
+    /// active.
+    pub fn new(config: PolicyConfig) -> Result<Policy> {
+        let light_sensor = LightSensor::start()?;
+        Policy::dim_leds(config.led_dim);
+        Ok(Policy {
+            config,
+            state: PolicyState {
+                display: DisplayState::Active,
+                battery_low: false,
+            },
+            last_activity: Instant::now(),
+            previous_motion: None,
+            light_sensor,
+            battery: Monitor::new(config.low_battery),
+            ambient: None,
+            backlight: None,
+            handler: None,
+        })
+    }
+
+    /// The configuration of this policy.
+    pub fn config(&self) -> &PolicyConfig {
+        &self.config
+    }
+
+    /// The current state.
+    pub fn state(&self) -> PolicyState {
+        self.state
+    }
+
+    /// The backlight brightness set by the last update.
+    pub fn backlight(&self) -> Option<u16> {
+        self.backlight
+    }
+
+    /// Sets the function which is called from `update()` when the state changes.
+    pub fn on_change(&mut self, handler: fn(PolicyState)) {
+        self.handler = Some(handler);
+    }
+
+    /// Reports activity of the user, e.g. a touch or a received message.
+    pub fn activity(&mut self) {
+        self.last_activity = Instant::now();
+    }
+
+    /// Processes a sample of the accelerometer, movements count as activity.
+    pub fn update_motion(&mut self, a: &Acceleration) {
+        let current = [a.x, a.y, a.z];
+        if let Some(previous) = self.previous_motion.replace(current) {
+            let change = (0..3)
+                .map(|i| (current[i] - previous[i]).abs())
+                .fold(0.0f32, f32::max);
+            if change >= self.config.motion_threshold {
+                self.activity();
+            }
+        }
+    }
+
+    /// Reads the buttons, the light sensor and the battery and applies the policy.
+    ///
+    /// # Arguments
+    /// * `display` - The display whose backlight is controlled
+    ///
+    /// # Returns
+    /// The new state if it changed, the handler set with `on_change()` is called as well.
+    pub fn update(&mut self, display: &Display) -> Result<Option<PolicyState>> {
+        if buttons::read(ACTIVITY_BUTTONS) != 0 {
+            self.activity();
+        }
+        let idle = self.last_activity.elapsed();
+        let display_state = if idle >= self.config.dark_timeout {
+            DisplayState::Dark
+        } else if idle >= self.config.dim_timeout {
+            DisplayState::Dimmed
+        } else {
+            DisplayState::Active
+        };
+
+        let light = self.light_sensor.read_smoothed()?;
+        let ambient = self.ambient_backlight(light);
+        self.ambient = Some(ambient);
+        let backlight = match display_state {
+            DisplayState::Active => ambient,
+            DisplayState::Dimmed => ambient.min(self.config.dim_backlight),
+            DisplayState::Dark => 0,
+        };
+        if self.backlight != Some(backlight) {
+            display.backlight(backlight)?;
+            self.backlight = Some(backlight);
+        }
+
+        self.battery.poll()?;
+        let battery_low = self.battery.is_low();
+        if battery_low != self.state.battery_low {
+            if battery_low {
+                Policy::dim_leds(self.config.low_battery_led_dim);
+                leds::set_powersave(true);
+            } else {
+                Policy::dim_leds(self.config.led_dim);
+                leds::set_powersave(false);
+            }
+        }
+
+        let state = PolicyState {
+            display: display_state,
+            battery_low,
+        };
+        if state == self.state {
+            return Ok(None);
+        }
+        self.state = state;
+        if let Some(handler) = self.handler {
+            handler(state);
+        }
+        Ok(Some(state))
+    }
+
+    /// Sets the global brightness of the top and bottom LEDs.
+    fn dim_leds((top, bottom): (u8, u8)) {
+        leds::dim_top(top);
+        leds::dim_bottom(bottom);
+    }
+
+    /// Backlight brightness for the ambient light, interpolated between the configured limits.
+    fn ambient_backlight(&self, light: f32) -> u16 {
+        let dark = f32::from(self.config.dark_light);
+        let bright = f32::from(self.config.bright_light).max(dark + 1.0);
+        let t = ((light - dark) / (bright - dark)).clamp(0.0, 1.0);
+        let min = f32::from(self.config.min_backlight.min(100));
+        let max = f32::from(self.config.max_backlight.min(100));
+        libm::roundf(min + t * (max - min)) as u16
+    }
+}
+
+impl Drop for Policy {
+    fn drop(&mut self) {
+        // the defaults of the firmware
+        leds::dim_top(1);
+        leds::dim_bottom(8);
+        leds::set_powersave(true);
+        if let Some(ambient) = self.ambient {
+            if self.backlight != Some(ambient) {
+                unsafe {
+                    sys::epic_disp_backlight(ambient);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::bhi160::test_util::accel;
+    use crate::display::Display;
+    use crate::framebuffer::Framebuffer;
+    use crate::mock;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    fn shown(x: u16, y: u16) -> u16 {
+        mock::with(|s| s.screen_pixel(usize::from(x), usize::from(y)))
+    }
+
+    /// A reading of the PMIC, the charger is connected if `chargein_voltage` is at least 4 V.
+    fn reading(battery_voltage: f32, battery_current: f32, chargein_voltage: f32) -> Reading {
+        Reading {
+            battery_voltage,
+            battery_current,
+            chargein_voltage,
+            chargein_current: 0.0,
+            system_voltage: battery_voltage,
+            thermistor_voltage: 1.0,
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn state_of_charge_interpolates_the_discharge_curve() {
+        // the points of the curve
+        assert_close(state_of_charge(3.27), 0.0);
+        assert_close(state_of_charge(3.61), 5.0);
+        assert_close(state_of_charge(3.84), 50.0);
+        assert_close(state_of_charge(4.20), 100.0);
+        // between the points
+        assert_close(state_of_charge(3.44), 2.5);
+        assert_close(state_of_charge(3.845), 52.5);
+        assert_close(state_of_charge(4.17), 97.0);
+        // clamped outside of the curve
+        assert_close(state_of_charge(0.0), 0.0);
+        assert_close(state_of_charge(3.0), 0.0);
+        assert_close(state_of_charge(4.5), 100.0);
+        // and monotonic
+        let mut previous = 0.0;
+        for i in 0..=150 {
+            let charge = state_of_charge(3.0 + i as f32 * 0.01);
+            assert!(charge >= previous, "{} V", 3.0 + i as f32 * 0.01);
+            previous = charge;
+        }
+    }
+
+    #[test]
+    fn state_of_charge_of_reading_removes_the_internal_resistance() {
+        // 3.8 V + 0.2 A * 0.15 Ω
+        assert_close(reading(3.8, -0.2, 0.0).state_of_charge(), 47.5);
+        // 4.0 V - 0.5 A * 0.15 Ω
+        assert_close(reading(4.0, 0.5, 5.0).state_of_charge(), 66.875);
+        assert_close(reading(3.84, 0.0, 0.0).state_of_charge(), 50.0);
+    }
+
+    #[test]
+    fn charge_state() {
+        assert_eq!(
+            reading(3.9, -0.05, 0.0).charge_state(),
+            ChargeState::Discharging
+        );
+        assert_eq!(
+            reading(3.9, -0.05, 3.9).charge_state(),
+            ChargeState::Discharging
+        );
+        assert_eq!(reading(3.9, 0.3, 5.0).charge_state(), ChargeState::Charging);
+        // the current is low at the end of charging, but the voltage is still low
+        assert_eq!(
+            reading(4.05, 0.005, 5.0).charge_state(),
+            ChargeState::Charging
+        );
+        assert_eq!(reading(4.15, 0.005, 5.0).charge_state(), ChargeState::Full);
+    }
+
+    #[test]
+    fn monitor_reports_the_charger() {
+        let mut monitor = Monitor::new(10.0);
+        // the first reading isn't a change
+        assert_eq!(monitor.update(&reading(3.9, 0.3, 5.0)), None);
+        assert_eq!(monitor.update(&reading(4.0, 0.2, 5.0)), None);
+        assert_eq!(
+            monitor.update(&reading(4.15, 0.0, 5.0)),
+            Some(PowerEvent::Charged)
+        );
+        assert_eq!(monitor.update(&reading(4.15, 0.0, 5.0)), None);
+        assert_eq!(
+            monitor.update(&reading(4.1, -0.05, 0.0)),
+            Some(PowerEvent::ChargerDisconnected)
+        );
+        assert_eq!(monitor.update(&reading(4.1, -0.05, 0.0)), None);
+        assert_eq!(
+            monitor.update(&reading(4.0, 0.3, 5.0)),
+            Some(PowerEvent::ChargerConnected)
+        );
+        // charged again after connecting the charger to a full battery
+        assert_eq!(
+            monitor.update(&reading(4.15, 0.0, 5.0)),
+            Some(PowerEvent::Charged)
+        );
+    }
+
+    #[test]
+    fn monitor_reports_low_battery_once() {
+        let mut monitor = Monitor::new(10.0);
+        // about 8.6 %
+        assert_eq!(
+            monitor.update(&reading(3.66, -0.05, 0.0)),
+            Some(PowerEvent::BatteryLow)
+        );
+        assert!(monitor.is_low());
+        assert_eq!(monitor.update(&reading(3.66, -0.05, 0.0)), None);
+        // about 11.9 %, the battery stays low until 15 %
+        assert_eq!(monitor.update(&reading(3.69, -0.05, 0.0)), None);
+        assert!(monitor.is_low());
+        assert_eq!(monitor.update(&reading(3.66, -0.05, 0.0)), None);
+        // about 15.6 %
+        assert_eq!(monitor.update(&reading(3.705, -0.05, 0.0)), None);
+        assert!(!monitor.is_low());
+        assert_eq!(
+            monitor.update(&reading(3.66, -0.05, 0.0)),
+            Some(PowerEvent::BatteryLow)
+        );
+    }
+
+    #[test]
+    fn monitor_charger_clears_low_battery() {
+        let mut monitor = Monitor::new(10.0);
+        assert_eq!(
+            monitor.update(&reading(3.6, -0.05, 0.0)),
+            Some(PowerEvent::BatteryLow)
+        );
+        assert_eq!(
+            monitor.update(&reading(3.7, 0.4, 5.0)),
+            Some(PowerEvent::ChargerConnected)
+        );
+        assert!(!monitor.is_low());
+        assert_eq!(monitor.update(&reading(3.7, 0.4, 5.0)), None);
+        // disconnected before the battery is charged
+        assert_eq!(
+            monitor.update(&reading(3.6, -0.05, 0.0)),
+            Some(PowerEvent::ChargerDisconnected)
+        );
+        assert_eq!(
+            monitor.update(&reading(3.6, -0.05, 0.0)),
+            Some(PowerEvent::BatteryLow)
+        );
+    }
+
+    #[test]
+    fn monitor_polls_the_pmic() {
+        mock::reset();
+        let mut monitor = Monitor::new(10.0);
+        assert_eq!(monitor.poll(), Ok(None));
+        mock::with(|s| s.chargein_voltage = 5.0);
+        assert_eq!(monitor.poll(), Ok(Some(PowerEvent::ChargerConnected)));
+        mock::push_return("epic_read_battery_voltage", -(mock::errno::EIO as i32));
+        assert_eq!(monitor.poll(), Err(Error::IoError));
+    }
+
+    #[test]
+    fn battery_indicator_on_display() {
+        mock::reset();
+        let indicator = BatteryIndicator::new(2, 3, 20, 10);
+        let mut display = Display::open().unwrap();
+        indicator.draw_level(&mut display, 50.0, false).unwrap();
+        display.update().unwrap();
+
+        let outline = indicator.outline.rgb565();
+        let fill = BatteryIndicator::level_color(50.0).rgb565();
+        // the one pixel wide outline with its corners
+        for &(x, y) in &[(2, 3), (19, 3), (2, 12), (19, 12), (10, 3), (2, 8), (19, 8)] {
+            assert_eq!(shown(x, y), outline);
+        }
+        // the tip
+        for &(x, y) in &[(20, 5), (21, 5), (20, 10), (21, 10)] {
+            assert_eq!(shown(x, y), outline);
+        }
+        assert_eq!(shown(20, 4), 0);
+        assert_eq!(shown(22, 8), 0);
+        // half of the 14 pixels of the inside are filled
+        for &(x, y) in &[(4, 5), (10, 5), (4, 10), (10, 10)] {
+            assert_eq!(shown(x, y), fill);
+        }
+        assert_eq!(shown(11, 5), 0);
+        assert_eq!(shown(3, 4), 0);
+    }
+
+    #[test]
+    fn battery_indicator_on_display_matches_framebuffer() {
+        mock::reset();
+        let mut indicator = BatteryIndicator::new(0, 0, 13, 7);
+        indicator.background = Color {
+            r: 40,
+            g: 40,
+            b: 40,
+        };
+        let mut display = Display::open().unwrap();
+        let mut fb = Framebuffer::new();
+        indicator.draw_level(&mut display, 30.0, true).unwrap();
+        indicator.draw_level(&mut fb, 30.0, true).unwrap();
+        display.update().unwrap();
+        for y in 0..10 {
+            for x in 0..16 {
+                assert_eq!(
+                    shown(x, y),
+                    fb.pixel(x, y).unwrap().rgb565(),
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    /// Sets the time since the start in ms and the battery, without current and charger.
+    fn set_time_and_battery(milliseconds: u64, battery_voltage: f32) {
+        mock::set_rtc_milliseconds(milliseconds);
+        mock::with(|s| {
+            s.battery_voltage = battery_voltage;
+            s.battery_current = 0.0;
+        });
+    }
+
+    fn leds() -> (u8, u8, bool) {
+        mock::with(|s| (s.leds_dim_top, s.leds_dim_bottom, s.leds_powersave))
+    }
+
+    fn display_state(change: Option<PolicyState>) -> Option<DisplayState> {
+        change.map(|state| state.display)
+    }
+
+    #[test]
+    fn policy_dims_and_darkens_the_display() {
+        mock::reset();
+        set_time_and_battery(1000, 3.9);
+        mock::with(|s| s.light_sensor_value = 200);
+        let display = Display::open().unwrap();
+        let mut policy = Policy::new(PolicyConfig::default()).unwrap();
+        assert_eq!(policy.update(&display).unwrap(), None);
+        assert_eq!(mock::with(|s| s.backlight), 100);
+
+        mock::set_rtc_milliseconds(20_500);
+        assert_eq!(policy.update(&display).unwrap(), None);
+        mock::set_rtc_milliseconds(21_000);
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Dimmed));
+        assert_eq!(mock::with(|s| s.backlight), 5);
+
+        mock::set_rtc_milliseconds(61_000);
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Dark));
+        assert_eq!(mock::with(|s| s.backlight), 0);
+        assert_eq!(policy.backlight(), Some(0));
+    }
+
+    #[test]
+    fn activity_resets_the_timeouts() {
+        mock::reset();
+        set_time_and_battery(0, 3.9);
+        mock::with(|s| s.light_sensor_value = 200);
+        let display = Display::open().unwrap();
+        let mut policy = Policy::new(PolicyConfig::default()).unwrap();
+
+        mock::set_rtc_milliseconds(20_000);
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Dimmed));
+        mock::set_buttons(buttons::RIGHT_TOP);
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Active));
+        assert_eq!(mock::with(|s| s.backlight), 100);
+        mock::set_buttons(0);
+
+        // small changes of the acceleration are no activity
+        mock::set_rtc_milliseconds(30_000);
+        policy.update_motion(&accel(0.0, 0.0, 9.8));
+        policy.update_motion(&accel(0.5, 0.0, 9.8));
+        mock::set_rtc_milliseconds(40_000);
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Dimmed));
+
+        mock::set_rtc_milliseconds(100_000);
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Dark));
+        policy.update_motion(&accel(3.0, 0.0, 9.8));
+        let change = policy.update(&display).unwrap();
+        assert_eq!(display_state(change), Some(DisplayState::Active));
+        mock::set_rtc_milliseconds(119_000);
+        assert_eq!(policy.update(&display).unwrap(), None);
+
+        policy.activity();
+        mock::set_rtc_milliseconds(138_000);
+        assert_eq!(policy.update(&display).unwrap(), None);
+    }
+
+    #[test]
+    fn low_battery_dims_the_leds() {
+        mock::reset();
+        set_time_and_battery(0, 3.9);
+        mock::with(|s| {
+            s.leds_dim_top = 8;
+            s.leds_powersave = false;
+        });
+        let display = Display::open().unwrap();
+        let mut policy = Policy::new(PolicyConfig::default()).unwrap();
+        assert_eq!(leds(), (1, 8, false));
+        assert_eq!(policy.update(&display).unwrap(), None);
+
+        // 17.5 %
+        set_time_and_battery(1000, 3.72);
+        let change = policy.update(&display).unwrap().unwrap();
+        assert!(change.battery_low);
+        assert_eq!(leds(), (1, 1, true));
+        // 22.5 % is still low, 27.5 % is above the hysteresis
+        set_time_and_battery(2000, 3.74);
+        assert_eq!(policy.update(&display).unwrap(), None);
+        set_time_and_battery(3000, 3.76);
+        let change = policy.update(&display).unwrap().unwrap();
+        assert!(!change.battery_low);
+        assert_eq!(leds(), (1, 8, false));
+
+        // a charger ends the low battery immediately
+        set_time_and_battery(4000, 3.72);
+        assert!(policy.update(&display).unwrap().unwrap().battery_low);
+        mock::with(|s| s.chargein_voltage = 5.0);
+        assert!(!policy.update(&display).unwrap().unwrap().battery_low);
+        assert_eq!(leds(), (1, 8, false));
+    }
+
+    static CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_change(_state: PolicyState) {
+        CHANGES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn on_change_is_called_once_per_change() {
+        mock::reset();
+        set_time_and_battery(0, 3.9);
+        let display = Display::open().unwrap();
+        let mut policy = Policy::new(PolicyConfig::default()).unwrap();
+        policy.on_change(count_change);
+        let mut changes = 0;
+        for &(milliseconds, voltage) in &[
+            (0, 3.9),
+            (10_000, 3.9),
+            (20_000, 3.9),
+            (25_000, 3.9),
+            (25_000, 3.72),
+            (30_000, 3.72),
+            (60_000, 3.72),
+            (70_000, 3.8),
+            (80_000, 3.8),
+        ] {
+            set_time_and_battery(milliseconds, voltage);
+            if policy.update(&display).unwrap().is_some() {
+                changes += 1;
+            }
+        }
+        // dimmed, low battery, dark, battery recovered
+        assert_eq!(changes, 4);
+        assert_eq!(CHANGES.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn drop_restores_the_backlight_and_the_leds() {
+        mock::reset();
+        set_time_and_battery(0, 3.72);
+        mock::with(|s| s.light_sensor_value = 200);
+        let display = Display::open().unwrap();
+        let mut policy = Policy::new(PolicyConfig::default()).unwrap();
+        mock::set_rtc_milliseconds(60_000);
+        policy.update(&display).unwrap();
+        assert_eq!(policy.state().display, DisplayState::Dark);
+        assert_eq!(mock::with(|s| s.backlight), 0);
+        mock::with(|s| s.leds_powersave = false);
+        drop(policy);
+        assert_eq!(mock::with(|s| s.backlight), 100);
+        assert_eq!(leds(), (1, 8, true));
+        assert!(!mock::with(|s| s.light_sensor_running));
+    }
+}
@@ -0,0 +1,980 @@
+//! Records samples of the BHI160, the BME680, the light sensor and the battery to files.
+//!
+//! A `Recorder` reads all added sources in `poll()`, timestamps the samples with the RTC and
+//! writes them through a buffer to the FAT file system. Two formats are supported:
+//!
+//! * `Format::Csv`: Comment lines `# <source>: <fields>` describe the channels, followed by the
+//!   rows `<timestamp_ms>,<source>,<values>`.
+//! * `Format::Binary`: A header with the magic `C10R`, the version, the number of channels,
+//!   the start time in ms since the Unix epoch (u64) and the source id and value count of each
+//!   channel, followed by the records. Each record has the channel index (u8), the time since the
+//!   start in ms (u32) and its values (f32). All numbers are little endian.
+//!
+//! The files are named `<prefix>_<index>.csv` or `.bin`, the index starts at the first unused
+//! number. When a file would exceed `Recorder::set_max_file_size()`, recording continues in a
+//! new file with the next index. Binary files are read with the `Decoder`, which also works on
+//! the host, e.g. with a file read into a `&[u8]`.
+//!
+//! # Example
+//! ```
+//! use rustcardium::bhi160::{SensorConfig, SensorType};
+//! use rustcardium::recorder::{Decoder, Format, Recorder, Source};
+//! use rustcardium::time::Duration;
+//!
+//! let mut recorder = Recorder::new("/motion", Format::Binary)?;
+//! recorder.set_max_file_size(Some(64 * 1024));
+//! recorder.add_bhi160(
+//!     SensorType::Accelerometer,
+//!     SensorConfig {
+//!         sample_buffer_len: 120,
+//!         sample_rate: 25,
+//!         dynamic_range: 4,
+//!     },
+//! )?;
+//! recorder.add_battery(Duration::from_secs(10))?;
+//! # rustcardium::mock::push_bhi160_samples(0, &[(0, 0, 8192, 3); 25]);
+//! recorder.poll()?;
+//! recorder.finish()?;
+//!
+//! let file = rustcardium::fs::File::open("/motion_000.bin")?;
+//! let decoder = Decoder::new(file)?;
+//! assert_eq!(decoder.channels(), &[Source::Bhi160(SensorType::Accelerometer), Source::Battery]);
+//! for record in decoder {
+//!     let record = record?;
+//!     // record.timestamp, record.source, record.values()
+//! }
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::bhi160::{DataVector, Sample, Sensor, SensorConfig, SensorType, DRAIN_CHUNK_LEN};
+use crate::bme680::Bme680;
+use crate::fs::{self, File, PathBuf};
+use crate::io::{Read, Write};
+use crate::light_sensor::LightSensor;
+use crate::power;
+use crate::time::{Duration, Instant, SystemTime};
+use arrayvec::ArrayVec;
+use core::fmt::Write as _;
+
+/// Maximum number of channels of a recorder.
+pub const MAX_CHANNELS: usize = 8;
+
+/// Maximum number of values of a sample.
+pub const MAX_VALUES: usize = 4;
+
+/// Size of the write buffer of a recorder in bytes.
+pub const BUFFER_LEN: usize = 512;
+
+const MAGIC: &[u8; 4] = b"C10R";
+const VERSION: u8 = 1;
+
+/// Highest index in the file names, `<prefix>_999`.
+const MAX_INDEX: u16 = 999;
+
+/// Format of the recorded files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Binary,
+}
+
+impl Format {
+    /// The file extension without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Binary => "bin",
+        }
+    }
+}
+
+/// A source of samples which can be recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Samples of a BHI160 sensor in physical units, see `bhi160::Sample`
+    Bhi160(SensorType),
+    /// Temperature in °C, humidity in %, pressure in hPa and gas resistance in Ω
+    Bme680,
+    /// Raw value of the light sensor
+    LightSensor,
+    /// Battery voltage in V and current in A
+    Battery,
+}
+
+impl Source {
+    /// Id of the source in binary files, the sensor type id for BHI160 sources.
+    pub fn id(self) -> u8 {
+        match self {
+            Source::Bhi160(sensor_type) => sensor_type as u8,
+            Source::Bme680 => 0x20,
+            Source::LightSensor => 0x21,
+            Source::Battery => 0x22,
+        }
+    }
+
+    /// The source with the given id, if it is known.
+    pub fn from_id(id: u8) -> Option<Source> {
+        match id {
+            0x20 => Some(Source::Bme680),
+            0x21 => Some(Source::LightSensor),
+            0x22 => Some(Source::Battery),
+            id => SensorType::from_id(u32::from(id)).map(Source::Bhi160),
+        }
+    }
+
+    /// Name of the source in CSV files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Bhi160(sensor_type) => match sensor_type {
+                SensorType::Accelerometer => "accelerometer",
+                SensorType::Magnetometer => "magnetometer",
+                SensorType::Orientation => "orientation",
+                SensorType::Gyroscope => "gyroscope",
+                SensorType::Gravity => "gravity",
+                SensorType::LinearAcceleration => "linear_acceleration",
+                SensorType::RotationVector => "rotation_vector",
+                SensorType::UncalibratedMagnetometer => "uncalibrated_magnetometer",
+                SensorType::GameRotationVector => "game_rotation_vector",
+                SensorType::UncalibratedGyroscope => "uncalibrated_gyroscope",
+                SensorType::GeomagneticRotationVector => "geomagnetic_rotation_vector",
+            },
+            Source::Bme680 => "bme680",
+            Source::LightSensor => "light_sensor",
+            Source::Battery => "battery",
+        }
+    }
+
+    /// Names of the values of a sample, including their unit.
+    pub fn fields(self) -> &'static [&'static str] {
+        match self {
+            Source::Bhi160(sensor_type) => match sensor_type {
+                SensorType::Accelerometer
+                | SensorType::Gravity
+                | SensorType::LinearAcceleration => &["x_m_s2", "y_m_s2", "z_m_s2"],
+                SensorType::Gyroscope | SensorType::UncalibratedGyroscope => {
+                    &["x_deg_s", "y_deg_s", "z_deg_s"]
+                }
+                SensorType::Orientation => &["heading_deg", "pitch_deg", "roll_deg"],
+                SensorType::RotationVector
+                | SensorType::GameRotationVector
+                | SensorType::GeomagneticRotationVector => &["w", "x", "y", "z"],
+                SensorType::Magnetometer | SensorType::UncalibratedMagnetometer => {
+                    &["x_raw", "y_raw", "z_raw"]
+                }
+            },
+            Source::Bme680 => &[
+                "temperature_c",
+                "humidity_percent",
+                "pressure_hpa",
+                "gas_resistance_ohm",
+            ],
+            Source::LightSensor => &["light"],
+            Source::Battery => &["voltage_v", "current_a"],
+        }
+    }
+}
+
+/// A recorded sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Record {
+    pub timestamp: SystemTime,
+    pub source: Source,
+    values: [f32; MAX_VALUES],
+    len: usize,
+}
+
+impl Record {
+    /// Creates a record, values beyond `MAX_VALUES` are ignored.
+    pub fn new(timestamp: SystemTime, source: Source, values: &[f32]) -> Record {
+        let len = values.len().min(MAX_VALUES);
+        let mut record = Record {
+            timestamp,
+            source,
+            values: [0.0; MAX_VALUES],
+            len,
+        };
+        record.values[..len].copy_from_slice(&values[..len]);
+        record
+    }
+
+    /// The values of the sample, in the order of `Source::fields()`.
+    pub fn values(&self) -> &[f32] {
+        &self.values[..self.len]
+    }
+}
+
+/// How the samples of a channel are read.
+enum Input {
+    /// The available samples are read in every poll
+    Bhi160 {
+        sensor: Sensor,
+        period_us: u64,
+        max_lag_us: u64,
+        last_us: Option<u64>,
+    },
+    /// A single sample is read in every interval
+    Polled {
+        interval: Duration,
+        next: Option<Instant>,
+        device: Device,
+    },
+}
+
+/// Handle which keeps the sensor of a polled source running.
+enum Device {
+    None,
+    Bme680(Bme680),
+    LightSensor(LightSensor),
+}
+
+struct Channel {
+    source: Source,
+    input: Input,
+}
+
+/// Reads a single sample of a polled source.
+fn read_polled(source: Source, device: &Device) -> Result<ArrayVec<[f32; MAX_VALUES]>> {
+    let mut values = ArrayVec::new();
+    match (source, device) {
+        (Source::Bme680, Device::Bme680(bme680)) => {
+            let reading = bme680.read()?;
+            values.push(reading.temperature);
+            values.push(reading.humidity);
+            values.push(reading.pressure);
+            values.push(reading.gas_resistance);
+        }
+        (Source::LightSensor, Device::LightSensor(sensor)) => {
+            values.push(f32::from(sensor.read()?));
+        }
+        (Source::Battery, _) => {
+            values.push(power::battery_voltage()?);
+            values.push(power::battery_current()?);
+        }
+        _ => return Err(Error::InvalidArgument),
+    }
+    Ok(values)
+}
+
+/// Values of a BHI160 sample in the order of `Source::fields()`.
+fn sample_values(sample: &Sample) -> ArrayVec<[f32; MAX_VALUES]> {
+    let mut values = ArrayVec::new();
+    match sample {
+        Sample::Acceleration(a) => values.extend([a.x, a.y, a.z].iter().copied()),
+        Sample::AngularRate(r) => values.extend([r.x, r.y, r.z].iter().copied()),
+        Sample::Orientation(o) => values.extend([o.heading, o.pitch, o.roll].iter().copied()),
+        Sample::Quaternion(q) => values.extend([q.w, q.x, q.y, q.z].iter().copied()),
+        Sample::Raw(raw) => values.extend(
+            [f32::from(raw.x), f32::from(raw.y), f32::from(raw.z)]
+                .iter()
+                .copied(),
+        ),
+    }
+    values
+}
+
+/// Records samples of several sources to CSV or binary files.
+///
+/// Buffered samples are written out when the recorder is dropped, use `finish()` to check for
+/// errors.
+pub struct Recorder {
+    prefix: PathBuf,
+    format: Format,
+    max_file_size: Option<u32>,
+    channels: ArrayVec<[Channel; MAX_CHANNELS]>,
+    file: Option<File>,
+    path: PathBuf,
+    next_index: u16,
+    /// Length of the current file including the buffered data
+    file_len: u32,
+    /// Start time of the current file in ms since the Unix epoch
+    start_ms: u64,
+    buffer: [u8; BUFFER_LEN],
+    buffered: usize,
+}
+
+impl Recorder {
+    /// Creates a recorder without channels, the first file is created when the first sample is
+    /// recorded.
+    ///
+    /// # Arguments
+    /// * `prefix` - Absolute path of the files without the index and the extension, e.g. `"/logs/walk"`
+    /// * `format` - Format of the files
+    pub fn new(prefix: &str, format: Format) -> Result<Recorder> {
+        // the longest suffix is "_999.csv"
+        if prefix.len() + 8 > fs::MAX_PATH {
+            return Err(Error::NameTooLong);
+        }
+        Ok(Recorder {
+            prefix: PathBuf::from(prefix).map_err(|_| Error::NameTooLong)?,
+            format,
+            max_file_size: None,
+            channels: ArrayVec::new(),
+            file: None,
+            path: PathBuf::new(),
+            next_index: 0,
+            file_len: 0,
+            start_ms: 0,
+            buffer: [0; BUFFER_LEN],
+            buffered: 0,
+        })
+    }
+
+    /// Sets the maximal size of a file in bytes, larger recordings are split into several files.
+    ///
+    /// # Arguments
+    /// * `max_file_size` - Maximal size in bytes or None for no limit
+    pub fn set_max_file_size(&mut self, max_file_size: Option<u32>) {
+        self.max_file_size = max_file_size;
+    }
+
+    /// The format of the recorded files.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Path of the current file, empty before the first sample is recorded.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The sources of the recorded channels, in the order they were added.
+    pub fn sources(&self) -> impl Iterator<Item = Source> + '_ {
+        self.channels.iter().map(|c| c.source)
+    }
+
+    /// Enables a BHI160 sensor and records all its samples.
+    ///
+    /// The samples carry no timestamp, so they are timestamped with the sample period, starting
+    /// when they are first read. Adding a channel while recording continues in a new file.
+    ///
+    /// # Arguments
+    /// * `sensor_type` - The sensor to record
+    /// * `cfg` - Configuration of the sensor, see `bhi160::Sensor::enable()`
+    pub fn add_bhi160(&mut self, sensor_type: SensorType, cfg: SensorConfig) -> Result<()> {
+        self.check_new(Source::Bhi160(sensor_type))?;
+        let period_us = 1_000_000 / u64::from(cfg.sample_rate.max(1));
+        let max_lag_us = period_us * cfg.sample_buffer_len.max(1) as u64;
+        let sensor = Sensor::enable(sensor_type, cfg)?;
+        self.add_channel(Channel {
+            source: Source::Bhi160(sensor_type),
+            input: Input::Bhi160 {
+                sensor,
+                period_us,
+                max_lag_us,
+                last_us: None,
+            },
+        })
+    }
+
+    /// Initializes the BME680, if there isn't a `Bme680` handle yet, and records its data.
+    ///
+    /// # Arguments
+    /// * `interval` - Time between two samples
+    pub fn add_bme680(&mut self, interval: Duration) -> Result<()> {
+        self.check_new(Source::Bme680)?;
+        let bme680 = Bme680::init()?;
+        self.add_polled(Source::Bme680, interval, Device::Bme680(bme680))
+    }
+
+    /// Starts the light sensor and records its value.
+    ///
+    /// # Arguments
+    /// * `interval` - Time between two samples
+    pub fn add_light_sensor(&mut self, interval: Duration) -> Result<()> {
+        self.check_new(Source::LightSensor)?;
+        let sensor = LightSensor::start()?;
+        self.add_polled(Source::LightSensor, interval, Device::LightSensor(sensor))
+    }
+
+    /// Records the voltage and current of the battery.
+    ///
+    /// # Arguments
+    /// * `interval` - Time between two samples
+    pub fn add_battery(&mut self, interval: Duration) -> Result<()> {
+        self.check_new(Source::Battery)?;
+        self.add_polled(Source::Battery, interval, Device::None)
+    }
+
+    /// Checks that a channel for the source can be added, before the source is started.
+    fn check_new(&self, source: Source) -> Result<()> {
+        if self.channels.iter().any(|c| c.source == source) {
+            return Err(Error::DeviceOrResourceBusy);
+        }
+        if self.channels.is_full() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn add_polled(&mut self, source: Source, interval: Duration, device: Device) -> Result<()> {
+        self.add_channel(Channel {
+            source,
+            input: Input::Polled {
+                interval,
+                next: None,
+                device,
+            },
+        })
+    }
+
+    fn add_channel(&mut self, channel: Channel) -> Result<()> {
+        // the header of the current file doesn't describe the new channel
+        self.close_file()?;
+        self.channels.push(channel);
+        Ok(())
+    }
+
+    /// Reads all channels and records their new samples.
+    ///
+    /// Call this regularly, at least before the sample buffers of the BHI160 sensors are full.
+    /// A failing channel doesn't stop the others from being read.
+    ///
+    /// # Returns
+    /// The number of recorded samples, or the first error after all channels were read.
+    pub fn poll(&mut self) -> Result<usize> {
+        let now = SystemTime::now().unix_millis();
+        let mut recorded = 0;
+        let mut first_error = None;
+        for index in 0..self.channels.len() {
+            let source = self.channels[index].source;
+            let result = match self.channels[index].input {
+                Input::Polled {
+                    interval,
+                    ref mut next,
+                    ref device,
+                } => {
+                    let current = Instant::now();
+                    if next.map(|next| current < next).unwrap_or(false) {
+                        continue;
+                    }
+                    *next = Some(current + interval);
+                    read_polled(source, device)
+                        .and_then(|values| self.record(index, now, &values))
+                        .map(|_| 1)
+                }
+                Input::Bhi160 { .. } => self.poll_bhi160(index, now),
+            };
+            match result {
+                Ok(n) => recorded += n,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(recorded),
+        }
+    }
+
+    fn poll_bhi160(&mut self, index: usize, now: u64) -> Result<usize> {
+        let now_us = now * 1000;
+        let mut chunk = [DataVector::default(); DRAIN_CHUNK_LEN];
+        let mut recorded = 0;
+        loop {
+            let n = match self.channels[index].input {
+                Input::Bhi160 { ref sensor, .. } => sensor.read_into(&mut chunk)?,
+                Input::Polled { .. } => 0,
+            };
+            if n == 0 {
+                return Ok(recorded);
+            }
+            for (i, raw) in chunk[..n].iter().enumerate() {
+                let (timestamp, values) = match self.channels[index].input {
+                    Input::Bhi160 {
+                        ref sensor,
+                        period_us,
+                        max_lag_us,
+                        ref mut last_us,
+                    } => {
+                        // continue at the sample period, unless the timestamps run ahead of the
+                        // clock or lag behind more than the sensor can buffer
+                        let timestamp = match *last_us {
+                            Some(last)
+                                if last + period_us <= now_us
+                                    && now_us - (last + period_us) <= max_lag_us =>
+                            {
+                                last + period_us
+                            }
+                            _ => now_us.saturating_sub((n - 1 - i) as u64 * period_us),
+                        };
+                        *last_us = Some(timestamp);
+                        (timestamp / 1000, sample_values(&sensor.scale(raw)))
+                    }
+                    Input::Polled { .. } => unreachable!(),
+                };
+                self.record(index, timestamp, &values)?;
+                recorded += 1;
+            }
+        }
+    }
+
+    /// Writes a sample of a channel, starting a new file if necessary.
+    fn record(&mut self, channel: usize, timestamp: u64, values: &[f32]) -> Result<()> {
+        let mut line = ArrayString::<[u8; 256]>::new();
+        let len = match self.format {
+            Format::Csv => {
+                let _ = write!(
+                    line,
+                    "{},{}",
+                    timestamp,
+                    self.channels[channel].source.name()
+                );
+                for value in values {
+                    let _ = write!(line, ",{}", value);
+                }
+                line.push('\n');
+                line.len()
+            }
+            Format::Binary => 1 + 4 + 4 * values.len(),
+        };
+        let too_large = self
+            .max_file_size
+            .map(|max| self.file_len as usize + len > max as usize)
+            .unwrap_or(false);
+        let too_long = self.format == Format::Binary
+            && timestamp.saturating_sub(self.start_ms) > u64::from(u32::MAX);
+        if self.file.is_some() && (too_large || too_long) {
+            self.close_file()?;
+        }
+        if self.file.is_none() {
+            self.open_file(timestamp)?;
+        }
+        match self.format {
+            Format::Csv => self.write(line.as_bytes()),
+            Format::Binary => {
+                let offset = timestamp.saturating_sub(self.start_ms) as u32;
+                self.write(&[channel as u8])?;
+                self.write(&offset.to_le_bytes())?;
+                for value in values {
+                    self.write(&value.to_le_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Creates the next unused file and writes the header.
+    fn open_file(&mut self, start_ms: u64) -> Result<()> {
+        loop {
+            if self.next_index > MAX_INDEX {
+                return Err(Error::FileExists);
+            }
+            self.path.clear();
+            let _ = write!(
+                self.path,
+                "{}_{:03}.{}",
+                self.prefix,
+                self.next_index,
+                self.format.extension()
+            );
+            self.next_index += 1;
+            match fs::metadata(&self.path) {
+                Err(Error::FileNotFound) => break,
+                Err(e) => return Err(e),
+                Ok(_) => {}
+            }
+        }
+        self.file = Some(File::create(&self.path)?);
+        self.file_len = 0;
+        self.start_ms = start_ms;
+        match self.format {
+            Format::Csv => {
+                for index in 0..self.channels.len() {
+                    let source = self.channels[index].source;
+                    let mut line = ArrayString::<[u8; 256]>::new();
+                    let _ = write!(line, "# {}: ", source.name());
+                    for (i, field) in source.fields().iter().enumerate() {
+                        let _ = write!(line, "{}{}", if i == 0 { "" } else { "," }, field);
+                    }
+                    line.push('\n');
+                    self.write(line.as_bytes())?;
+                }
+                self.write(b"timestamp_ms,source,values\n")
+            }
+            Format::Binary => {
+                self.write(MAGIC)?;
+                self.write(&[VERSION, self.channels.len() as u8])?;
+                self.write(&start_ms.to_le_bytes())?;
+                for index in 0..self.channels.len() {
+                    let source = self.channels[index].source;
+                    self.write(&[source.id(), source.fields().len() as u8])?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Appends data to the write buffer, writing out the buffer when it is full.
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.buffered + data.len() > BUFFER_LEN {
+            self.write_buffer()?;
+        }
+        self.buffer[self.buffered..self.buffered + data.len()].copy_from_slice(data);
+        self.buffered += data.len();
+        self.file_len += data.len() as u32;
+        Ok(())
+    }
+
+    fn write_buffer(&mut self) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&self.buffer[..self.buffered])?;
+        }
+        self.buffered = 0;
+        Ok(())
+    }
+
+    fn close_file(&mut self) -> Result<()> {
+        self.write_buffer()?;
+        match self.file.take() {
+            Some(file) => file.close(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the buffered samples to the file system.
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_buffer()?;
+        match self.file.as_ref() {
+            Some(file) => file.sync(),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the buffered samples, closes the file and stops all sources.
+    pub fn finish(mut self) -> Result<()> {
+        self.close_file()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        let _ = self.close_file();
+    }
+}
+
+/// Reader of the records of a binary file.
+///
+/// # Example
+/// ```
+/// use rustcardium::recorder::{Decoder, Source};
+///
+/// let data: &[u8] = &[
+///     b'C', b'1', b'0', b'R', 1, 1, // magic, version and one channel
+///     0xe8, 0x03, 0, 0, 0, 0, 0, 0, // start at 1000 ms
+///     0x22, 2, // battery with two values
+///     0, 10, 0, 0, 0, // channel 0 at 10 ms
+///     0, 0, 0x80, 0x40, 0, 0, 0, 0xbf, // 4.0 V, -0.5 A
+/// ];
+/// let mut decoder = Decoder::new(data)?;
+/// let record = decoder.next_record()?.unwrap();
+/// assert_eq!(record.timestamp.unix_millis(), 1010);
+/// assert_eq!(record.source, Source::Battery);
+/// assert_eq!(record.values(), &[4.0, -0.5]);
+/// assert!(decoder.next_record()?.is_none());
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+pub struct Decoder<R> {
+    reader: R,
+    start_ms: u64,
+    channels: ArrayVec<[(Source, u8); MAX_CHANNELS]>,
+    sources: ArrayVec<[Source; MAX_CHANNELS]>,
+    done: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    /// Reads the header of a binary file.
+    ///
+    /// # Returns
+    /// `Error::FileNotInLoadableFormat` if the data is no binary recording or uses an unknown
+    /// version or source.
+    pub fn new(mut reader: R) -> Result<Decoder<R>> {
+        let mut header = [0; 14];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != VERSION || usize::from(header[5]) > MAX_CHANNELS {
+            return Err(Error::FileNotInLoadableFormat);
+        }
+        let mut start = [0; 8];
+        start.copy_from_slice(&header[6..]);
+        let mut channels = ArrayVec::new();
+        let mut sources = ArrayVec::new();
+        for _ in 0..header[5] {
+            let mut channel = [0; 2];
+            reader.read_exact(&mut channel)?;
+            let source = Source::from_id(channel[0]).ok_or(Error::FileNotInLoadableFormat)?;
+            if usize::from(channel[1]) > MAX_VALUES {
+                return Err(Error::FileNotInLoadableFormat);
+            }
+            channels.push((source, channel[1]));
+            sources.push(source);
+        }
+        Ok(Decoder {
+            reader,
+            start_ms: u64::from_le_bytes(start),
+            channels,
+            sources,
+            done: false,
+        })
+    }
+
+    /// Time when the file was started.
+    pub fn start_time(&self) -> SystemTime {
+        SystemTime::from_unix_millis(self.start_ms)
+    }
+
+    /// The sources of the channels in the file.
+    pub fn channels(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Reads the next record.
+    ///
+    /// # Returns
+    /// None at the end of the file, `Error::UnexpectedEof` if the last record is incomplete
+    /// and `Error::FileNotInLoadableFormat` for records of unknown channels.
+    pub fn next_record(&mut self) -> Result<Option<Record>> {
+        let mut channel = [0; 1];
+        if self.reader.read(&mut channel)? == 0 {
+            return Ok(None);
+        }
+        let (source, len) = *self
+            .channels
+            .get(usize::from(channel[0]))
+            .ok_or(Error::FileNotInLoadableFormat)?;
+        let mut offset = [0; 4];
+        self.reader.read_exact(&mut offset)?;
+        let mut values = [0.0; MAX_VALUES];
+        for value in values.iter_mut().take(usize::from(len)) {
+            let mut bytes = [0; 4];
+            self.reader.read_exact(&mut bytes)?;
+            *value = f32::from_le_bytes(bytes);
+        }
+        let timestamp = self.start_ms + u64::from(u32::from_le_bytes(offset));
+        Ok(Some(Record::new(
+            SystemTime::from_unix_millis(timestamp),
+            source,
+            &values[..usize::from(len)],
+        )))
+    }
+}
+
+impl<R: Read> Iterator for Decoder<R> {
+    type Item = Result<Record>;
+
+    /// Reads the next record, the iteration stops after the first error.
+    fn next(&mut self) -> Option<Result<Record>> {
+        if self.done {
+            return None;
+        }
+        let record = self.next_record().transpose();
+        if let Some(Ok(_)) = record {
+            return record;
+        }
+        self.done = true;
+        record
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::bhi160::STANDARD_GRAVITY;
+    use crate::mock;
+    use std::string::String;
+    use std::vec::Vec;
+
+    const CSV_HEADER: &str = "# battery: voltage_v,current_a\ntimestamp_ms,source,values\n";
+
+    fn accelerometer() -> SensorConfig {
+        SensorConfig {
+            sample_buffer_len: 120,
+            sample_rate: 25,
+            dynamic_range: 4,
+        }
+    }
+
+    fn content(path: &str) -> Vec<u8> {
+        mock::file(path).unwrap()
+    }
+
+    /// Decodes a binary file.
+    ///
+    /// # Returns
+    /// The start time, the channels and the records.
+    fn records(path: &str) -> (u64, Vec<Source>, Vec<Record>) {
+        let data = content(path);
+        let decoder = Decoder::new(&data[..]).unwrap();
+        let start = decoder.start_time().unix_millis();
+        let channels = decoder.channels().to_vec();
+        let records = decoder.collect::<Result<Vec<_>>>().unwrap();
+        (start, channels, records)
+    }
+
+    #[test]
+    fn csv_header_and_rows() {
+        mock::reset();
+        mock::set_rtc_milliseconds(5000);
+        let mut recorder = Recorder::new("/log", Format::Csv).unwrap();
+        recorder.add_battery(Duration::from_secs(1)).unwrap();
+        let first = SystemTime::now().unix_millis();
+        assert_eq!(recorder.poll().unwrap(), 1);
+        // the interval hasn't passed yet
+        assert_eq!(recorder.poll().unwrap(), 0);
+        mock::set_rtc_milliseconds(6000);
+        mock::with(|s| s.battery_voltage = 3.5);
+        assert_eq!(recorder.poll().unwrap(), 1);
+        assert_eq!(recorder.path(), "/log_000.csv");
+        recorder.finish().unwrap();
+
+        let expected = std::format!(
+            "{}{},battery,3.9,-0.05\n{},battery,3.5,-0.05\n",
+            CSV_HEADER,
+            first,
+            first + 1000
+        );
+        assert_eq!(
+            String::from_utf8(content("/log_000.csv")).unwrap(),
+            expected
+        );
+    }
+
+    fn poll_at(recorder: &mut Recorder, seconds: u64) {
+        mock::set_rtc_milliseconds(seconds * 1000);
+        recorder.poll().unwrap();
+    }
+
+    #[test]
+    fn samples_are_buffered() {
+        mock::reset();
+        let mut recorder = Recorder::new("/log", Format::Binary).unwrap();
+        recorder.add_battery(Duration::from_secs(1)).unwrap();
+        poll_at(&mut recorder, 0);
+        assert_eq!(content("/log_000.bin").len(), 0);
+        recorder.flush().unwrap();
+        // header of 16 bytes and a record of 13 bytes
+        assert_eq!(content("/log_000.bin").len(), 29);
+
+        // 39 records of 507 bytes fit into the buffer, the 40th fills it up and is partly written
+        // out
+        for seconds in 1..=39 {
+            poll_at(&mut recorder, seconds);
+        }
+        assert_eq!(content("/log_000.bin").len(), 29);
+        poll_at(&mut recorder, 40);
+        assert_eq!(content("/log_000.bin").len(), 29 + BUFFER_LEN);
+        drop(recorder);
+        assert_eq!(content("/log_000.bin").len(), 29 + 40 * 13);
+    }
+
+    #[test]
+    fn large_files_are_continued_in_a_new_file() {
+        mock::reset();
+        mock::add_file("/log_000.csv", b"an older recording");
+        let mut recorder = Recorder::new("/log", Format::Csv).unwrap();
+        recorder.set_max_file_size(Some(100));
+        recorder.add_battery(Duration::from_secs(1)).unwrap();
+        for seconds in 0..3 {
+            poll_at(&mut recorder, seconds);
+        }
+        assert_eq!(recorder.path(), "/log_003.csv");
+        recorder.finish().unwrap();
+
+        assert_eq!(content("/log_000.csv"), b"an older recording");
+        for path in &["/log_001.csv", "/log_002.csv", "/log_003.csv"] {
+            let text = String::from_utf8(content(path)).unwrap();
+            assert!(text.len() <= 100, "{}", text);
+            assert!(text.starts_with(CSV_HEADER), "{}", text);
+            assert_eq!(text.lines().count(), 3, "{}", text);
+        }
+    }
+
+    #[test]
+    fn binary_files_are_continued_before_the_offset_overflows() {
+        mock::reset();
+        mock::set_rtc_milliseconds(1000);
+        let mut recorder = Recorder::new("/log", Format::Binary).unwrap();
+        recorder.add_battery(Duration::from_secs(1)).unwrap();
+        let start = SystemTime::now().unix_millis();
+        recorder.poll().unwrap();
+        mock::set_rtc_milliseconds(1000 + u64::from(u32::MAX));
+        recorder.poll().unwrap();
+        assert_eq!(recorder.path(), "/log_000.bin");
+        mock::set_rtc_milliseconds(1000 + u64::from(u32::MAX) + 1000);
+        recorder.poll().unwrap();
+        assert_eq!(recorder.path(), "/log_001.bin");
+        recorder.finish().unwrap();
+
+        let (first_start, _, first) = records("/log_000.bin");
+        assert_eq!(first_start, start);
+        assert_eq!(first.len(), 2);
+        assert_eq!(
+            first[1].timestamp.unix_millis(),
+            start + u64::from(u32::MAX)
+        );
+        let (second_start, _, second) = records("/log_001.bin");
+        let restart = start + u64::from(u32::MAX) + 1000;
+        assert_eq!(second_start, restart);
+        assert_eq!(second.len(), 1);
+        assert_eq!(second[0].timestamp.unix_millis(), restart);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        mock::reset();
+        mock::set_rtc_milliseconds(10_000);
+        let mut recorder = Recorder::new("/motion", Format::Binary).unwrap();
+        recorder
+            .add_bhi160(SensorType::Accelerometer, accelerometer())
+            .unwrap();
+        recorder.add_battery(Duration::from_secs(10)).unwrap();
+        let now = SystemTime::now().unix_millis();
+        mock::push_bhi160_samples(0, &[(0, 0, 8192, 3), (8192, 0, 0, 3), (0, -8192, 0, 3)]);
+        assert_eq!(recorder.poll().unwrap(), 4);
+        recorder.finish().unwrap();
+
+        let (start, channels, records) = records("/motion_000.bin");
+        let accelerometer = Source::Bhi160(SensorType::Accelerometer);
+        assert_eq!(channels, [accelerometer, Source::Battery]);
+        // the samples are timestamped backwards from the poll at 25 Hz
+        assert_eq!(start, now - 80);
+        let g = STANDARD_GRAVITY;
+        let expected: [(u64, Source, &[f32]); 4] = [
+            (now - 80, accelerometer, &[0.0, 0.0, g]),
+            (now - 40, accelerometer, &[g, 0.0, 0.0]),
+            (now, accelerometer, &[0.0, -g, 0.0]),
+            (now, Source::Battery, &[3.9, -0.05]),
+        ];
+        assert_eq!(records.len(), expected.len());
+        for (record, &(timestamp, source, values)) in records.iter().zip(expected.iter()) {
+            assert_eq!(record.timestamp.unix_millis(), timestamp);
+            assert_eq!(record.source, source);
+            assert_eq!(record.values().len(), values.len());
+            for (value, expected) in record.values().iter().zip(values) {
+                assert!((value - expected).abs() < 0.01, "{:?}", record);
+            }
+        }
+    }
+
+    #[test]
+    fn failing_channel_doesnt_stop_the_others() {
+        mock::reset();
+        let mut recorder = Recorder::new("/log", Format::Binary).unwrap();
+        recorder.add_battery(Duration::from_secs(1)).unwrap();
+        recorder
+            .add_bhi160(SensorType::Accelerometer, accelerometer())
+            .unwrap();
+        mock::push_bhi160_samples(0, &[(0, 0, 8192, 3), (0, 0, 8192, 3)]);
+        mock::push_return("epic_read_battery_voltage", -(mock::errno::EIO as i32));
+        assert_eq!(recorder.poll(), Err(Error::IoError));
+        assert!(mock::with(|s| s.bhi160_samples[0].is_empty()));
+        recorder.finish().unwrap();
+
+        let (_, _, records) = records("/log_000.bin");
+        assert_eq!(records.len(), 2);
+        assert!(records
+            .iter()
+            .all(|r| r.source == Source::Bhi160(SensorType::Accelerometer)));
+    }
+}
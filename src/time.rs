@@ -0,0 +1,698 @@
+//! The `time` module gives access to the real time clock (RTC) of card10.
+//!
+//! * `SystemTime` is the wall clock time in UTC, it can be converted into a calendar `DateTime`
+//!   in UTC or in local time (see `set_utc_offset()`).
+//! * `Instant` is a monotonic point in time to measure intervals with `Duration`. It is not
+//!   affected by changing the wall clock with `SystemTime::set()`.
+//! * `Alarm` calls a handler at a given time using the RTC alarm interrupt.
+//! * `sleep()` and `sleep_until()` wait asynchronously, see `executor`.
+//!
+//! # Example
+//! ```
+//! use rustcardium::time::{self, Instant, SystemTime};
+//!
+//! // Central European Summer Time
+//! time::set_utc_offset(2 * 3600)?;
+//!
+//! let start = Instant::now();
+//! let now = SystemTime::now().to_local();
+//! let text = now.time_str();
+//! if let Ok(display) = rustcardium::display::Display::open() {
+//!     let white = rustcardium::Color { r: 255, g: 255, b: 255 };
+//!     let black = rustcardium::Color { r: 0, g: 0, b: 0 };
+//!     display.print(&text, white, black, 0, 0)?;
+//!     display.update()?;
+//! }
+//! let drawing_took = start.elapsed();
+//! # Ok::<(), rustcardium::Error>(())
+//! ```
+use super::*;
+use crate::executor;
+use crate::interrupts::{self, Interrupt, Registration};
+
+use core::fmt;
+use core::future::Future;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use core::task::{Context, Poll};
+
+pub use core::time::Duration;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const MILLIS_PER_DAY: u64 = SECONDS_PER_DAY * 1000;
+
+/// Offset of the local time to UTC in seconds.
+static UTC_OFFSET: AtomicI32 = AtomicI32::new(0);
+
+/// Sum of all changes of the wall clock with `SystemTime::set()` in milliseconds,
+/// stored as two halves since there are no 64 bit atomics on card10.
+///
+/// The halves are only accessed within `interrupts::free()`, together with the RTC, so an
+/// interrupt handler never sees a torn value or a clock which is set but not yet adjusted.
+static CLOCK_ADJUSTMENT_HIGH: AtomicU32 = AtomicU32::new(0);
+static CLOCK_ADJUSTMENT_LOW: AtomicU32 = AtomicU32::new(0);
+
+fn clock_adjustment() -> u64 {
+    let high = u64::from(CLOCK_ADJUSTMENT_HIGH.load(Ordering::SeqCst));
+    let low = u64::from(CLOCK_ADJUSTMENT_LOW.load(Ordering::SeqCst));
+    (high << 32) | low
+}
+
+fn set_clock_adjustment(value: u64) {
+    CLOCK_ADJUSTMENT_HIGH.store((value >> 32) as u32, Ordering::SeqCst);
+    CLOCK_ADJUSTMENT_LOW.store(value as u32, Ordering::SeqCst);
+}
+
+/// Resets the UTC offset and the clock adjustment, see `mock::reset()`.
+#[cfg(feature = "mock")]
+pub(crate) fn reset() {
+    UTC_OFFSET.store(0, Ordering::SeqCst);
+    interrupts::free(|| set_clock_adjustment(0));
+}
+
+/// Sets the offset of the local time to UTC.
+///
+/// # Arguments
+/// * `seconds` - Offset in seconds, e.g. 3600 for UTC+1. Must be between -24 and +24 hours.
+pub fn set_utc_offset(seconds: i32) -> Result<()> {
+    if i64::from(seconds).abs() > SECONDS_PER_DAY as i64 {
+        return Err(Error::InvalidArgument);
+    }
+    UTC_OFFSET.store(seconds, Ordering::SeqCst);
+    Ok(())
+}
+
+/// The offset of the local time to UTC in seconds (0 by default).
+pub fn utc_offset() -> i32 {
+    UTC_OFFSET.load(Ordering::SeqCst)
+}
+
+/// A monotonic point in time with millisecond resolution, used to measure intervals.
+///
+/// # Example
+/// ```
+/// use rustcardium::time::{Duration, Instant};
+///
+/// let timeout = Instant::now() + Duration::from_secs(5);
+/// while Instant::now() < timeout {
+/// #   rustcardium::mock::with(|s| s.rtc_milliseconds += 1000);
+///     // poll something
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The current point in time.
+    pub fn now() -> Instant {
+        interrupts::free(|| {
+            let rtc = unsafe { sys::epic_rtc_get_milliseconds() };
+            Instant(rtc.wrapping_sub(clock_adjustment()))
+        })
+    }
+
+    /// Time passed since `earlier`, or zero if `earlier` is later than this instant.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    /// Time passed since `earlier`, or None if `earlier` is later than this instant.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_millis)
+    }
+
+    /// Time passed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        millis(duration).and_then(|ms| self.0.checked_add(ms).map(Instant))
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        millis(duration).and_then(|ms| self.0.checked_sub(ms).map(Instant))
+    }
+}
+
+/// Converts a duration to milliseconds, if it fits into an u64.
+fn millis(duration: Duration) -> Option<u64> {
+    let ms = duration.as_millis();
+    if ms > u128::from(u64::MAX) {
+        None
+    } else {
+        Some(ms as u64)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, duration: Duration) {
+        *self = *self + duration;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, duration: Duration) {
+        *self = *self - duration;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Wall clock time in UTC with millisecond resolution, measured since the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(u64);
+
+impl SystemTime {
+    /// 1970-01-01 00:00:00 UTC
+    pub const UNIX_EPOCH: SystemTime = SystemTime(0);
+
+    /// The current time of the RTC.
+    pub fn now() -> SystemTime {
+        SystemTime(unsafe { sys::epic_rtc_get_milliseconds() })
+    }
+
+    /// Sets the RTC to this time.
+    ///
+    /// Instants are not affected, intervals measured with `Instant` stay correct.
+    pub fn set(self) {
+        interrupts::free(|| {
+            let previous = unsafe { sys::epic_rtc_get_milliseconds() };
+            unsafe { sys::epic_rtc_set_milliseconds(self.0) };
+            set_clock_adjustment(clock_adjustment().wrapping_add(self.0.wrapping_sub(previous)));
+        })
+    }
+
+    /// Creates a time from the number of milliseconds since the Unix epoch.
+    pub fn from_unix_millis(milliseconds: u64) -> SystemTime {
+        SystemTime(milliseconds)
+    }
+
+    /// Creates a time from the number of seconds since the Unix epoch.
+    pub fn from_unix_seconds(seconds: u32) -> SystemTime {
+        SystemTime(u64::from(seconds) * 1000)
+    }
+
+    /// Milliseconds since the Unix epoch.
+    pub fn unix_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn unix_seconds(&self) -> u64 {
+        self.0 / 1000
+    }
+
+    /// Time passed since `earlier`, or None if `earlier` is later than this time.
+    pub fn duration_since(&self, earlier: SystemTime) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_millis)
+    }
+
+    /// Time passed since this time, or None if this time is in the future.
+    pub fn elapsed(&self) -> Option<Duration> {
+        SystemTime::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<SystemTime> {
+        millis(duration).and_then(|ms| self.0.checked_add(ms).map(SystemTime))
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<SystemTime> {
+        millis(duration).and_then(|ms| self.0.checked_sub(ms).map(SystemTime))
+    }
+
+    /// The calendar date and time in UTC.
+    pub fn to_utc(&self) -> DateTime {
+        DateTime::from_unix_millis(self.0)
+    }
+
+    /// The calendar date and time in local time, as configured with `set_utc_offset()`.
+    pub fn to_local(&self) -> DateTime {
+        let offset = i64::from(utc_offset()) * 1000;
+        DateTime::from_unix_millis((self.0 as i64 + offset).max(0) as u64)
+    }
+
+    /// Converts a calendar date and time in UTC.
+    pub fn from_utc(datetime: &DateTime) -> SystemTime {
+        SystemTime(datetime.unix_millis())
+    }
+
+    /// Converts a calendar date and time in local time, as configured with `set_utc_offset()`.
+    pub fn from_local(datetime: &DateTime) -> SystemTime {
+        let offset = i64::from(utc_offset()) * 1000;
+        SystemTime((datetime.unix_millis() as i64 - offset).max(0) as u64)
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, duration: Duration) -> SystemTime {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to system time")
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, duration: Duration) -> SystemTime {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from system time")
+    }
+}
+
+/// Day of the week.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Weekday {
+    Monday = 0,
+    Tuesday = 1,
+    Wednesday = 2,
+    Thursday = 3,
+    Friday = 4,
+    Saturday = 5,
+    Sunday = 6,
+}
+
+impl Weekday {
+    /// Days since Monday, 0 <= n <= 6.
+    pub fn from_monday(n: u8) -> Weekday {
+        match n % 7 {
+            0 => Weekday::Monday,
+            1 => Weekday::Tuesday,
+            2 => Weekday::Wednesday,
+            3 => Weekday::Thursday,
+            4 => Weekday::Friday,
+            5 => Weekday::Saturday,
+            _ => Weekday::Sunday,
+        }
+    }
+
+    /// English name of the day, e.g. "Monday".
+    pub fn name(self) -> &'static str {
+        match self {
+            Weekday::Monday => "Monday",
+            Weekday::Tuesday => "Tuesday",
+            Weekday::Wednesday => "Wednesday",
+            Weekday::Thursday => "Thursday",
+            Weekday::Friday => "Friday",
+            Weekday::Saturday => "Saturday",
+            Weekday::Sunday => "Sunday",
+        }
+    }
+
+    /// Abbreviated English name of the day, e.g. "Mon".
+    pub fn short_name(self) -> &'static str {
+        &self.name()[..3]
+    }
+}
+
+impl fmt::Display for Weekday {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// True for leap years of the Gregorian calendar.
+pub fn is_leap_year(year: u16) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+/// Number of days of a month.
+///
+/// # Arguments
+/// * `year` - Year, needed for February
+/// * `month` - Month, 1 <= month <= 12
+pub fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Abbreviated English names of the months.
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, negative for earlier dates.
+fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    // Shift the year to start in March, so the leap day is the last day of the year
+    let year = i64::from(year) - if month <= 2 { 1 } else { 0 };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (i64::from(month) + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719_468 days from 0000-03-01 to 1970-01-01
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Date (year, month, day) of a day since 1970-01-01.
+fn civil_from_days(days: u64) -> (u16, u8, u8) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as u16, month as u8, day as u8)
+}
+
+/// A date and time of the Gregorian calendar, without a time zone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    /// Year, e.g. 2019
+    pub year: u16,
+    /// Month, 1 <= month <= 12
+    pub month: u8,
+    /// Day of the month, 1 <= day <= 31
+    pub day: u8,
+    /// Hour, 0 <= hour <= 23
+    pub hour: u8,
+    /// Minute, 0 <= minute <= 59
+    pub minute: u8,
+    /// Second, 0 <= second <= 59
+    pub second: u8,
+    /// Millisecond, 0 <= millisecond <= 999
+    pub millisecond: u16,
+}
+
+impl DateTime {
+    /// Creates a date and time after checking that it exists.
+    ///
+    /// # Arguments
+    /// * `year` - Year, must be at least 1970
+    /// * `month` - Month, 1 <= month <= 12
+    /// * `day` - Day of the month, 1 <= day <= number of days of the month
+    /// * `hour` - Hour, 0 <= hour <= 23
+    /// * `minute` - Minute, 0 <= minute <= 59
+    /// * `second` - Second, 0 <= second <= 59
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Result<DateTime> {
+        if year < 1970
+            || !(1..=12).contains(&month)
+            || !(1..=days_in_month(year, month)).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            millisecond: 0,
+        })
+    }
+
+    /// Converts milliseconds since the Unix epoch.
+    pub fn from_unix_millis(milliseconds: u64) -> DateTime {
+        let days = milliseconds / MILLIS_PER_DAY;
+        let rest = milliseconds % MILLIS_PER_DAY;
+        let (year, month, day) = civil_from_days(days);
+        let seconds = rest / 1000;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+            millisecond: (rest % 1000) as u16,
+        }
+    }
+
+    /// Milliseconds since the Unix epoch, dates before 1970 are mapped to the epoch.
+    pub fn unix_millis(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day).max(0) as u64;
+        let seconds = days * SECONDS_PER_DAY
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second);
+        seconds * 1000 + u64::from(self.millisecond)
+    }
+
+    /// The day of the week.
+    pub fn weekday(&self) -> Weekday {
+        let days = days_from_civil(self.year, self.month, self.day);
+        // 1970-01-01 was a Thursday
+        Weekday::from_monday((days + 3).rem_euclid(7) as u8)
+    }
+
+    /// Day of the year, 1 <= n <= 366.
+    pub fn day_of_year(&self) -> u16 {
+        (days_from_civil(self.year, self.month, self.day) - days_from_civil(self.year, 1, 1) + 1)
+            as u16
+    }
+
+    /// Abbreviated English name of the month, e.g. "Aug".
+    pub fn month_name(&self) -> &'static str {
+        MONTH_NAMES[usize::from(self.month.clamp(1, 12) - 1)]
+    }
+
+    /// The time as "hh:mm:ss".
+    pub fn time_str(&self) -> ArrayString<[u8; 8]> {
+        let mut text = ArrayString::new();
+        // can't fail, the text always has 8 characters
+        let _ = fmt::write(
+            &mut text,
+            format_args!("{:02}:{:02}:{:02}", self.hour, self.minute, self.second),
+        );
+        text
+    }
+
+    /// The time as "hh:mm".
+    pub fn short_time_str(&self) -> ArrayString<[u8; 5]> {
+        let mut text = ArrayString::new();
+        let _ = fmt::write(
+            &mut text,
+            format_args!("{:02}:{:02}", self.hour, self.minute),
+        );
+        text
+    }
+
+    /// The date as "YYYY-MM-DD".
+    pub fn date_str(&self) -> ArrayString<[u8; 10]> {
+        let mut text = ArrayString::new();
+        let _ = fmt::write(
+            &mut text,
+            format_args!("{:04}-{:02}-{:02}", self.year, self.month, self.day),
+        );
+        text
+    }
+}
+
+/// Formats the date and time as ISO 8601, e.g. "2019-08-21 13:37:00".
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// The RTC alarm, which calls a handler at a given time without the need to poll the clock.
+///
+/// card10 has a single RTC alarm, so only one `Alarm` can exist at a time.
+/// The alarm is cancelled when the variable gets out of scope.
+///
+/// The handler is called from an interrupt service routine, it should return quickly and
+/// must not block, e.g. set a flag which is checked by the main loop.
+///
+/// # Example
+/// ```
+/// use core::sync::atomic::{AtomicBool, Ordering};
+/// use rustcardium::time::{Alarm, Duration, SystemTime};
+///
+/// static WAKE_UP: AtomicBool = AtomicBool::new(false);
+///
+/// fn on_alarm() {
+///     WAKE_UP.store(true, Ordering::SeqCst);
+/// }
+///
+/// let mut alarm = Alarm::schedule(SystemTime::now() + Duration::from_secs(60), on_alarm)?;
+/// // snooze
+/// alarm.reschedule(SystemTime::now() + Duration::from_secs(300))?;
+/// # rustcardium::mock::trigger_interrupt(3);
+/// # assert!(WAKE_UP.load(Ordering::SeqCst));
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+pub struct Alarm {
+    at: SystemTime,
+    /// Interrupt count when the alarm was scheduled
+    count: u32,
+    registration: Registration,
+}
+
+impl Alarm {
+    /// Schedules the alarm to call a function.
+    ///
+    /// # Arguments
+    /// * `at` - Time of the alarm, the RTC alarm has a resolution of one second
+    /// * `handler` - Function which is called when the alarm triggers
+    ///
+    /// # Returns
+    /// `Error::DeviceOrResourceBusy` if another `Alarm` or handler for `Interrupt::RtcAlarm` exists.
+    pub fn schedule(at: SystemTime, handler: fn()) -> Result<Alarm> {
+        Alarm::new(at, interrupts::register(Interrupt::RtcAlarm, handler)?)
+    }
+
+    /// Schedules the alarm to call a closure.
+    ///
+    /// # Arguments
+    /// * `at` - Time of the alarm, the RTC alarm has a resolution of one second
+    /// * `handler` - Closure which is called when the alarm triggers
+    ///
+    /// # Returns
+    /// `Error::DeviceOrResourceBusy` if another `Alarm` or handler for `Interrupt::RtcAlarm` exists.
+    pub fn schedule_with(
+        at: SystemTime,
+        handler: &'static mut (dyn FnMut() + Send),
+    ) -> Result<Alarm> {
+        Alarm::new(at, interrupts::register_with(Interrupt::RtcAlarm, handler)?)
+    }
+
+    fn new(at: SystemTime, registration: Registration) -> Result<Alarm> {
+        let mut alarm = Alarm {
+            at,
+            count: 0,
+            registration,
+        };
+        alarm.reschedule(at)?;
+        Ok(alarm)
+    }
+
+    /// Moves the alarm to another time, also if it already triggered.
+    ///
+    /// # Arguments
+    /// * `at` - New time of the alarm
+    pub fn reschedule(&mut self, at: SystemTime) -> Result<()> {
+        // round up, so the alarm never triggers too early
+        let seconds = at.unix_millis().div_ceil(1000);
+        if seconds > u64::from(u32::MAX) {
+            return Err(Error::InvalidArgument);
+        }
+        self.registration.disable()?;
+        self.count = interrupts::count(Interrupt::RtcAlarm);
+        unsafe { check(sys::epic_rtc_schedule_alarm(seconds as u32))? };
+        self.registration.enable()?;
+        self.at = at;
+        Ok(())
+    }
+
+    /// Cancels the alarm, the handler will not be called anymore.
+    pub fn cancel(self) {}
+
+    /// The time the alarm is scheduled for.
+    pub fn at(&self) -> SystemTime {
+        self.at
+    }
+
+    /// True if the alarm triggered since it was scheduled.
+    pub fn has_fired(&self) -> bool {
+        interrupts::count(Interrupt::RtcAlarm) != self.count
+    }
+}
+
+/// Future which completes at a deadline, created with `sleep()` or `sleep_until()`.
+pub struct Sleep {
+    deadline: Instant,
+}
+
+impl Sleep {
+    /// The instant at which the future completes.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            executor::register_timer(self.deadline);
+            Poll::Pending
+        }
+    }
+}
+
+/// Waits asynchronously until the duration has passed.
+///
+/// While all tasks are waiting, the executor sleeps until the RTC alarm wakes it up. The alarm
+/// has a resolution of one second, so the core busy-waits during the last second before the
+/// deadline, and during the whole time while an `Alarm` exists.
+///
+/// # Example
+/// ```
+/// use rustcardium::executor::block_on;
+/// use rustcardium::time::{self, Duration};
+///
+/// # rustcardium::mock::with(|s| s.rtc_auto_advance = 1);
+/// block_on(async {
+///     for _ in 0..3 {
+///         rustcardium::leds::set_flashlight(true);
+///         time::sleep(Duration::from_millis(100)).await;
+///         rustcardium::leds::set_flashlight(false);
+///         time::sleep(Duration::from_millis(100)).await;
+///     }
+/// });
+/// ```
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Waits asynchronously until the deadline.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline }
+}
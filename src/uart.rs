@@ -1,4 +1,6 @@
+use crate::interrupts::{self, Interrupt};
 use crate::sys::*;
+use crate::{check, Error, Result};
 use core::fmt::Write;
 
 pub struct Uart;
@@ -6,8 +8,79 @@ pub struct Uart;
 impl Write for Uart {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
         unsafe {
-            epic_uart_write_str(s.as_ptr(), s.len() as isize);
+            epic_uart_write_str(s.as_ptr() as *const cty::c_char, s.len() as isize);
         }
         Ok(())
     }
 }
+
+/// Reads a single byte from the serial console without blocking.
+///
+/// # Returns
+/// None if no byte was available.
+pub fn read_char() -> Option<u8> {
+    let c = unsafe { epic_uart_read_char() };
+    if c < 0 {
+        None
+    } else {
+        Some(c as u8)
+    }
+}
+
+/// Reads as many bytes as available from the serial console without blocking.
+///
+/// # Returns
+/// The number of bytes read, 0 if no data was available.
+pub fn read(buf: &mut [u8]) -> Result<usize> {
+    let n = unsafe {
+        check(epic_uart_read_str(
+            buf.as_mut_ptr() as *mut cty::c_char,
+            buf.len() as size_t,
+        ))?
+    };
+    Ok(n as usize)
+}
+
+/// Reads a line from the serial console asynchronously, see `executor`.
+///
+/// The line is terminated by `\n` or `\r`, the terminator is not stored in the buffer.
+/// Empty lines are skipped, so `\r\n` line endings don't result in additional empty lines.
+///
+/// # Arguments
+/// * `buf` - Buffer for the line
+///
+/// # Returns
+/// The length of the line or `Error::NoSpaceLeft` if the line does not fit into the buffer.
+///
+/// # Example
+/// ```
+/// use rustcardium::executor::block_on;
+/// use rustcardium::uart;
+///
+/// # rustcardium::mock::push_uart_input("blink\r\n");
+/// let mut line = [0; 32];
+/// let len = block_on(uart::read_line(&mut line))?;
+/// if &line[..len] == b"blink" {
+///     // ...
+/// }
+/// # Ok::<(), rustcardium::Error>(())
+/// ```
+pub async fn read_line(buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+    loop {
+        // wait for interrupts that occur while reading the available data
+        let received = interrupts::wait(Interrupt::UartRx);
+        while let Some(c) = read_char() {
+            match c {
+                b'\n' | b'\r' if len == 0 => {}
+                b'\n' | b'\r' => return Ok(len),
+                c if len < buf.len() => {
+                    buf[len] = c;
+                    len += 1;
+                }
+                _ => return Err(Error::NoSpaceLeft),
+            }
+        }
+        received.await?;
+    }
+}